@@ -0,0 +1,223 @@
+//! Lookups that don't block an async runtime.
+//!
+//! The libc functions behind every lookup block while they read files or
+//! ask a directory service, which would stall every other task on the same
+//! runtime thread. `AsyncUsers` runs them on tokio's blocking thread pool
+//! instead.
+
+use std::future::Future;
+use std::panic;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use libc::{uid_t, gid_t};
+use tokio::task::{self, JoinHandle};
+
+use super::{UsersRead, User, Group, SharedUsers};
+
+/// A producer of user and group instances whose lookups return futures.
+///
+/// Each lookup that isn't already cached is run with tokio's
+/// `spawn_blocking` when its future is first polled, so the futures have to
+/// be awaited inside a tokio runtime, and will panic otherwise. Results go
+/// into the same cache as a `SharedUsers` object, so looking the same user
+/// up twice only makes one libc call, and cached results are returned
+/// without leaving the current task.
+///
+/// ```
+/// extern crate tokio;
+/// extern crate users;
+///
+/// # fn main() {
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// let users = users::AsyncUsers::empty_cache();
+/// let root = runtime.block_on(users.get_user_by_uid(0));
+/// println!("{:?}", root.map(|u| u.name));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncUsers {
+    cache: SharedUsers,
+}
+
+impl AsyncUsers {
+    /// Create a new empty async users object.
+    pub fn empty_cache() -> AsyncUsers {
+        AsyncUsers { cache: SharedUsers::empty_cache() }
+    }
+
+    /// Create an async users object that shares its cache with the given
+    /// `SharedUsers` object.
+    pub fn with_shared(cache: SharedUsers) -> AsyncUsers {
+        AsyncUsers { cache }
+    }
+
+    /// Return a User object if one exists for the given user ID; otherwise,
+    /// return None.
+    pub fn get_user_by_uid(&self, uid: uid_t) -> Lookup<Option<User>> {
+        if let Some(user) = self.cache.cached_user_by_uid(uid) {
+            return Lookup::ready(user);
+        }
+
+        let cache = self.cache.clone();
+        Lookup::spawn(move || cache.get_user_by_uid(uid))
+    }
+
+    /// Return a User object if one exists for the given username; otherwise,
+    /// return None.
+    pub fn get_user_by_name(&self, username: &str) -> Lookup<Option<User>> {
+        if let Some(user) = self.cache.cached_user_by_name(username) {
+            return Lookup::ready(user);
+        }
+
+        let cache = self.cache.clone();
+        let username = username.to_owned();
+        Lookup::spawn(move || cache.get_user_by_name(&username))
+    }
+
+    /// Return a Group object if one exists for the given group ID; otherwise,
+    /// return None.
+    pub fn get_group_by_gid(&self, gid: gid_t) -> Lookup<Option<Group>> {
+        if let Some(group) = self.cache.cached_group_by_gid(gid) {
+            return Lookup::ready(group);
+        }
+
+        let cache = self.cache.clone();
+        Lookup::spawn(move || cache.get_group_by_gid(gid))
+    }
+
+    /// Return a Group object if one exists for the given groupname;
+    /// otherwise, return None.
+    pub fn get_group_by_name(&self, group_name: &str) -> Lookup<Option<Group>> {
+        if let Some(group) = self.cache.cached_group_by_name(group_name) {
+            return Lookup::ready(group);
+        }
+
+        let cache = self.cache.clone();
+        let group_name = group_name.to_owned();
+        Lookup::spawn(move || cache.get_group_by_name(&group_name))
+    }
+
+    /// Return the groups that the given user is a member of, or None if
+    /// there's no such user.
+    pub fn get_user_groups(&self, username: &str, gid: gid_t) -> Lookup<Option<Vec<Group>>> {
+        let cache = self.cache.clone();
+        let username = username.to_owned();
+        Lookup::spawn(move || cache.get_user_groups(&username, gid))
+    }
+
+    /// Return the user ID for the user running the process.
+    pub fn get_current_uid(&self) -> uid_t {
+        self.cache.get_current_uid()
+    }
+
+    /// Return the username of the user running the process.
+    pub fn get_current_username(&self) -> Lookup<Option<String>> {
+        let uid = self.get_current_uid();
+        if let Some(user) = self.cache.cached_user_by_uid(uid) {
+            return Lookup::ready(user.map(|u| u.name));
+        }
+
+        let cache = self.cache.clone();
+        Lookup::spawn(move || cache.get_user_by_uid(uid).map(|u| u.name))
+    }
+
+    /// Return the group ID for the user running the process.
+    pub fn get_current_gid(&self) -> gid_t {
+        self.cache.get_current_gid()
+    }
+
+    /// Return the effective user id.
+    pub fn get_effective_uid(&self) -> uid_t {
+        self.cache.get_effective_uid()
+    }
+
+    /// Return the effective group id.
+    pub fn get_effective_gid(&self) -> gid_t {
+        self.cache.get_effective_gid()
+    }
+}
+
+/// The future returned by an `AsyncUsers` lookup.
+///
+/// If the lookup panics on the blocking thread, the panic is resumed in the
+/// task awaiting it.
+pub struct Lookup<T> {
+    state: LookupState<T>,
+}
+
+enum LookupState<T> {
+    Ready(Option<T>),
+    Unstarted(Option<Box<dyn FnOnce() -> T + Send>>),
+    Blocking(JoinHandle<T>),
+}
+
+impl<T: Send + 'static> Lookup<T> {
+    fn ready(value: T) -> Lookup<T> {
+        Lookup { state: LookupState::Ready(Some(value)) }
+    }
+
+    fn spawn<F>(f: F) -> Lookup<T>
+    where F: FnOnce() -> T + Send + 'static {
+        Lookup { state: LookupState::Unstarted(Some(Box::new(f))) }
+    }
+}
+
+impl<T: Send + Unpin + 'static> Future for Lookup<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let this = self.get_mut();
+        if let LookupState::Unstarted(ref mut f) = this.state {
+            let f = f.take().expect("Lookup polled after completion");
+            this.state = LookupState::Blocking(task::spawn_blocking(f));
+        }
+
+        match this.state {
+            LookupState::Unstarted(_) => unreachable!(),
+            LookupState::Ready(ref mut value) => {
+                Poll::Ready(value.take().expect("Lookup polled after completion"))
+            },
+            LookupState::Blocking(ref mut handle) => match Pin::new(handle).poll(cx) {
+                Poll::Ready(Ok(value))  => Poll::Ready(value),
+                Poll::Ready(Err(e))     => {
+                    if e.is_panic() { panic::resume_unwind(e.into_panic()) }
+                    else { panic!("Lookup was cancelled: {}", e) }
+                },
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AsyncUsers;
+    use tokio::runtime::{Builder, Runtime};
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().build().unwrap()
+    }
+
+    #[test]
+    fn user_by_uid() {
+        let users = AsyncUsers::empty_cache();
+        let root = runtime().block_on(users.get_user_by_uid(0)).unwrap();
+        assert_eq!(root.uid, 0);
+    }
+
+    #[test]
+    fn cached_lookups_agree() {
+        let runtime = runtime();
+        let users = AsyncUsers::empty_cache();
+        let root = runtime.block_on(users.get_user_by_uid(0)).unwrap();
+        assert!(users.cache.cached_user_by_uid(0) == Some(Some(root.clone())));
+        assert!(runtime.block_on(users.get_user_by_name(&root.name)) == Some(root));
+    }
+
+    #[test]
+    fn missing_group() {
+        let users = AsyncUsers::empty_cache();
+        assert!(runtime().block_on(users.get_group_by_name("group\0")).is_none());
+    }
+}
@@ -0,0 +1,322 @@
+//! Builders for users and groups that don't come from the system.
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use libc::{uid_t, gid_t};
+
+use super::{User, Group, gecos_full_name};
+
+impl User {
+    /// Start building a user with the given ID and name. Every other field
+    /// gets a default: their primary group has the same ID as the user,
+    /// their home directory is `/`, their shell is `/bin/sh`, and their
+    /// GECOS field is empty.
+    ///
+    /// ```
+    /// use users::User;
+    /// let user = User::builder(1000, "fred").shell("/bin/bash").build();
+    /// assert_eq!(user.primary_group, 1000);
+    /// ```
+    pub fn builder(uid: uid_t, name: &str) -> UserBuilder {
+        UserBuilder {
+            user: User {
+                uid,
+                name:          name.to_owned(),
+                name_os:       OsString::from(name),
+                primary_group: uid,
+                home_dir:      PathBuf::from("/"),
+                shell:         PathBuf::from("/bin/sh"),
+                full_name:     String::new(),
+                gecos:         String::new(),
+                passwd:        "x".to_owned(),
+                raw_line:      None,
+            },
+        }
+    }
+
+    /// Create a user with the ID and name given, following the default
+    /// policies for everything else: their home directory is
+    /// `/home/{name}`, their shell is `/bin/bash`, and their primary group has
+    /// the same ID as the user.
+    ///
+    /// ```
+    /// use users::User;
+    /// use std::path::Path;
+    /// let user = User::with_defaults(1000, "fred");
+    /// assert_eq!(user.home_dir, Path::new("/home/fred"));
+    /// ```
+    pub fn with_defaults(uid: uid_t, name: &str) -> User {
+        User::with_policies(uid, name, &HomePolicy::default(), &ShellPolicy::default())
+    }
+
+    /// Create a user in the same way as `with_defaults`, but with their home
+    /// directory inside the given one instead of `/home`.
+    pub fn with_defaults_in<P: AsRef<Path>>(uid: uid_t, name: &str, base: P) -> User {
+        User::with_policies(uid, name, &HomePolicy::new(base.as_ref()), &ShellPolicy::default())
+    }
+
+    /// Create a user in the same way as `with_defaults`, but with their home
+    /// directory and shell decided by the given policies.
+    ///
+    /// ```
+    /// use users::{User, HomePolicy, ShellPolicy};
+    /// use std::path::Path;
+    /// let user = User::with_policies(501, "fred", &HomePolicy::macos(), &ShellPolicy::new("/bin/zsh"));
+    /// assert_eq!(user.home_dir, Path::new("/Users/fred"));
+    /// ```
+    pub fn with_policies(uid: uid_t, name: &str, home: &HomePolicy, shell: &ShellPolicy) -> User {
+        User::builder(uid, name)
+            .home_dir(home.home_for(name))
+            .shell(shell.shell())
+            .build()
+    }
+}
+
+/// Where new users' home directories go, which differs between systems:
+/// Linux puts them in `/home`, macOS in `/Users`, and FreeBSD in
+/// `/usr/home`.
+///
+/// The default is `/home`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HomePolicy {
+    base: PathBuf,
+}
+
+impl HomePolicy {
+    /// Put home directories inside the given directory.
+    pub fn new<P: Into<PathBuf>>(base: P) -> HomePolicy {
+        HomePolicy { base: base.into() }
+    }
+
+    /// Put home directories in `/home`, as Linux does.
+    pub fn linux() -> HomePolicy {
+        HomePolicy::new("/home")
+    }
+
+    /// Put home directories in `/Users`, as macOS does.
+    pub fn macos() -> HomePolicy {
+        HomePolicy::new("/Users")
+    }
+
+    /// Put home directories in `/usr/home`, as FreeBSD does.
+    pub fn freebsd() -> HomePolicy {
+        HomePolicy::new("/usr/home")
+    }
+
+    /// The directory that home directories go in.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// The home directory for a new user with the given name.
+    pub fn home_for(&self, name: &str) -> PathBuf {
+        self.base.join(name)
+    }
+}
+
+impl Default for HomePolicy {
+    fn default() -> HomePolicy {
+        HomePolicy::linux()
+    }
+}
+
+/// Which shell new users get.
+///
+/// The default is `/bin/bash`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ShellPolicy {
+    shell: PathBuf,
+}
+
+impl ShellPolicy {
+    /// Give new users the given shell.
+    pub fn new<P: Into<PathBuf>>(shell: P) -> ShellPolicy {
+        ShellPolicy { shell: shell.into() }
+    }
+
+    /// The shell that new users get.
+    pub fn shell(&self) -> &Path {
+        &self.shell
+    }
+}
+
+impl Default for ShellPolicy {
+    fn default() -> ShellPolicy {
+        ShellPolicy::new("/bin/bash")
+    }
+}
+
+impl Group {
+    /// Start building a group with the given ID and name, and no members.
+    pub fn builder(gid: gid_t, name: &str) -> GroupBuilder {
+        GroupBuilder {
+            group: Group {
+                gid,
+                name:    name.to_owned(),
+                name_os: OsString::from(name),
+                members: Vec::new(),
+                passwd:  "x".to_owned(),
+            },
+        }
+    }
+}
+
+/// A way of making a `User` without having to fill in every field. This is
+/// mostly useful for adding users to a `MockUsers` table.
+pub struct UserBuilder {
+    user: User,
+}
+
+impl UserBuilder {
+    /// Replace the user's name with one that doesn't have to be valid UTF-8.
+    pub fn name_os(mut self, name: &OsStr) -> UserBuilder {
+        self.user.name = name.to_string_lossy().into_owned();
+        self.user.name_os = name.to_os_string();
+        self
+    }
+
+    /// Set the ID of the user's primary group.
+    pub fn primary_group(mut self, gid: gid_t) -> UserBuilder {
+        self.user.primary_group = gid;
+        self
+    }
+
+    /// Set the user's home directory.
+    pub fn home_dir<P: Into<PathBuf>>(mut self, home_dir: P) -> UserBuilder {
+        self.user.home_dir = home_dir.into();
+        self
+    }
+
+    /// Set the user's shell.
+    pub fn shell<P: Into<PathBuf>>(mut self, shell: P) -> UserBuilder {
+        self.user.shell = shell.into();
+        self
+    }
+
+    /// Set the user's GECOS field, which also sets their full name to its
+    /// first comma-separated component.
+    pub fn gecos(mut self, gecos: &str) -> UserBuilder {
+        self.user.full_name = gecos_full_name(gecos);
+        self.user.gecos = gecos.to_owned();
+        self
+    }
+
+    /// Set the user's password field.
+    pub fn passwd(mut self, passwd: &str) -> UserBuilder {
+        self.user.passwd = passwd.to_owned();
+        self
+    }
+
+    /// Finish building the user.
+    pub fn build(self) -> User {
+        self.user
+    }
+}
+
+/// A way of making a `Group` without having to fill in every field.
+pub struct GroupBuilder {
+    group: Group,
+}
+
+impl GroupBuilder {
+    /// Replace the group's name with one that doesn't have to be valid UTF-8.
+    pub fn name_os(mut self, name: &OsStr) -> GroupBuilder {
+        self.group.name = name.to_string_lossy().into_owned();
+        self.group.name_os = name.to_os_string();
+        self
+    }
+
+    /// Set the group's password field.
+    pub fn passwd(mut self, passwd: &str) -> GroupBuilder {
+        self.group.passwd = passwd.to_owned();
+        self
+    }
+
+    /// Add a user to the group's members.
+    pub fn member(mut self, username: &str) -> GroupBuilder {
+        self.group.members.push(username.to_owned());
+        self
+    }
+
+    /// Finish building the group.
+    pub fn build(self) -> Group {
+        self.group
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{User, Group};
+    use super::{HomePolicy, ShellPolicy};
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[test]
+    fn user_defaults() {
+        let user = User::builder(1000, "fred").build();
+        assert_eq!(user.uid, 1000);
+        assert_eq!(user.name, "fred");
+        assert_eq!(user.name_os, "fred");
+        assert_eq!(user.primary_group, 1000);
+        assert_eq!(user.home_dir, Path::new("/"));
+        assert_eq!(user.shell, Path::new("/bin/sh"));
+        assert!(!user.is_locked());
+    }
+
+    #[test]
+    fn user_fields() {
+        let user = User::builder(1000, "fred")
+                       .primary_group(100)
+                       .home_dir("/home/fred")
+                       .shell("/bin/bash")
+                       .gecos("Fred,,,")
+                       .passwd("!")
+                       .build();
+
+        assert_eq!(user.primary_group, 100);
+        assert_eq!(user.home_dir, Path::new("/home/fred"));
+        assert_eq!(user.shell, Path::new("/bin/bash"));
+        assert_eq!(user.full_name, "Fred");
+        assert_eq!(user.gecos, "Fred,,,");
+        assert!(user.is_locked());
+    }
+
+    #[test]
+    fn user_with_defaults() {
+        let user = User::with_defaults(1000, "fred");
+        assert_eq!(user.home_dir, Path::new("/home/fred"));
+        assert_eq!(user.shell, Path::new("/bin/bash"));
+        assert_eq!(user.primary_group, 1000);
+    }
+
+    #[test]
+    fn user_with_defaults_in() {
+        let user = User::with_defaults_in(1000, "fred", "/srv/users");
+        assert_eq!(user.home_dir, Path::new("/srv/users/fred"));
+        assert_eq!(user.shell, Path::new("/bin/bash"));
+    }
+
+    #[test]
+    fn user_with_policies() {
+        let user = User::with_policies(1000, "fred", &HomePolicy::freebsd(), &ShellPolicy::new("/bin/tcsh"));
+        assert_eq!(user.home_dir, Path::new("/usr/home/fred"));
+        assert_eq!(user.shell, Path::new("/bin/tcsh"));
+    }
+
+    #[test]
+    fn user_name_os() {
+        let user = User::builder(1000, "fred").name_os(OsStr::from_bytes(b"fr\xffd")).build();
+        assert_eq!(user.name, "fr\u{FFFD}d");
+        assert_eq!(user.name_os.as_bytes(), b"fr\xffd");
+    }
+
+    #[test]
+    fn group_members() {
+        let group = Group::builder(100, "users").member("fred").member("bob").build();
+        assert_eq!(group.gid, 100);
+        assert_eq!(group.name_os, "users");
+        assert_eq!(group.members, vec![ "fred".to_string(), "bob".to_string() ]);
+    }
+}
@@ -0,0 +1,228 @@
+//! Comparing two snapshots of the users or groups database.
+//!
+//! Take a snapshot with `Users::snapshot_users` or `Users::snapshot_groups`,
+//! keep it as a baseline, and compare it with a later one to find out which
+//! accounts have been added, removed, or modified in between.
+
+use std::collections::HashMap;
+
+use libc::{uid_t, gid_t};
+
+use super::{User, Group};
+
+/// The differences between two snapshots of the users database.
+#[derive(PartialEq, Clone, Default)]
+pub struct UserDiff {
+
+    /// Users in the new snapshot but not the old one
+    pub added: Vec<User>,
+
+    /// Users in the old snapshot but not the new one
+    pub removed: Vec<User>,
+
+    /// Users in both snapshots that have changed
+    pub changed: Vec<UserChange>,
+}
+
+/// A user that's in both snapshots, but with different details.
+#[derive(PartialEq, Debug, Clone)]
+pub struct UserChange {
+
+    /// The user's ID, which is what matched the two entries up
+    pub uid: uid_t,
+
+    /// Each field that's different
+    pub fields: Vec<FieldChange>,
+}
+
+/// The differences between two snapshots of the groups database.
+#[derive(PartialEq, Clone, Default)]
+pub struct GroupDiff {
+
+    /// Groups in the new snapshot but not the old one
+    pub added: Vec<Group>,
+
+    /// Groups in the old snapshot but not the new one
+    pub removed: Vec<Group>,
+
+    /// Groups in both snapshots that have changed
+    pub changed: Vec<GroupChange>,
+}
+
+/// A group that's in both snapshots, but with different details.
+#[derive(PartialEq, Debug, Clone)]
+pub struct GroupChange {
+
+    /// The group's ID, which is what matched the two entries up
+    pub gid: gid_t,
+
+    /// Each field other than the member list that's different
+    pub fields: Vec<FieldChange>,
+
+    /// Members in the new group but not the old one
+    pub members_added: Vec<String>,
+
+    /// Members in the old group but not the new one
+    pub members_removed: Vec<String>,
+}
+
+/// One field that's different between two versions of a user or group.
+#[derive(PartialEq, Debug, Clone)]
+pub struct FieldChange {
+
+    /// The name of the field, such as `shell`
+    pub field: &'static str,
+
+    /// The old value, formatted as it would be in `/etc/passwd`
+    pub old: String,
+
+    /// The new value
+    pub new: String,
+}
+
+impl UserDiff {
+    /// Whether the two snapshots are the same.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl GroupDiff {
+    /// Whether the two snapshots are the same.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two snapshots of the users database, matching users up by ID.
+/// Each list in the result is in order of user ID.
+pub fn diff_users(old: &HashMap<uid_t, User>, new: &HashMap<uid_t, User>) -> UserDiff {
+    let mut diff = UserDiff::default();
+
+    for (uid, user) in new {
+        match old.get(uid) {
+            None => diff.added.push(user.clone()),
+            Some(old_user) => {
+                let fields = user_fields(old_user, user);
+                if !fields.is_empty() {
+                    diff.changed.push(UserChange { uid: *uid, fields });
+                }
+            },
+        }
+    }
+
+    diff.removed = old.iter().filter(|&(uid, _)| !new.contains_key(uid)).map(|(_, u)| u.clone()).collect();
+
+    diff.added.sort_by_key(|u| u.uid);
+    diff.removed.sort_by_key(|u| u.uid);
+    diff.changed.sort_by_key(|c| c.uid);
+    diff
+}
+
+/// Compare two snapshots of the groups database, matching groups up by ID.
+/// Each list in the result is in order of group ID.
+pub fn diff_groups(old: &HashMap<gid_t, Group>, new: &HashMap<gid_t, Group>) -> GroupDiff {
+    let mut diff = GroupDiff::default();
+
+    for (gid, group) in new {
+        match old.get(gid) {
+            None => diff.added.push(group.clone()),
+            Some(old_group) => {
+                let mut fields = Vec::new();
+                compare(&mut fields, "name", &old_group.name, &group.name);
+                compare(&mut fields, "passwd", &old_group.passwd, &group.passwd);
+
+                let members_added: Vec<String> = group.members.iter().filter(|m| !old_group.members.contains(m)).cloned().collect();
+                let members_removed: Vec<String> = old_group.members.iter().filter(|m| !group.members.contains(m)).cloned().collect();
+
+                if !fields.is_empty() || !members_added.is_empty() || !members_removed.is_empty() {
+                    diff.changed.push(GroupChange { gid: *gid, fields, members_added, members_removed });
+                }
+            },
+        }
+    }
+
+    diff.removed = old.iter().filter(|&(gid, _)| !new.contains_key(gid)).map(|(_, g)| g.clone()).collect();
+
+    diff.added.sort_by_key(|g| g.gid);
+    diff.removed.sort_by_key(|g| g.gid);
+    diff.changed.sort_by_key(|c| c.gid);
+    diff
+}
+
+fn user_fields(old: &User, new: &User) -> Vec<FieldChange> {
+    let mut fields = Vec::new();
+    compare(&mut fields, "name", &old.name, &new.name);
+    compare(&mut fields, "passwd", &old.passwd, &new.passwd);
+    compare(&mut fields, "primary_group", &old.primary_group.to_string(), &new.primary_group.to_string());
+    compare(&mut fields, "gecos", &old.gecos, &new.gecos);
+    compare(&mut fields, "home_dir", &old.home_dir.display().to_string(), &new.home_dir.display().to_string());
+    compare(&mut fields, "shell", &old.shell.display().to_string(), &new.shell.display().to_string());
+    fields
+}
+
+fn compare(fields: &mut Vec<FieldChange>, field: &'static str, old: &str, new: &str) {
+    if old != new {
+        fields.push(FieldChange { field, old: old.to_owned(), new: new.to_owned() });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff_users, diff_groups, FieldChange};
+    use super::super::{User, Group};
+    use std::collections::HashMap;
+
+    fn users(list: Vec<User>) -> HashMap<u32, User> {
+        list.into_iter().map(|u| (u.uid, u)).collect()
+    }
+
+    fn groups(list: Vec<Group>) -> HashMap<u32, Group> {
+        list.into_iter().map(|g| (g.gid, g)).collect()
+    }
+
+    #[test]
+    fn same_snapshot() {
+        let snapshot = users(vec![ User::builder(1000, "fred").build() ]);
+        assert!(diff_users(&snapshot, &snapshot.clone()).is_empty());
+    }
+
+    #[test]
+    fn changed_shell() {
+        let old = users(vec![ User::builder(1000, "fred").shell("/bin/bash").build() ]);
+        let new = users(vec![ User::builder(1000, "fred").shell("/bin/zsh").build() ]);
+
+        let diff = diff_users(&old, &new);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].uid, 1000);
+        assert_eq!(diff.changed[0].fields, vec![
+            FieldChange { field: "shell", old: "/bin/bash".to_string(), new: "/bin/zsh".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn added_and_removed() {
+        let old = users(vec![ User::builder(1000, "fred").build(), User::builder(1001, "sue").build() ]);
+        let new = users(vec![ User::builder(1000, "fred").build(), User::builder(1002, "barney").build() ]);
+
+        let diff = diff_users(&old, &new);
+        assert_eq!(diff.removed.iter().map(|u| &u.name[..]).collect::<Vec<_>>(), vec![ "sue" ]);
+        assert_eq!(diff.added.iter().map(|u| &u.name[..]).collect::<Vec<_>>(), vec![ "barney" ]);
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn group_members() {
+        let old = groups(vec![ Group::builder(10, "wheel").member("fred").member("sue").build() ]);
+        let new = groups(vec![ Group::builder(10, "wheel").member("sue").member("barney").build(), Group::builder(20, "audio").build() ]);
+
+        let diff = diff_groups(&old, &new);
+        assert_eq!(diff.added.len(), 1);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.changed.len(), 1);
+        assert!(diff.changed[0].fields.is_empty());
+        assert_eq!(diff.changed[0].members_added, vec![ "barney".to_string() ]);
+        assert_eq!(diff.changed[0].members_removed, vec![ "fred".to_string() ]);
+    }
+}
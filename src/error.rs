@@ -0,0 +1,103 @@
+//! The errors that the `try_*` lookups can fail with.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use libc::ERANGE;
+
+/// The reason a lookup failed.
+///
+/// Not finding a user or group usually isn't an error: the `try_*` lookups
+/// by ID or name return `Ok(None)` for that, and only the ones that always
+/// expect an entry, such as `try_get_current_username`, fail with
+/// `NotFound`.
+///
+/// Errors from libc are mapped by their errno value:
+///
+/// - `ERANGE` becomes `BufferTooSmall`. The lookups retry with bigger
+///   buffers for as long as they get this, so it only gets this far when
+///   an entry is too big for any buffer they're willing to allocate.
+/// - Everything else, such as `EIO`, `EMFILE`, or `EINTR`, becomes `Io`.
+///
+/// A lookup through a `TimeoutSource` that takes too long fails with
+/// `TimedOut`.
+#[derive(Debug)]
+pub enum Error {
+
+    /// There's no user or group where there has to be one.
+    NotFound,
+
+    /// The lookup itself failed.
+    Io(io::Error),
+
+    /// The entry didn't fit in the largest buffer the lookup would offer.
+    BufferTooSmall,
+
+    /// A name that has to be returned as a string isn't valid UTF-8.
+    InvalidUtf8,
+
+    /// The lookup was given up on after taking longer than its time limit.
+    TimedOut,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        if error.raw_os_error() == Some(ERANGE) { Error::BufferTooSmall }
+        else if error.kind() == io::ErrorKind::TimedOut { Error::TimedOut }
+        else { Error::Io(error) }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::NotFound       => io::Error::new(io::ErrorKind::NotFound, "no such user or group"),
+            Error::Io(e)          => e,
+            Error::BufferTooSmall => io::Error::from_raw_os_error(ERANGE),
+            Error::InvalidUtf8    => io::Error::new(io::ErrorKind::InvalidData, "name isn't valid UTF-8"),
+            Error::TimedOut       => io::Error::new(io::ErrorKind::TimedOut, "lookup timed out"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotFound       => write!(f, "no such user or group"),
+            Error::Io(ref e)      => write!(f, "lookup failed: {}", e),
+            Error::BufferTooSmall => write!(f, "entry is too big to look up"),
+            Error::InvalidUtf8    => write!(f, "name isn't valid UTF-8"),
+            Error::TimedOut       => write!(f, "lookup timed out"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _                => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Error;
+    use libc::{EIO, ERANGE};
+    use std::io;
+
+    #[test]
+    fn errno_mapping() {
+        assert!(matches!(Error::from(io::Error::from_raw_os_error(ERANGE)), Error::BufferTooSmall));
+        assert!(matches!(Error::from(io::Error::from_raw_os_error(EIO)), Error::Io(ref e) if e.raw_os_error() == Some(EIO)));
+        assert!(matches!(Error::from(io::Error::from(io::ErrorKind::TimedOut)), Error::TimedOut));
+    }
+
+    #[test]
+    fn back_to_io() {
+        assert_eq!(io::Error::from(Error::NotFound).kind(), io::ErrorKind::NotFound);
+        assert_eq!(io::Error::from(Error::BufferTooSmall).raw_os_error(), Some(ERANGE));
+    }
+}
@@ -0,0 +1,867 @@
+//! Users and groups read straight from passwd- and group-format files,
+//! without going through libc.
+//!
+//! The functions in libc consult the Name Service Switch, which may not work
+//! inside a sandbox or a chroot even when plain `/etc/passwd` and
+//! `/etc/group` files are still readable. A `PasswdFile` parses such a file
+//! once, and then answers lookups from what it read, the same way a
+//! `MockUsers` does. Give it a `GroupFile` to answer group lookups as well.
+//!
+//! To read the files when they're there and use libc when they aren't, as a
+//! fully static binary might want to, give an `OSUsers` a `PreferFiles`
+//! source instead.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::{CStr, OsStr};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{fchown, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::{c_char, uid_t, gid_t};
+
+use super::{Users, UsersRead, User, Group, PasswdSource, LibcSource, IntegrityIssue, check_integrity, gecos_full_name};
+use super::{getuid, geteuid, getgid, getegid, process_groups, login_name};
+use super::source::member_group_list;
+
+/// A users table read from a file in the format of `/etc/passwd`.
+///
+/// ```no_run
+/// use users::{Users, PasswdFile, GroupFile};
+/// let mut users = PasswdFile::open("/etc/passwd").unwrap()
+///                            .with_groups(GroupFile::open("/etc/group").unwrap());
+/// println!("{:?}", users.get_user_by_uid(0).map(|u| u.name));
+/// println!("{:?}", users.get_group_by_gid(0).map(|g| g.name));
+/// ```
+///
+/// Blank lines, comments, and lines that can't be parsed are skipped. When
+/// more than one line has the same user ID or name, the first one wins, as
+/// it does for libc.
+///
+/// Without a `GroupFile`, every group lookup misses.
+#[derive(Clone)]
+pub struct PasswdFile {
+    users: Vec<Rc<User>>,
+    lines: Vec<Option<Vec<u8>>>,
+    groups: GroupFile,
+}
+
+/// A groups table read from a file in the format of `/etc/group`. It's
+/// parsed by the same rules as a `PasswdFile`.
+#[derive(Clone)]
+pub struct GroupFile {
+    groups: Vec<Group>,
+}
+
+impl PasswdFile {
+    /// Read and parse the passwd-format file at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<PasswdFile> {
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
+        Ok(PasswdFile::parse(&contents))
+    }
+
+    fn parse(contents: &[u8]) -> PasswdFile {
+        let (users, lines) = contents.split(|&b| b == b'\n')
+                                     .filter_map(|line| parse_passwd_line(line).map(|u| (Rc::new(u), Some(line.to_vec()))))
+                                     .unzip();
+
+        PasswdFile { users, lines, groups: GroupFile { groups: Vec::new() } }
+    }
+
+    /// Add a user to the end of the table.
+    pub fn add_user(&mut self, user: User) {
+        self.users.push(Rc::new(user));
+        self.lines.push(None);
+    }
+
+    /// Write every user in the table to the file at the given path, in
+    /// `/etc/passwd` format and in the order they were read or added.
+    /// Comments and lines that couldn't be parsed when the table was read
+    /// aren't kept.
+    ///
+    /// Users that were read from a file are written back exactly as their
+    /// line was, bytes and all. Users that were added are written with
+    /// `User::write_to`, so saving fails without touching the target if
+    /// one of their fields contains a colon or a newline.
+    ///
+    /// The users are written to a new temporary file in the same directory,
+    /// which is then renamed over the target, so the target is never left
+    /// half-written, and two saves at once can't write to the same
+    /// temporary file. If the target already exists, its permissions and
+    /// its owner are copied to the new file; copying an owner other than
+    /// your own needs root, and saving fails without it.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let file_name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")),
+        };
+
+        let mut contents = Vec::new();
+        for (user, line) in self.users.iter().zip(&self.lines) {
+            match *line {
+                Some(ref line) => contents.extend_from_slice(line),
+                None => user.write_to(&mut contents)?,
+            }
+            contents.push(b'\n');
+        }
+
+        let (temp_path, temp) = create_temp_file(path, &file_name)?;
+        let result = replace_file(path, &temp_path, temp, &contents);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Answer group lookups from the given groups table.
+    pub fn with_groups(self, groups: GroupFile) -> PasswdFile {
+        PasswdFile { groups, .. self }
+    }
+
+    fn find_by_uid(&self, uid: uid_t) -> Option<&Rc<User>> {
+        self.users.iter().find(|u| u.uid == uid)
+    }
+
+    fn find_by_name(&self, username: &OsStr) -> Option<&Rc<User>> {
+        self.users.iter().find(|u| u.name_os == username)
+    }
+}
+
+impl GroupFile {
+    /// Read and parse the group-format file at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<GroupFile> {
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
+        Ok(GroupFile::parse(&contents))
+    }
+
+    fn parse(contents: &[u8]) -> GroupFile {
+        let groups = contents.split(|&b| b == b'\n')
+                             .filter_map(parse_group_line)
+                             .collect();
+
+        GroupFile { groups }
+    }
+
+    /// Return the group with the given ID, if there is one.
+    pub fn get_group_by_gid(&self, gid: gid_t) -> Option<Group> {
+        self.find_by_gid(gid).cloned()
+    }
+
+    /// Return the group with the given name, if there is one.
+    pub fn get_group_by_name(&self, group_name: &str) -> Option<Group> {
+        self.find_by_name(group_name.as_ref()).cloned()
+    }
+
+    fn find_by_gid(&self, gid: gid_t) -> Option<&Group> {
+        self.groups.iter().find(|g| g.gid == gid)
+    }
+
+    fn find_by_name(&self, group_name: &OsStr) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name_os == group_name)
+    }
+}
+
+/// A source for `OSUsers` that reads users from a passwd-format file and
+/// groups from a group-format file, and only uses libc for whichever of the
+/// two files couldn't be read.
+///
+/// ```no_run
+/// use users::{Users, OSUsers};
+/// let mut users = OSUsers::prefer_files();
+/// println!("{:?}", users.get_user_by_uid(0).map(|u| u.name));
+/// ```
+///
+/// This suits fully static binaries, such as ones linked against musl,
+/// where the Name Service Switch isn't available. But while a file is
+/// there, it's the only place that's looked: users and groups that come
+/// from other NSS sources, such as LDAP or systemd, won't be found, even by
+/// a binary that could have used them.
+#[derive(Clone)]
+pub struct PreferFiles {
+    passwd: Option<PasswdFile>,
+    groups: Option<GroupFile>,
+}
+
+impl PreferFiles {
+    /// Read the files at the given paths. Either one can be missing, in which
+    /// case its lookups go to libc instead.
+    pub fn open<P: AsRef<Path>, G: AsRef<Path>>(passwd: P, group: G) -> PreferFiles {
+        PreferFiles {
+            passwd: PasswdFile::open(passwd).ok(),
+            groups: GroupFile::open(group).ok(),
+        }
+    }
+}
+
+impl PasswdSource for PreferFiles {
+    fn user_by_uid(&self, uid: uid_t, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        match self.passwd {
+            Some(ref passwd) => Ok(passwd.find_by_uid(uid).map(|u| (**u).clone())),
+            None => LibcSource.user_by_uid(uid, buffer),
+        }
+    }
+
+    fn user_by_name(&self, username: &CStr, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        match self.passwd {
+            Some(ref passwd) => Ok(passwd.find_by_name(OsStr::from_bytes(username.to_bytes())).map(|u| (**u).clone())),
+            None => LibcSource.user_by_name(username, buffer),
+        }
+    }
+
+    fn group_by_gid(&self, gid: gid_t, buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        match self.groups {
+            Some(ref groups) => Ok(groups.get_group_by_gid(gid)),
+            None => LibcSource.group_by_gid(gid, buffer),
+        }
+    }
+
+    fn group_by_name(&self, group_name: &CStr, buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        match self.groups {
+            Some(ref groups) => Ok(groups.find_by_name(OsStr::from_bytes(group_name.to_bytes())).cloned()),
+            None => LibcSource.group_by_name(group_name, buffer),
+        }
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+        match self.passwd {
+            Some(ref passwd) => passwd.all_users(),
+            None => LibcSource.all_users(),
+        }
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+        match self.groups {
+            Some(ref groups) => Box::new(groups.groups.iter().cloned()),
+            None => LibcSource.all_groups(),
+        }
+    }
+
+    fn group_list(&self, username: &str, gid: gid_t) -> Option<Vec<gid_t>> {
+        match self.groups {
+            Some(ref groups) => Some(member_group_list(groups.groups.iter().cloned(), username, gid)),
+            None => LibcSource.group_list(username, gid),
+        }
+    }
+
+    fn group_count(&self, username: &str, gid: gid_t) -> usize {
+        match self.groups {
+            Some(_) => self.group_list(username, gid).map_or(0, |gids| gids.len()),
+            None => LibcSource.group_count(username, gid),
+        }
+    }
+}
+
+/// A passwd file can be the source for an `OSUsers`, which then caches what
+/// it finds there.
+impl PasswdSource for PasswdFile {
+    fn user_by_uid(&self, uid: uid_t, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        Ok(self.find_by_uid(uid).map(|u| (**u).clone()))
+    }
+
+    fn user_by_name(&self, username: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        Ok(self.find_by_name(OsStr::from_bytes(username.to_bytes())).map(|u| (**u).clone()))
+    }
+
+    fn group_by_gid(&self, gid: gid_t, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        Ok(self.groups.find_by_gid(gid).cloned())
+    }
+
+    fn group_by_name(&self, group_name: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        Ok(self.groups.find_by_name(OsStr::from_bytes(group_name.to_bytes())).cloned())
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+        Box::new(self.users.iter().map(|u| (**u).clone()))
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+        Box::new(self.groups.groups.iter().cloned())
+    }
+}
+
+/// Write the contents to the temporary path, then rename it over the target.
+fn replace_file(path: &Path, temp_path: &Path, mut temp: File, contents: &[u8]) -> io::Result<()> {
+    temp.write_all(contents)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        temp.set_permissions(metadata.permissions())?;
+
+        let temp_metadata = temp.metadata()?;
+        if (temp_metadata.uid(), temp_metadata.gid()) != (metadata.uid(), metadata.gid()) {
+            fchown(&temp, Some(metadata.uid()), Some(metadata.gid()))?;
+        }
+    }
+    temp.sync_all()?;
+    fs::rename(temp_path, path)
+}
+
+/// The number of temporary files this process has tried to create, to give
+/// each one a different name.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Create a new temporary file next to the target, with a name that no
+/// other file has, and return its path along with the file.
+fn create_temp_file(path: &Path, file_name: &str) -> io::Result<(PathBuf, File)> {
+    loop {
+        let count = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+        let temp_path = path.with_file_name(format!(".{}.{}.{}.tmp", file_name, process::id(), count));
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(temp) => return Ok((temp_path, temp)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Parse one line of a passwd file, which has seven colon-separated fields:
+/// name, password, user ID, group ID, GECOS, home directory, and shell.
+fn parse_passwd_line(line: &[u8]) -> Option<User> {
+    if line.iter().all(|b| b.is_ascii_whitespace()) || line.starts_with(b"#") {
+        return None;
+    }
+
+    let fields: Vec<&[u8]> = line.split(|&b| b == b':').collect();
+    if fields.len() != 7 || fields[0].is_empty() {
+        return None;
+    }
+
+    let uid = parse_id(fields[2])?;
+    let gid = parse_id(fields[3])?;
+    let gecos = String::from_utf8_lossy(fields[4]).into_owned();
+
+    Some(User {
+        uid,
+        name: String::from_utf8_lossy(fields[0]).into_owned(),
+        name_os: OsStr::from_bytes(fields[0]).to_os_string(),
+        primary_group: gid,
+        home_dir: PathBuf::from(OsStr::from_bytes(fields[5])),
+        shell: PathBuf::from(OsStr::from_bytes(fields[6].trim_ascii_end())),
+        full_name: gecos_full_name(&gecos),
+        gecos,
+        passwd: String::from_utf8_lossy(fields[1]).into_owned(),
+        raw_line: Some(String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned()),
+    })
+}
+
+/// Parse one line of a group file, which has four colon-separated fields:
+/// name, password, group ID, and a comma-separated list of members.
+fn parse_group_line(line: &[u8]) -> Option<Group> {
+    let line = line.trim_ascii_end();
+    if line.is_empty() || line.starts_with(b"#") {
+        return None;
+    }
+
+    let fields: Vec<&[u8]> = line.split(|&b| b == b':').collect();
+    if fields.len() != 4 || fields[0].is_empty() {
+        return None;
+    }
+
+    let members = fields[3].split(|&b| b == b',')
+                           .map(|m| m.trim_ascii())
+                           .filter(|m| !m.is_empty())
+                           .map(|m| String::from_utf8_lossy(m).into_owned())
+                           .collect();
+
+    Some(Group {
+        gid: parse_id(fields[2])?,
+        name: String::from_utf8_lossy(fields[0]).into_owned(),
+        name_os: OsStr::from_bytes(fields[0]).to_os_string(),
+        members,
+        passwd: String::from_utf8_lossy(fields[1]).into_owned(),
+    })
+}
+
+fn parse_id(field: &[u8]) -> Option<uid_t> {
+    ::std::str::from_utf8(field).ok()?.parse().ok()
+}
+
+impl UsersRead for PasswdFile {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<User> {
+        self.find_by_uid(uid).map(|u| (**u).clone())
+    }
+
+    fn get_user_by_uid_cow(&self, uid: uid_t) -> Option<Cow<'_, User>> {
+        self.find_by_uid(uid).map(|u| Cow::Borrowed(&**u))
+    }
+
+    fn get_user_by_name(&self, username: &str) -> Option<User> {
+        self.find_by_name(username.as_ref()).map(|u| (**u).clone())
+    }
+
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Group> {
+        self.groups.get_group_by_gid(gid)
+    }
+
+    fn get_group_by_name(&self, group_name: &str) -> Option<Group> {
+        self.groups.get_group_by_name(group_name)
+    }
+
+    fn get_user_groups(&self, username: &str, gid: gid_t) -> Option<Vec<Group>> {
+        self.find_by_name(username.as_ref())?;
+
+        let mut groups: Vec<Group> = self.groups.find_by_gid(gid).into_iter().cloned().collect();
+        for group in &self.groups.groups {
+            if group.members.iter().any(|m| m == username) && !groups.iter().any(|g| g.gid == group.gid) {
+                groups.push(group.clone());
+            }
+        }
+
+        Some(groups)
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        unsafe { getuid() }
+    }
+
+    fn get_current_username(&self) -> Option<String> {
+        self.find_by_uid(UsersRead::get_current_uid(self)).map(|u| u.name.clone())
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        unsafe { getgid() }
+    }
+
+    fn get_current_groupname(&self) -> Option<String> {
+        self.groups.find_by_gid(UsersRead::get_current_gid(self)).map(|g| g.name.clone())
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        unsafe { geteuid() }
+    }
+
+    fn get_effective_username(&self) -> Option<String> {
+        self.find_by_uid(UsersRead::get_effective_uid(self)).map(|u| u.name.clone())
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        unsafe { getegid() }
+    }
+
+    fn get_effective_groupname(&self) -> Option<String> {
+        self.groups.find_by_gid(UsersRead::get_effective_gid(self)).map(|g| g.name.clone())
+    }
+}
+
+impl Users for PasswdFile {
+    fn get_user_by_uid_rc(&mut self, uid: uid_t) -> Option<Rc<User>> {
+        self.find_by_uid(uid).cloned()
+    }
+
+    fn get_user_by_name_rc(&mut self, username: &str) -> Option<Rc<User>> {
+        self.find_by_name(username.as_ref()).cloned()
+    }
+
+    fn get_user_by_name_os(&mut self, username: &OsStr) -> Option<User> {
+        self.find_by_name(username).map(|u| (**u).clone())
+    }
+
+    fn get_user_by_name_ci(&mut self, username: &str) -> Option<User> {
+        self.find_by_name(username.as_ref())
+            .or_else(|| self.users.iter().find(|u| u.name.eq_ignore_ascii_case(username)))
+            .map(|u| (**u).clone())
+    }
+
+    fn get_group_by_name_os(&mut self, group_name: &OsStr) -> Option<Group> {
+        self.groups.find_by_name(group_name).cloned()
+    }
+
+    fn get_group_by_gid(&mut self, gid: gid_t) -> Option<Group> {
+        UsersRead::get_group_by_gid(self, gid)
+    }
+
+    fn get_all_users_by_uid(&mut self, uid: uid_t) -> Vec<User> {
+        self.users.iter().filter(|u| u.uid == uid).map(|u| (**u).clone()).collect()
+    }
+
+    fn users_in_range(&mut self, range: Range<uid_t>) -> Vec<User> {
+        self.users.iter().filter(|u| range.contains(&u.uid)).map(|u| (**u).clone()).collect()
+    }
+
+    fn snapshot_users(&mut self) -> HashMap<uid_t, User> {
+        let mut users = HashMap::new();
+        for user in &self.users {
+            users.entry(user.uid).or_insert_with(|| (**user).clone());
+        }
+        users
+    }
+
+    fn snapshot_groups(&mut self) -> HashMap<gid_t, Group> {
+        let mut groups = HashMap::new();
+        for group in &self.groups.groups {
+            groups.entry(group.gid).or_insert_with(|| group.clone());
+        }
+        groups
+    }
+
+    fn validate_integrity(&mut self) -> Vec<IntegrityIssue> {
+        let users: Vec<User> = self.users.iter().map(|u| (**u).clone()).collect();
+        check_integrity(&users, &self.groups.groups)
+    }
+
+    fn get_group_members(&mut self, gid: gid_t) -> Vec<User> {
+        let members = self.groups.find_by_gid(gid).map(|g| &g.members[..]).unwrap_or(&[]);
+        self.users.iter()
+            .filter(|u| u.primary_group == gid || members.contains(&u.name))
+            .map(|u| (**u).clone())
+            .collect()
+    }
+
+    fn get_group_by_name(&mut self, group_name: &str) -> Option<Group> {
+        UsersRead::get_group_by_name(self, group_name)
+    }
+
+    fn get_user_groups(&mut self, username: &str, gid: gid_t) -> Option<Vec<Group>> {
+        UsersRead::get_user_groups(self, username, gid)
+    }
+
+    fn get_current_uid(&mut self) -> uid_t {
+        UsersRead::get_current_uid(self)
+    }
+
+    fn get_current_username(&mut self) -> Option<String> {
+        UsersRead::get_current_username(self)
+    }
+
+    fn get_current_gid(&mut self) -> gid_t {
+        UsersRead::get_current_gid(self)
+    }
+
+    fn get_current_groupname(&mut self) -> Option<String> {
+        UsersRead::get_current_groupname(self)
+    }
+
+    fn get_effective_uid(&mut self) -> uid_t {
+        UsersRead::get_effective_uid(self)
+    }
+
+    fn get_effective_username(&mut self) -> Option<String> {
+        UsersRead::get_effective_username(self)
+    }
+
+    fn get_effective_gid(&mut self) -> gid_t {
+        UsersRead::get_effective_gid(self)
+    }
+
+    fn get_effective_groupname(&mut self) -> Option<String> {
+        UsersRead::get_effective_groupname(self)
+    }
+
+    fn current_process_groups(&mut self) -> Vec<gid_t> {
+        process_groups()
+    }
+
+    fn get_login_name(&mut self) -> Option<String> {
+        login_name()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Users, PasswdFile, GroupFile, PreferFiles};
+    use super::super::{OSUsers, User, Group};
+    use super::super::mock::MockUsers;
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::Path;
+
+    fn fixture() -> PasswdFile {
+        PasswdFile::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/passwd")).unwrap()
+    }
+
+    fn group_fixture() -> GroupFile {
+        GroupFile::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/group")).unwrap()
+    }
+
+    fn prefer_fixtures(passwd: &str, group: &str) -> OSUsers<PreferFiles> {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/");
+        OSUsers::with_source(PreferFiles::open(format!("{}{}", dir, passwd), format!("{}{}", dir, group)))
+    }
+
+    #[test]
+    fn user_fields() {
+        let fred = fixture().get_user_by_name("fred").unwrap();
+        assert_eq!(fred.uid, 1000);
+        assert_eq!(fred.primary_group, 100);
+        assert_eq!(fred.full_name, "Fred Flintstone");
+        assert_eq!(fred.gecos, "Fred Flintstone,,,");
+        assert_eq!(fred.home_dir, Path::new("/home/fred"));
+        assert_eq!(fred.shell, Path::new("/bin/zsh"));
+        assert_eq!(fred.passwd, "x");
+    }
+
+    #[test]
+    fn raw_lines() {
+        let fred = fixture().get_user_by_name("fred").unwrap();
+        assert_eq!(fred.raw_line(), Some("fred:x:1000:100:Fred Flintstone,,,:/home/fred:/bin/zsh"));
+
+        let root = OSUsers::empty_cache().get_user_by_uid(0).unwrap();
+        assert_eq!(root.raw_line(), None);
+
+        let barney = super::parse_passwd_line(b"barney:x:1001:100::/home/barney:/bin/sh\r").unwrap();
+        assert_eq!(barney.raw_line(), Some("barney:x:1001:100::/home/barney:/bin/sh"));
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        let mut users = fixture();
+        assert_eq!(users.users.len(), 5);
+        assert!(users.get_user_by_name("bob").is_none());
+        assert!(users.get_user_by_name("this line is malformed").is_none());
+    }
+
+    #[test]
+    fn first_entry_wins() {
+        let mut users = fixture();
+        assert_eq!(Some("fred".to_string()), users.get_user_by_uid(1000).map(|u| u.name));
+        assert_eq!(Some(1000), users.get_user_by_name("barney").map(|u| u.uid));
+    }
+
+    #[test]
+    fn all_users_by_uid() {
+        let names: Vec<_> = fixture().get_all_users_by_uid(1000).into_iter().map(|u| u.name).collect();
+        assert_eq!(names, vec![ "fred".to_string(), "barney".to_string() ]);
+    }
+
+    #[test]
+    fn cached_over_file() {
+        let mut users = OSUsers::with_source(fixture().with_groups(group_fixture()));
+        assert_eq!(users.get_user_by_uid(1000).map(|u| u.name), Some("fred".to_string()));
+        assert_eq!(users.get_user_by_name("fred").map(|u| u.uid), Some(1000));
+
+        let gid = users.get_user_by_uid(1000).unwrap().primary_group;
+        assert!(users.get_group_by_gid(gid).is_some());
+        assert!(users.get_user_by_name("nobody-here").is_none());
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut users = fixture().with_groups(group_fixture());
+        let snapshot = users.snapshot_users();
+        assert_eq!(snapshot.len(), 4);
+        assert_eq!(snapshot[&1000].name, "fred");
+        assert_eq!(users.snapshot_groups().len(), 5);
+    }
+
+    #[test]
+    fn written_mock_round_trips() {
+        let mut mock = MockUsers::with_current_uid(0);
+        mock.add_user(User::builder(1001, "sue").gecos("Sue,Room 2").build());
+        mock.add_user(User::builder(1000, "fred").primary_group(100).home_dir("/home/fred").build());
+        mock.add_group(Group::builder(100, "users").member("fred").member("sue").build());
+
+        let dir = env::temp_dir().join(format!("users-write-test-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        mock.write_passwd(File::create(dir.join("passwd")).unwrap()).unwrap();
+        mock.write_group(File::create(dir.join("group")).unwrap()).unwrap();
+
+        let written = fs::read_to_string(dir.join("passwd")).unwrap();
+        let mut users = PasswdFile::open(dir.join("passwd")).unwrap()
+                                   .with_groups(GroupFile::open(dir.join("group")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(written.starts_with("fred:"));
+        assert!(users.get_user_by_uid(1000) == mock.get_user_by_uid(1000));
+        assert!(users.get_user_by_uid(1001) == mock.get_user_by_uid(1001));
+        assert_eq!(users.get_group_by_gid(100).map(|g| g.members), Some(vec![ "fred".to_string(), "sue".to_string() ]));
+    }
+
+    #[test]
+    fn written_names_keep_their_bytes() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut fred = User::builder(1000, "fred").build();
+        fred.name_os = OsStr::from_bytes(b"fr\xe9d").to_os_string();
+        fred.home_dir = Path::new(OsStr::from_bytes(b"/home/fr\xe9d")).to_path_buf();
+        let mut users = Group::builder(100, "users").member("fred").build();
+        users.name_os = OsStr::from_bytes(b"\xfcsers").to_os_string();
+
+        let mut mock = MockUsers::with_current_uid(0);
+        mock.add_user(fred.clone());
+        mock.add_group(users.clone());
+        let (mut passwd, mut group) = (Vec::new(), Vec::new());
+        mock.write_passwd(&mut passwd).unwrap();
+        mock.write_group(&mut group).unwrap();
+
+        let read = PasswdFile::parse(&passwd).with_groups(GroupFile::parse(&group));
+        assert_eq!(read.users[0].name_os, fred.name_os);
+        assert_eq!(read.users[0].home_dir, fred.home_dir);
+        assert_eq!(read.groups.groups[0].name_os, users.name_os);
+
+        mock.add_user(User::builder(1001, "sue").shell("/bin/sh\nroot::0:0::/:/bin/sh").build());
+        assert!(mock.write_passwd(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn largest_ids() {
+        assert_eq!(super::parse_id(b"4294967294"), Some(4294967294));
+        assert_eq!(super::parse_id(b"4294967295"), Some(0xFFFFFFFF));
+        assert_eq!(super::parse_id(b"4294967296"), None);
+        assert_eq!(super::parse_id(b"-2"), None);
+
+        let nobody = super::parse_passwd_line(b"nobody:x:4294967294:4294967294:::").unwrap();
+        assert_eq!((nobody.uid, nobody.primary_group), (4294967294, 4294967294));
+    }
+
+    #[test]
+    fn fixture_integrity() {
+        use super::IntegrityIssue;
+
+        assert_eq!(fixture().with_groups(group_fixture()).validate_integrity(), vec![
+            IntegrityIssue::DanglingPrimaryGroup { username: "daemon".to_string(), uid: 1, gid: 1 },
+            IntegrityIssue::DanglingPrimaryGroup { username: "sue".to_string(), uid: 1001, gid: 1001 },
+            IntegrityIssue::DuplicateUid { uid: 1000, usernames: vec![ "fred".to_string(), "barney".to_string() ] },
+        ]);
+    }
+
+    #[test]
+    fn locked_user() {
+        assert!(fixture().get_user_by_uid(1001).unwrap().is_locked());
+    }
+
+    #[test]
+    fn group_fields() {
+        let groups = group_fixture();
+        let users = groups.get_group_by_name("users").unwrap();
+        assert_eq!(users.gid, 100);
+        assert_eq!(users.members, vec![ "fred".to_string(), "barney".to_string() ]);
+        assert_eq!(groups.get_group_by_gid(50).map(|g| g.members), Some(vec![ "sue".to_string() ]));
+    }
+
+    #[test]
+    fn empty_members() {
+        let groups = group_fixture();
+        assert_eq!(groups.get_group_by_gid(0).map(|g| g.members.len()), Some(0));
+        assert_eq!(groups.get_group_by_name("audio").map(|g| g.members.len()), Some(0));
+    }
+
+    #[test]
+    fn skips_malformed_groups() {
+        let groups = group_fixture();
+        assert_eq!(groups.groups.len(), 5);
+        assert!(groups.get_group_by_name("broken").is_none());
+    }
+
+    #[test]
+    fn user_groups() {
+        let mut users = fixture().with_groups(group_fixture());
+        let gids = users.get_user_groups("fred", 100).map(|gs| gs.into_iter().map(|g| g.gid).collect::<Vec<_>>());
+        assert_eq!(Some(vec![ 100, 10 ]), gids);
+        assert!(users.is_user_in_group("sue", "staff"));
+        assert!(!users.is_user_in_group("sue", "users"));
+    }
+
+    #[test]
+    fn group_members() {
+        let mut users = fixture().with_groups(group_fixture());
+        let names: Vec<_> = users.get_group_members(10).into_iter().map(|u| u.name).collect();
+        assert_eq!(names, vec![ "fred".to_string(), "sue".to_string() ]);
+
+        let names: Vec<_> = users.get_group_members(100).into_iter().map(|u| u.name).collect();
+        assert_eq!(names, vec![ "fred".to_string(), "barney".to_string() ]);
+    }
+
+    #[test]
+    fn user_groups_without_groups() {
+        let mut users = fixture();
+        assert_eq!(Some(0), users.get_user_groups("fred", 100).map(|gs| gs.len()));
+        assert!(users.get_user_groups("wilma", 100).is_none());
+    }
+
+    #[test]
+    fn windows_line_endings() {
+        let users = PasswdFile::parse(b"fred:x:1000:100:Fred:/home/fred:/bin/sh\r\n");
+        assert_eq!(users.users[0].shell, Path::new("/bin/sh"));
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(PasswdFile::open("/this/file/is/not/there").is_err());
+    }
+
+    #[test]
+    fn prefer_files() {
+        let mut users = prefer_fixtures("passwd", "group");
+        assert_eq!(users.get_user_by_uid(1000).map(|u| u.name), Some("fred".to_string()));
+        assert_eq!(users.get_user_by_name("sue").map(|u| u.uid), Some(1001));
+        assert_eq!(users.get_group_by_name("wheel").map(|g| g.gid), Some(10));
+
+        // The files are all that's consulted while they're there.
+        assert!(users.get_user_by_name("nobody").is_none());
+        assert!(users.get_group_by_gid(65534).is_none());
+    }
+
+    #[test]
+    fn prefer_files_falls_back_to_libc() {
+        let mut users = prefer_fixtures("no such passwd file", "no such group file");
+        let uid = users.get_current_uid();
+        let gid = users.get_current_gid();
+        assert_eq!(users.get_user_by_uid(uid).map(|u| u.uid), Some(uid));
+        assert_eq!(users.get_group_by_gid(gid).map(|g| g.gid), Some(gid));
+    }
+
+    #[test]
+    fn prefer_files_falls_back_separately() {
+        let mut users = prefer_fixtures("passwd", "no such group file");
+        assert_eq!(users.get_user_by_uid(1000).map(|u| u.name), Some("fred".to_string()));
+
+        let gid = users.get_current_gid();
+        assert_eq!(users.get_group_by_gid(gid).map(|g| g.gid), Some(gid));
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let path = env::temp_dir().join(format!("users-save-test-{}", ::std::process::id()));
+        let mut users = fixture();
+        users.add_user(User::builder(1002, "wilma").home_dir("/home/wilma").shell("/bin/bash").build());
+        users.save(&path).unwrap();
+
+        let mut reloaded = PasswdFile::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let names: Vec<&str> = reloaded.users.iter().map(|u| &u.name[..]).collect();
+        assert_eq!(names, vec![ "root", "daemon", "fred", "sue", "barney", "wilma" ]);
+        assert_eq!(reloaded.get_user_by_name("wilma").map(|u| u.uid), Some(1002));
+        assert_eq!(reloaded.get_user_by_name("sue").map(|u| u.full_name), Some("Sue".to_string()));
+    }
+
+    #[test]
+    fn save_keeps_lines_exactly() {
+        let dir = env::temp_dir().join(format!("users-save-exact-test-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let original = dir.join("passwd");
+        let saved = dir.join("saved");
+        let contents = b"fr\xe9d:x:1000:100:Fr\xe9d,,,:/home/fr\xe9d:/bin/zsh  \nsue:x:1001:100::/home/sue:/bin/sh\n";
+        fs::write(&original, &contents[..]).unwrap();
+
+        PasswdFile::open(&original).unwrap().save(&saved).unwrap();
+        let written = fs::read(&saved).unwrap();
+        let leftovers = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written, &contents[..]);
+        assert_eq!(leftovers, 2);
+    }
+
+    #[test]
+    fn save_refuses_injected_lines() {
+        let path = env::temp_dir().join(format!("users-save-inject-test-{}", ::std::process::id()));
+        let mut users = fixture();
+        users.add_user(User::builder(1002, "wilma").gecos("x\nroot::0:0::/:/bin/sh").build());
+        assert!(users.save(&path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn save_without_file_name() {
+        assert!(fixture().save("/").is_err());
+    }
+}
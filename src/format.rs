@@ -0,0 +1,352 @@
+//! Converting users and groups to and from lines of `/etc/passwd` and
+//! `/etc/group`.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::{User, Group, gecos_full_name};
+
+/// Formats a user as a line of `/etc/passwd`, without the newline:
+/// `name:passwd:uid:gid:gecos:home:shell`.
+///
+/// This is for showing to people: bytes that aren't valid UTF-8 are
+/// replaced, and fields aren't checked for colons or newlines. Use
+/// `User::write_to` to write a line to a file.
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}:{}:{}:{}:{}",
+               self.name, self.passwd, self.uid, self.primary_group,
+               self.gecos, self.home_dir.display(), self.shell.display())
+    }
+}
+
+impl User {
+    /// Write this user as a line of `/etc/passwd`, without the newline, in
+    /// the same format as `Display` but with the name, home directory, and
+    /// shell exactly as they are, bytes and all.
+    ///
+    /// Fails with an error of kind `InvalidData`, without writing anything,
+    /// if a field contains a colon or a newline, since the line would then
+    /// be read back as something else, or as more than one entry.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let name = field("name", self.name_os.as_bytes(), DELIMITERS)?;
+        let passwd = field("password", self.passwd.as_bytes(), DELIMITERS)?;
+        let gecos = field("GECOS", self.gecos.as_bytes(), DELIMITERS)?;
+        let home_dir = field("home directory", self.home_dir.as_os_str().as_bytes(), DELIMITERS)?;
+        let shell = field("shell", self.shell.as_os_str().as_bytes(), DELIMITERS)?;
+
+        let mut line = Vec::new();
+        line.extend_from_slice(name);
+        line.push(b':');
+        line.extend_from_slice(passwd);
+        line.extend_from_slice(format!(":{}:{}:", self.uid, self.primary_group).as_bytes());
+        line.extend_from_slice(gecos);
+        line.push(b':');
+        line.extend_from_slice(home_dir);
+        line.push(b':');
+        line.extend_from_slice(shell);
+        out.write_all(&line)
+    }
+}
+
+/// The bytes that can't appear in a field of a passwd or group line.
+const DELIMITERS: &[u8] = b":\n";
+
+/// The bytes that can't appear in the name of a group's member.
+const MEMBER_DELIMITERS: &[u8] = b":\n,";
+
+/// Return the given field's value if it doesn't contain any of the given
+/// delimiters, or an error saying which field it was otherwise.
+fn field<'a>(which: &str, value: &'a [u8], delimiters: &[u8]) -> io::Result<&'a [u8]> {
+    match value.iter().find(|b| delimiters.contains(b)) {
+        Some(&b) => {
+            let message = format!("{} field contains {:?}", which, b as char);
+            Err(io::Error::new(io::ErrorKind::InvalidData, message))
+        },
+        None => Ok(value),
+    }
+}
+
+/// The reason a line of `/etc/passwd` couldn't be parsed.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ParsePasswdError {
+
+    /// The line doesn't have exactly seven colon-separated fields; this is
+    /// how many it has.
+    WrongFieldCount(usize),
+
+    /// The name field is empty.
+    EmptyName,
+
+    /// The user ID field isn't a number.
+    InvalidUid(String),
+
+    /// The group ID field isn't a number.
+    InvalidGid(String),
+}
+
+impl fmt::Display for ParsePasswdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParsePasswdError::WrongFieldCount(n) if n < 7 => write!(f, "passwd line has too few fields ({}, expected 7)", n),
+            ParsePasswdError::WrongFieldCount(n)          => write!(f, "passwd line has too many fields ({}, expected 7)", n),
+            ParsePasswdError::EmptyName                   => write!(f, "passwd line has an empty name"),
+            ParsePasswdError::InvalidUid(ref uid)         => write!(f, "passwd line has an invalid user ID {:?}", uid),
+            ParsePasswdError::InvalidGid(ref gid)         => write!(f, "passwd line has an invalid group ID {:?}", gid),
+        }
+    }
+}
+
+impl Error for ParsePasswdError {}
+
+/// Parses a line of `/etc/passwd`, without the newline.
+impl FromStr for User {
+    type Err = ParsePasswdError;
+
+    fn from_str(line: &str) -> Result<User, ParsePasswdError> {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() != 7 {
+            return Err(ParsePasswdError::WrongFieldCount(fields.len()));
+        }
+        else if fields[0].is_empty() {
+            return Err(ParsePasswdError::EmptyName);
+        }
+
+        let uid = fields[2].parse().map_err(|_| ParsePasswdError::InvalidUid(fields[2].to_owned()))?;
+        let gid = fields[3].parse().map_err(|_| ParsePasswdError::InvalidGid(fields[3].to_owned()))?;
+
+        Ok(User {
+            uid,
+            name: fields[0].to_owned(),
+            name_os: OsString::from(fields[0]),
+            primary_group: gid,
+            home_dir: PathBuf::from(fields[5]),
+            shell: PathBuf::from(fields[6]),
+            full_name: gecos_full_name(fields[4]),
+            gecos: fields[4].to_owned(),
+            passwd: fields[1].to_owned(),
+            raw_line: None,
+        })
+    }
+}
+
+/// Formats a group as a line of `/etc/group`, without the newline:
+/// `name:passwd:gid:member1,member2`.
+///
+/// As with users, this is for showing to people. Use `Group::write_to` to
+/// write a line to a file.
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}:{}", self.name, self.passwd, self.gid, self.members.join(","))
+    }
+}
+
+impl Group {
+    /// Write this group as a line of `/etc/group`, without the newline, in
+    /// the same format as `Display` but with the name exactly as it is.
+    ///
+    /// Fails with an error of kind `InvalidData`, without writing anything,
+    /// if a field contains a colon or a newline, or a member's name
+    /// contains a comma.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let name = field("name", self.name_os.as_bytes(), DELIMITERS)?;
+        let passwd = field("password", self.passwd.as_bytes(), DELIMITERS)?;
+
+        let mut line = Vec::new();
+        line.extend_from_slice(name);
+        line.push(b':');
+        line.extend_from_slice(passwd);
+        line.extend_from_slice(format!(":{}:", self.gid).as_bytes());
+        for (i, member) in self.members.iter().enumerate() {
+            if i > 0 {
+                line.push(b',');
+            }
+            line.extend_from_slice(field("member", member.as_bytes(), MEMBER_DELIMITERS)?);
+        }
+        out.write_all(&line)
+    }
+}
+
+/// The reason a line of `/etc/group` couldn't be parsed.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ParseGroupError {
+
+    /// The line doesn't have exactly four colon-separated fields; this is
+    /// how many it has.
+    WrongFieldCount(usize),
+
+    /// The name field is empty.
+    EmptyName,
+
+    /// The group ID field isn't a number.
+    InvalidGid(String),
+}
+
+impl fmt::Display for ParseGroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseGroupError::WrongFieldCount(n) if n < 4 => write!(f, "group line has too few fields ({}, expected 4)", n),
+            ParseGroupError::WrongFieldCount(n)          => write!(f, "group line has too many fields ({}, expected 4)", n),
+            ParseGroupError::EmptyName                   => write!(f, "group line has an empty name"),
+            ParseGroupError::InvalidGid(ref gid)         => write!(f, "group line has an invalid group ID {:?}", gid),
+        }
+    }
+}
+
+impl Error for ParseGroupError {}
+
+/// Parses a line of `/etc/group`, without the newline.
+impl FromStr for Group {
+    type Err = ParseGroupError;
+
+    fn from_str(line: &str) -> Result<Group, ParseGroupError> {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() != 4 {
+            return Err(ParseGroupError::WrongFieldCount(fields.len()));
+        }
+        else if fields[0].is_empty() {
+            return Err(ParseGroupError::EmptyName);
+        }
+
+        let gid = fields[2].parse().map_err(|_| ParseGroupError::InvalidGid(fields[2].to_owned()))?;
+        let members = fields[3].split(',').filter(|m| !m.is_empty()).map(|m| m.to_owned()).collect();
+
+        Ok(Group {
+            gid,
+            name: fields[0].to_owned(),
+            name_os: OsString::from(fields[0]),
+            members,
+            passwd: fields[1].to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ParsePasswdError, ParseGroupError};
+    use super::super::{User, Group};
+    use std::path::Path;
+
+    #[test]
+    fn display() {
+        let user = User::builder(1000, "fred").primary_group(100).home_dir("/home/fred").shell("/bin/zsh").gecos("Fred,,,").build();
+        assert_eq!(user.to_string(), "fred:x:1000:100:Fred,,,:/home/fred:/bin/zsh");
+    }
+
+    #[test]
+    fn write_to() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut user = User::builder(1000, "fred").primary_group(100).home_dir("/home/fred").shell("/bin/zsh").build();
+        user.name_os = OsStr::from_bytes(b"fr\xe9d").to_os_string();
+        let mut line = Vec::new();
+        user.write_to(&mut line).unwrap();
+        assert_eq!(line, b"fr\xe9d:x:1000:100::/home/fred:/bin/zsh");
+    }
+
+    #[test]
+    fn write_to_refuses_delimiters() {
+        use std::io::ErrorKind;
+
+        let user = User::builder(1000, "fred").gecos("x\nroot::0:0::/:/bin/sh").build();
+        let mut line = Vec::new();
+        assert_eq!(user.write_to(&mut line).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(line.is_empty());
+
+        let user = User::builder(1000, "fred").home_dir("/home/a:b").build();
+        assert!(user.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let line = "sue:!:1001:1001:Sue,Room 2::/bin/bash";
+        let user: User = line.parse().unwrap();
+        assert_eq!(user.name_os, "sue");
+        assert_eq!(user.uid, 1001);
+        assert_eq!(user.full_name, "Sue");
+        assert_eq!(user.home_dir, Path::new(""));
+        assert!(user.is_locked());
+        assert_eq!(user.to_string(), line);
+    }
+
+    #[test]
+    fn wrong_field_count() {
+        assert_eq!("fred:x:1000:100".parse::<User>().err(), Some(ParsePasswdError::WrongFieldCount(4)));
+        let error = "fred:x:1000:100::/home/fred:/bin/sh:extra".parse::<User>().err().unwrap();
+        assert_eq!(error.to_string(), "passwd line has too many fields (8, expected 7)");
+    }
+
+    #[test]
+    fn invalid_ids() {
+        assert_eq!("fred:x:one:100:::".parse::<User>().err(), Some(ParsePasswdError::InvalidUid("one".to_string())));
+        assert_eq!("fred:x:1000:-1:::".parse::<User>().err(), Some(ParsePasswdError::InvalidGid("-1".to_string())));
+        assert_eq!(":x:1000:100:::".parse::<User>().err(), Some(ParsePasswdError::EmptyName));
+    }
+
+    #[test]
+    fn largest_ids() {
+        let line = "nobody:x:4294967294:4294967295:::";
+        let user: User = line.parse().unwrap();
+        assert_eq!((user.uid, user.primary_group), (4294967294, 0xFFFFFFFF));
+        assert_eq!(user.to_string(), line);
+
+        assert_eq!("fred:x:4294967296:100:::".parse::<User>().err(), Some(ParsePasswdError::InvalidUid("4294967296".to_string())));
+        assert_eq!("fred:x:-2:100:::".parse::<User>().err(), Some(ParsePasswdError::InvalidUid("-2".to_string())));
+        assert_eq!("nogroup:x:4294967294:".parse::<Group>().map(|g| g.gid), Ok(4294967294));
+    }
+
+    #[test]
+    fn group_display() {
+        let group = Group::builder(10, "wheel").member("fred").member("sue").build();
+        assert_eq!(group.to_string(), "wheel:x:10:fred,sue");
+    }
+
+    #[test]
+    fn group_write_to() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut group = Group::builder(10, "wheel").member("fred").member("sue").build();
+        group.name_os = OsStr::from_bytes(b"wh\xeeel").to_os_string();
+        let mut line = Vec::new();
+        group.write_to(&mut line).unwrap();
+        assert_eq!(line, b"wh\xeeel:x:10:fred,sue");
+
+        let group = Group::builder(10, "wheel").member("fred,root").build();
+        assert!(group.write_to(&mut Vec::new()).is_err());
+        let group = Group::builder(10, "wheel").passwd("x\nroot").build();
+        assert!(group.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn group_without_members() {
+        let group: Group = "audio:x:29:".parse().unwrap();
+        assert_eq!(group.gid, 29);
+        assert!(group.members.is_empty());
+        assert_eq!(group.to_string(), "audio:x:29:");
+    }
+
+    #[test]
+    fn group_with_three_members() {
+        let line = "users::100:fred,barney,sue";
+        let group: Group = line.parse().unwrap();
+        assert_eq!(group.passwd, "");
+        assert_eq!(group.members, vec![ "fred".to_string(), "barney".to_string(), "sue".to_string() ]);
+        assert_eq!(group.to_string(), line);
+    }
+
+    #[test]
+    fn malformed_groups() {
+        assert_eq!("wheel:x:10".parse::<Group>().err(), Some(ParseGroupError::WrongFieldCount(3)));
+        assert_eq!("wheel:x:ten:".parse::<Group>().err(), Some(ParseGroupError::InvalidGid("ten".to_string())));
+        assert_eq!(":x:10:".parse::<Group>().err(), Some(ParseGroupError::EmptyName));
+        let error = "wheel:x:10:fred:sue".parse::<Group>().err().unwrap();
+        assert_eq!(error.to_string(), "group line has too many fields (5, expected 4)");
+    }
+}
@@ -0,0 +1,141 @@
+//! Checking that a users and groups database is consistent with itself.
+//!
+//! Hand-edited passwd and group files can end up with users whose primary
+//! group doesn't exist, groups that list users who've since been deleted,
+//! or two entries with the same ID. Nothing stops these from being read,
+//! but they're almost always mistakes.
+
+use std::collections::{BTreeMap, HashSet};
+
+use libc::{uid_t, gid_t};
+
+use super::{User, Group};
+
+/// Something wrong with a users and groups database, as found by
+/// `Users::validate_integrity`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum IntegrityIssue {
+
+    /// A user's primary group isn't in the groups database.
+    DanglingPrimaryGroup {
+
+        /// The user's name
+        username: String,
+
+        /// The user's ID
+        uid: uid_t,
+
+        /// The ID of the missing group
+        gid: gid_t,
+    },
+
+    /// A group lists a member who isn't in the users database.
+    UnknownMember {
+
+        /// The group's name
+        group_name: String,
+
+        /// The group's ID
+        gid: gid_t,
+
+        /// The name of the missing member
+        member: String,
+    },
+
+    /// More than one user has the same user ID.
+    DuplicateUid {
+
+        /// The shared ID
+        uid: uid_t,
+
+        /// The names of the users with it, in the order they were found
+        usernames: Vec<String>,
+    },
+
+    /// More than one group has the same group ID.
+    DuplicateGid {
+
+        /// The shared ID
+        gid: gid_t,
+
+        /// The names of the groups with it, in the order they were found
+        group_names: Vec<String>,
+    },
+}
+
+/// Check the given users and groups against each other. Users with dangling
+/// primary groups come first, in the order given, then groups with unknown
+/// members, then duplicate user IDs and duplicate group IDs, each in order
+/// of ID.
+pub fn check_integrity(users: &[User], groups: &[Group]) -> Vec<IntegrityIssue> {
+    let mut issues = Vec::new();
+
+    let gids: HashSet<gid_t> = groups.iter().map(|g| g.gid).collect();
+    for user in users.iter().filter(|u| !gids.contains(&u.primary_group)) {
+        issues.push(IntegrityIssue::DanglingPrimaryGroup {
+            username: user.name.clone(),
+            uid: user.uid,
+            gid: user.primary_group,
+        });
+    }
+
+    let usernames: HashSet<&str> = users.iter().map(|u| &u.name[..]).collect();
+    for group in groups {
+        for member in group.members.iter().filter(|m| !usernames.contains(&m[..])) {
+            issues.push(IntegrityIssue::UnknownMember {
+                group_name: group.name.clone(),
+                gid: group.gid,
+                member: member.clone(),
+            });
+        }
+    }
+
+    let mut by_uid: BTreeMap<uid_t, Vec<String>> = BTreeMap::new();
+    for user in users {
+        by_uid.entry(user.uid).or_default().push(user.name.clone());
+    }
+    for (uid, usernames) in by_uid.into_iter().filter(|(_, names)| names.len() > 1) {
+        issues.push(IntegrityIssue::DuplicateUid { uid, usernames });
+    }
+
+    let mut by_gid: BTreeMap<gid_t, Vec<String>> = BTreeMap::new();
+    for group in groups {
+        by_gid.entry(group.gid).or_default().push(group.name.clone());
+    }
+    for (gid, group_names) in by_gid.into_iter().filter(|(_, names)| names.len() > 1) {
+        issues.push(IntegrityIssue::DuplicateGid { gid, group_names });
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_integrity, IntegrityIssue};
+    use super::super::{User, Group};
+
+    #[test]
+    fn consistent() {
+        let users = vec![ User::builder(1000, "fred").primary_group(100).build() ];
+        let groups = vec![ Group::builder(100, "users").member("fred").build() ];
+        assert!(check_integrity(&users, &groups).is_empty());
+    }
+
+    #[test]
+    fn every_kind_of_issue() {
+        let users = vec![
+            User::builder(1000, "fred").primary_group(100).build(),
+            User::builder(1001, "sue").primary_group(200).build(),
+        ];
+        let groups = vec![
+            Group::builder(100, "users").member("fred").member("bob").build(),
+            Group::builder(100, "staff").build(),
+        ];
+
+        assert_eq!(check_integrity(&users, &groups), vec![
+            IntegrityIssue::DanglingPrimaryGroup { username: "sue".to_string(), uid: 1001, gid: 200 },
+            IntegrityIssue::UnknownMember { group_name: "users".to_string(), gid: 100, member: "bob".to_string() },
+            IntegrityIssue::DuplicateGid { gid: 100, group_names: vec![ "users".to_string(), "staff".to_string() ] },
+        ]);
+    }
+}
@@ -0,0 +1,111 @@
+//! When users last logged in, from the `lastlog` file on Linux.
+//!
+//! The file holds one fixed-size record per user ID, at an offset of the
+//! user ID times the record size, and is written by `login` and `sshd`. It's
+//! sparse, so a user who has never logged in has a record of zeroes, or none
+//! at all if their ID is past the end of the file.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc::uid_t;
+
+/// The file that `lastlog` records are normally kept in.
+pub const LASTLOG_PATH: &str = "/var/log/lastlog";
+
+// struct lastlog { int32_t ll_time; char ll_line[32]; char ll_host[256]; }
+const LINE_SIZE: usize = 32;
+const HOST_SIZE: usize = 256;
+const RECORD_SIZE: usize = 4 + LINE_SIZE + HOST_SIZE;
+
+/// The last time a user logged in.
+#[derive(PartialEq, Debug, Clone)]
+pub struct LastLogin {
+
+    /// When they logged in
+    pub time: SystemTime,
+
+    /// The terminal they logged in on, such as `pts/0`
+    pub tty: String,
+
+    /// The host they logged in from, which is empty for a local login
+    pub host: String,
+}
+
+/// Return when the user with the given ID last logged in, according to the
+/// system's `lastlog` file, or None if they never have or the file can't
+/// be read.
+pub fn get_last_login(uid: uid_t) -> Option<LastLogin> {
+    read_last_login(LASTLOG_PATH, uid).unwrap_or(None)
+}
+
+/// Return when the user with the given ID last logged in, according to the
+/// `lastlog`-format file at the given path, `Ok(None)` if they never have,
+/// or the error if the file couldn't be read.
+pub fn read_last_login<P: AsRef<Path>>(path: P, uid: uid_t) -> io::Result<Option<LastLogin>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(uid as u64 * RECORD_SIZE as u64))?;
+
+    let mut record = [0; RECORD_SIZE];
+    match file.read_exact(&mut record) {
+        Ok(()) => Ok(parse_record(&record)),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn parse_record(record: &[u8; RECORD_SIZE]) -> Option<LastLogin> {
+    let time = i32::from_ne_bytes([ record[0], record[1], record[2], record[3] ]);
+    if time == 0 {
+        return None;
+    }
+
+    Some(LastLogin {
+        time: UNIX_EPOCH + Duration::from_secs(time as u32 as u64),
+        tty:  c_field(&record[4 .. 4 + LINE_SIZE]),
+        host: c_field(&record[4 + LINE_SIZE ..]),
+    })
+}
+
+/// Read a fixed-size field that holds a string padded with '\0'.
+fn c_field(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[.. end]).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_last_login;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/lastlog");
+
+    #[test]
+    fn remote_login() {
+        let login = read_last_login(FIXTURE, 1).unwrap().unwrap();
+        assert_eq!(login.time, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert_eq!(login.tty, "pts/0");
+        assert_eq!(login.host, "example.com");
+    }
+
+    #[test]
+    fn local_login() {
+        let login = read_last_login(FIXTURE, 3).unwrap().unwrap();
+        assert_eq!(login.tty, "tty1");
+        assert_eq!(login.host, "");
+    }
+
+    #[test]
+    fn never_logged_in() {
+        assert!(read_last_login(FIXTURE, 0).unwrap().is_none());
+        assert!(read_last_login(FIXTURE, 2).unwrap().is_none());
+        assert!(read_last_login(FIXTURE, 1000).unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(read_last_login("/no/such/lastlog", 0).is_err());
+    }
+}
@@ -28,6 +28,10 @@
 //! - **uid:** The user's ID
 //! - **name:** The user's name
 //! - **primary_group:** The ID of this user's primary group
+//! - **full_name:** The user's real name, taken from the GECOS field
+//!
+//! The `gecos` method splits the whole GECOS field into the full name, room,
+//! work and home phone numbers, and anything else.
 //!
 //! Here is a complete example that prints out the current user's name:
 //!
@@ -44,7 +48,17 @@
 //! never existed. So always check the return values from `user_to_uid`!
 //!
 //! There is also a `get_current_username` function, as it's such a common
-//! operation that it deserves special treatment.
+//! operation that it deserves special treatment:
+//!
+//! ```rust
+//! let name = users::get_current_username().unwrap_or_default();
+//! println!("I am {}", name);
+//! ```
+//!
+//! These free functions return the same values as the `Users` methods of the
+//! same names. Each call creates a cache, makes one lookup, and throws the
+//! cache away, so nothing is remembered from one call to the next; use an
+//! `OSUsers` object, described below, to avoid repeating lookups.
 //!
 //! Caching
 //! -------
@@ -70,10 +84,10 @@
 //! println!("Hello again, {}!", user.name);
 //! ```
 //!
-//! This cache is **only additive**: it's not possible to drop it, or erase
-//! selected entries, as when the database may have been modified, it's best to
-//! start entirely afresh. So to accomplish this, just start using a new
-//! `OSUsers` object.
+//! Entries stay in the cache until they're removed. When the database may
+//! have been modified, call `flush` to start entirely afresh, or `forget_user`
+//! and `forget_group` to drop selected entries; the next lookup of each one
+//! queries the database again.
 //!
 //! Groups
 //! ------
@@ -110,33 +124,264 @@
 //!
 //! Use the mocking module to create custom tables to test your code for these
 //! edge cases.
+//!
+//! This crate only supports Unix. Windows identifies accounts by SIDs rather
+//! than numeric IDs, and has no users database that `getpwuid` could read,
+//! so cross-platform programs should only depend on it for Unix targets.
+
+#[cfg(not(unix))]
+compile_error!("the users crate only supports Unix; depend on it under [target.'cfg(unix)'.dependencies]");
 
 extern crate libc;
+#[cfg(feature = "async")]
+extern crate tokio;
 pub use libc::{uid_t, gid_t, c_int};
+use libc::{mode_t, size_t, sysconf, EINVAL, ENOENT, ERANGE, _SC_GETPW_R_SIZE_MAX, _SC_GETGR_R_SIZE_MAX};
+
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly"))]
 use libc::{c_char, time_t};
 #[cfg(target_os = "linux")]
 use libc::c_char;
 
-use std::borrow::ToOwned;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
-use std::ffi::{CStr, CString};
-use std::io;
-use std::ptr::read;
-use std::str::from_utf8_unchecked;
+use std::borrow::{Cow, ToOwned};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::num::ParseIntError;
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::ptr::{self, read};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 pub mod mock;
 
+mod builder;
+pub use builder::{UserBuilder, GroupBuilder, HomePolicy, ShellPolicy};
+
+mod format;
+pub use format::{ParsePasswdError, ParseGroupError};
+
+mod names;
+pub use names::{NameError, MAX_NAME_LENGTH, validate_username, validate_groupname, is_valid_username, is_valid_groupname};
+
+mod error;
+pub use error::Error;
+
+mod shared;
+pub use shared::SharedUsers;
+
+mod source;
+pub use source::{PasswdSource, LibcSource, TimeoutSource};
+
+mod diff;
+pub use diff::{UserDiff, UserChange, GroupDiff, GroupChange, FieldChange, diff_users, diff_groups};
+
+mod integrity;
+pub use integrity::{IntegrityIssue, check_integrity};
+
+#[cfg(feature = "files")]
+mod files;
+#[cfg(feature = "files")]
+pub use files::{PasswdFile, GroupFile, PreferFiles};
+
+#[cfg(all(feature = "shadow", target_os = "linux"))]
+pub mod shadow;
+
+#[cfg(all(feature = "lastlog", target_os = "linux"))]
+pub mod lastlog;
+
+pub mod subid;
+pub use subid::IdRange;
+
+#[cfg(all(feature = "watch", target_os = "linux"))]
+mod watch;
+
+#[cfg(feature = "async")]
+mod async_users;
+#[cfg(feature = "async")]
+pub use async_users::{AsyncUsers, Lookup};
+
 
 /// The trait for the `OSUsers` object.
 pub trait Users {
 
     /// Return a User object if one exists for the given user ID; otherwise, return None.
-    fn get_user_by_uid(&mut self, uid: uid_t) -> Option<User>;
+    ///
+    /// When more than one user has the ID, this is the first of them, which
+    /// is usually the canonical one (such as `root` rather than `toor`).
+    fn get_user_by_uid(&mut self, uid: uid_t) -> Option<User> {
+        self.get_user_by_uid_rc(uid).map(|u| (*u).clone())
+    }
+
+    /// Parse the given string as a user ID, such as one given on the command
+    /// line, and return the User object with that ID. Return `Ok(None)` if
+    /// there's no such user, and the parse error if the string isn't a user
+    /// ID at all.
+    fn get_user_by_uid_str(&mut self, uid: &str) -> Result<Option<User>, ParseIntError> {
+        let uid = uid.parse()?;
+        Ok(self.get_user_by_uid(uid))
+    }
+
+    /// Return every user with the given user ID, in the order that they
+    /// appear in the users database. This is empty if there are none.
+    ///
+    /// The default implementation only returns the one from
+    /// `get_user_by_uid`.
+    fn get_all_users_by_uid(&mut self, uid: uid_t) -> Vec<User> {
+        self.get_user_by_uid(uid).into_iter().collect()
+    }
+
+    /// Look up each of the given user IDs, returning the results in the same
+    /// order. Each distinct ID is only looked up once, however many times it
+    /// appears, which suits tools such as `ls -l` that print the owners of
+    /// many files.
+    fn get_users_by_uids(&mut self, uids: &[uid_t]) -> Vec<Option<User>> {
+        let mut found = HashMap::new();
+        for &uid in uids {
+            found.entry(uid).or_insert_with(|| self.get_user_by_uid(uid));
+        }
+
+        uids.iter().map(|uid| found[uid].clone()).collect()
+    }
+
+    /// Return the given user IDs that don't belong to any user, each only
+    /// once, in the order that they first appear. Given the owners of the
+    /// files on a filesystem, these are the users that have been deleted
+    /// without their files.
+    fn filter_orphan_uids(&mut self, uids: &[uid_t]) -> Vec<uid_t> {
+        let mut orphans = Vec::new();
+        for (&uid, user) in uids.iter().zip(self.get_users_by_uids(uids)) {
+            if user.is_none() && !orphans.contains(&uid) {
+                orphans.push(uid);
+            }
+        }
+
+        orphans
+    }
+
+    /// Return the name of the user with the given ID, or the ID itself as
+    /// a decimal string if there's no such user, as `ls -l` displays file
+    /// owners.
+    fn username_or_uid(&mut self, uid: uid_t) -> String {
+        self.get_user_by_uid_rc(uid).map_or_else(|| uid.to_string(), |u| u.name.clone())
+    }
 
     /// Return a User object if one exists for the given username; otherwise, return None.
-    fn get_user_by_name(&mut self, username: &str) -> Option<User>;
+    fn get_user_by_name(&mut self, username: &str) -> Option<User> {
+        self.get_user_by_name_rc(username).map(|u| (*u).clone())
+    }
+
+    /// Return the user ID and primary group ID of the user with the given
+    /// name if one exists; otherwise, return None. Unlike
+    /// `get_user_by_name`, this doesn't copy the user's other details.
+    fn get_user_id_by_name(&mut self, username: &str) -> Option<UserId> {
+        self.get_user_by_name_rc(username).map(|u| UserId::from(&*u))
+    }
+
+    /// Return a User object if one exists whose name matches the given one
+    /// when ASCII case is ignored; otherwise, return None. An exact match is
+    /// preferred over any others.
+    ///
+    /// This is for systems that treat usernames case-insensitively, such as
+    /// ones backed by Active Directory. POSIX usernames are case-sensitive,
+    /// so `get_user_by_name` doesn't do this. Unless there's an exact match,
+    /// this has to look at every user.
+    ///
+    /// The default implementation checks the users in order of user ID, and
+    /// looks at every user that shares an ID through `get_all_users_by_uid`,
+    /// so the same user wins each time however the snapshot is ordered.
+    fn get_user_by_name_ci(&mut self, username: &str) -> Option<User> {
+        self.get_user_by_name(username).or_else(|| {
+            let mut uids: Vec<uid_t> = self.snapshot_users().into_keys().collect();
+            uids.sort_unstable();
+
+            uids.into_iter()
+                .flat_map(|uid| self.get_all_users_by_uid(uid))
+                .find(|u| u.name.eq_ignore_ascii_case(username))
+        })
+    }
+
+    /// Return a shared User object if one exists for the given user ID;
+    /// otherwise, return None. This avoids cloning the user each time it's
+    /// looked up.
+    fn get_user_by_uid_rc(&mut self, uid: uid_t) -> Option<Rc<User>>;
+
+    /// Return a shared User object if one exists for the given username;
+    /// otherwise, return None. This avoids cloning the user each time it's
+    /// looked up.
+    fn get_user_by_name_rc(&mut self, username: &str) -> Option<Rc<User>>;
+
+    /// Return a User object if one exists for the given username, which
+    /// doesn't have to be valid UTF-8; otherwise, return None.
+    fn get_user_by_name_os(&mut self, username: &OsStr) -> Option<User> {
+        username.to_str().and_then(|name| self.get_user_by_name(name))
+    }
+
+    /// Return the home directory of the user with the given name if they
+    /// exist; otherwise, return None.
+    fn get_home_dir(&mut self, username: &str) -> Option<PathBuf> {
+        self.get_user_by_name_rc(username).map(|u| u.home_dir.clone())
+    }
+
+    /// Return the shell of the user with the given name if they exist;
+    /// otherwise, return None.
+    fn get_shell(&mut self, username: &str) -> Option<PathBuf> {
+        self.get_user_by_name_rc(username).map(|u| u.shell.clone())
+    }
+
+    /// Expand a leading `~user` in the given path to that user's home
+    /// directory, or a leading `~` on its own to the current user's, as
+    /// shells do. A path that doesn't start with `~` is returned unchanged.
+    /// Return None if the user doesn't exist.
+    ///
+    /// The current user's home directory comes from the users database, not
+    /// from the `HOME` environment variable.
+    fn expand_tilde(&mut self, path: &str) -> Option<PathBuf> {
+        let rest = match path.strip_prefix('~') {
+            Some(rest) => rest,
+            None => return Some(PathBuf::from(path)),
+        };
+
+        let (username, rest) = match rest.find('/') {
+            Some(index) => (&rest[.. index], &rest[index + 1 ..]),
+            None => (rest, ""),
+        };
+
+        let home = if username.is_empty() { self.get_current_user()?.home_dir }
+                   else { self.get_home_dir(username)? };
+
+        Some(if rest.is_empty() { home } else { home.join(rest) })
+    }
+
+    /// Return the user named by a command-line argument such as the one
+    /// `chown` takes, which can be either a user ID or a username; otherwise,
+    /// return None.
+    ///
+    /// If the argument is a number, it's looked up as a user ID first, so a
+    /// user whose name is all digits can only be found this way if no user
+    /// has that ID. Anything that isn't a number, or is a number no user
+    /// has as their ID, is looked up as a username.
+    fn resolve_user(&mut self, spec: &str) -> Option<User> {
+        spec.parse().ok()
+            .and_then(|uid| self.get_user_by_uid(uid))
+            .or_else(|| self.get_user_by_name(spec))
+    }
 
     /// Return a Group object if one exists for the given group ID; otherwise, return None.
     fn get_group_by_gid(&mut self, gid: gid_t) -> Option<Group>;
@@ -144,29 +389,704 @@ pub trait Users {
     /// Return a Group object if one exists for the given groupname; otherwise, return None.
     fn get_group_by_name(&mut self, group_name: &str) -> Option<Group>;
 
+    /// Look up each of the given group IDs, returning the results in the same
+    /// order. Each distinct ID is only looked up once, as with
+    /// `get_users_by_uids`.
+    fn get_groups_by_gids(&mut self, gids: &[gid_t]) -> Vec<Option<Group>> {
+        let mut found = HashMap::new();
+        for &gid in gids {
+            found.entry(gid).or_insert_with(|| self.get_group_by_gid(gid));
+        }
+
+        gids.iter().map(|gid| found[gid].clone()).collect()
+    }
+
+    /// Return a Group object if one exists for the given groupname, which
+    /// doesn't have to be valid UTF-8; otherwise, return None.
+    fn get_group_by_name_os(&mut self, group_name: &OsStr) -> Option<Group> {
+        group_name.to_str().and_then(|name| self.get_group_by_name(name))
+    }
+
+    /// Return the group named by a command-line argument such as the one
+    /// `chgrp` takes, which can be either a group ID or a group name;
+    /// otherwise, return None. Numbers are looked up as group IDs first, in
+    /// the same way as `resolve_user`.
+    fn resolve_group(&mut self, spec: &str) -> Option<Group> {
+        spec.parse().ok()
+            .and_then(|gid| self.get_group_by_gid(gid))
+            .or_else(|| self.get_group_by_name(spec))
+    }
+
+    /// Return the name of the group with the given ID, or the ID itself as
+    /// a decimal string if there's no such group.
+    fn groupname_or_gid(&mut self, gid: gid_t) -> String {
+        self.get_group_by_gid(gid).map_or_else(|| gid.to_string(), |g| g.name)
+    }
+
+    /// Return the given user's primary group if it exists; otherwise,
+    /// return None.
+    fn get_user_primary_group(&mut self, user: &User) -> Option<Group> {
+        self.get_group_by_gid(user.primary_group)
+    }
+
+    /// Return the name of the primary group of the user with the given
+    /// name, as printed in `user:group`. Return None if there's no such
+    /// user, or if their primary group ID has no group.
+    fn get_user_primary_group_name(&mut self, username: &str) -> Option<String> {
+        let user = self.get_user_by_name(username)?;
+        self.get_user_primary_group(&user).map(|g| g.name)
+    }
+
+    /// Return every group the given user belongs to, counting the group with
+    /// the given ID (usually their primary group) as well as their
+    /// supplementary groups. Each group appears only once. Return None if no
+    /// such user exists.
+    fn get_user_groups(&mut self, username: &str, gid: gid_t) -> Option<Vec<Group>>;
+
+    /// Return the IDs of every group the given user belongs to, including
+    /// the given primary group ID even if they aren't in any others, in the
+    /// order `getgrouplist` produces them. This is the list to pass to
+    /// `setgroups` when switching to that user.
+    fn supplementary_gids_for(&mut self, username: &str, primary_gid: gid_t) -> Vec<gid_t> {
+        let mut gids = vec![ primary_gid ];
+        for group in self.get_user_groups(username, primary_gid).unwrap_or_default() {
+            if !gids.contains(&group.gid) {
+                gids.push(group.gid);
+            }
+        }
+        gids
+    }
+
+    /// Return how many groups the given user belongs to, counting the group
+    /// with the given ID as well as their supplementary groups, which is
+    /// what's needed to size a list for `setgroups`. The given group is
+    /// counted even if no such user exists, as `getgrouplist` does.
+    fn count_user_groups(&mut self, username: &str, gid: gid_t) -> usize {
+        match self.get_user_groups(username, gid) {
+            Some(groups) => groups.len().max(1),
+            None => 1,
+        }
+    }
+
+    /// Return every user in the users database, keyed by their user ID. When
+    /// more than one user has the same ID, the first one is kept.
+    ///
+    /// For `OSUsers`, this walks the whole users database, which can be
+    /// expensive when it's backed by a network service such as LDAP.
+    fn snapshot_users(&mut self) -> HashMap<uid_t, User>;
+
+    /// Return every user whose ID falls in the given range, such as
+    /// `uid_min()..uid_max() + 1` for the accounts that belong to people
+    /// rather than to system services.
+    ///
+    /// Like `snapshot_users`, this has to look at every user.
+    fn users_in_range(&mut self, range: Range<uid_t>) -> Vec<User> {
+        let mut users: Vec<User> = self.snapshot_users().into_values()
+            .filter(|u| range.contains(&u.uid))
+            .collect();
+        users.sort_by_key(|u| u.uid);
+        users
+    }
+
+    /// Return every user whose shell is exactly the given path, in order of
+    /// user ID, or for `OSUsers`, in the order of the users database, with
+    /// any duplicate IDs included. No symlinks are followed, so `/bin/bash`
+    /// and `/usr/bin/bash` are different shells even where they're the same
+    /// file.
+    ///
+    /// Like `snapshot_users`, this has to look at every user.
+    fn users_with_shell(&mut self, shell: &Path) -> Vec<User> {
+        let mut users: Vec<User> = self.snapshot_users().into_values()
+            .filter(|u| u.shell == shell)
+            .collect();
+        users.sort_by_key(|u| u.uid);
+        users
+    }
+
+    /// Return every user whose shell is one that refuses logins, such as
+    /// `/usr/sbin/nologin` or `/bin/false`, in the same order as
+    /// `users_with_shell`. These are usually accounts for system services.
+    ///
+    /// Like `snapshot_users`, this has to look at every user.
+    fn users_without_login_shell(&mut self) -> Vec<User> {
+        let mut users: Vec<User> = self.snapshot_users().into_values()
+            .filter(|u| is_nologin_shell(&u.shell))
+            .collect();
+        users.sort_by_key(|u| u.uid);
+        users
+    }
+
+    /// Return the name of every user in the users database, such as for
+    /// completing usernames, without keeping any of their other details.
+    ///
+    /// The default implementation takes them from `snapshot_users`, in
+    /// order of user ID.
+    fn get_all_usernames(&mut self) -> Vec<String> {
+        let mut users: Vec<User> = self.snapshot_users().into_values().collect();
+        users.sort_by_key(|u| u.uid);
+        users.into_iter().map(|u| u.name).collect()
+    }
+
+    /// Return the name of every group in the groups database, without
+    /// keeping their member lists.
+    ///
+    /// The default implementation takes them from `snapshot_groups`, in
+    /// order of group ID.
+    fn get_all_group_names(&mut self) -> Vec<String> {
+        let mut groups: Vec<Group> = self.snapshot_groups().into_values().collect();
+        groups.sort_by_key(|g| g.gid);
+        groups.into_iter().map(|g| g.name).collect()
+    }
+
+    /// Check the users and groups databases against each other, returning
+    /// every user whose primary group doesn't exist, every group member who
+    /// isn't a user, and every user or group ID that's used more than once.
+    /// See `check_integrity` for the order they come in. This is empty if
+    /// nothing's wrong.
+    ///
+    /// This has to look at every user and group. The default
+    /// implementation takes them from `snapshot_users`, and
+    /// `get_all_users_by_uid` for users that share an ID, and from
+    /// `snapshot_groups`, so it can't find groups that share an ID.
+    fn validate_integrity(&mut self) -> Vec<IntegrityIssue> {
+        let mut uids: Vec<uid_t> = self.snapshot_users().into_keys().collect();
+        uids.sort_unstable();
+        let users: Vec<User> = uids.into_iter().flat_map(|uid| self.get_all_users_by_uid(uid)).collect();
+
+        let mut groups: Vec<Group> = self.snapshot_groups().into_values().collect();
+        groups.sort_by_key(|g| g.gid);
+        check_integrity(&users, &groups)
+    }
+
+    /// Write every user in the users database to the given writer as a
+    /// passwd-format file, in order of user ID, such as to save a copy of a
+    /// `MockUsers` table to open with `PasswdFile` later. Like
+    /// `snapshot_users`, when more than one user has the same ID, only the
+    /// first one is written.
+    ///
+    /// Each user is written with `User::write_to`, so names, home
+    /// directories, and shells keep their exact bytes, and writing stops
+    /// with an error at the first user with a field that contains a colon
+    /// or a newline.
+    fn write_passwd<W: Write>(&mut self, mut out: W) -> io::Result<()> where Self: Sized {
+        let mut users: Vec<User> = self.snapshot_users().into_values().collect();
+        users.sort_by_key(|u| u.uid);
+        for user in users {
+            user.write_to(&mut out)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+
+    /// Write every group in the groups database to the given writer as a
+    /// group-format file, in order of group ID. Each group is written with
+    /// `Group::write_to`, in the same way as `write_passwd`.
+    fn write_group<W: Write>(&mut self, mut out: W) -> io::Result<()> where Self: Sized {
+        let mut groups: Vec<Group> = self.snapshot_groups().into_values().collect();
+        groups.sort_by_key(|g| g.gid);
+        for group in groups {
+            group.write_to(&mut out)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+
+    /// Return every group in the groups database, keyed by their group ID.
+    /// When more than one group has the same ID, the first one is kept.
+    ///
+    /// For `OSUsers`, this walks the whole groups database, which can be
+    /// expensive when it's backed by a network service such as LDAP.
+    fn snapshot_groups(&mut self) -> HashMap<gid_t, Group>;
+
+    /// Return every user who belongs to the group with the given ID, either
+    /// because it's their primary group or because they're listed as one of
+    /// its members. Each user appears only once.
+    ///
+    /// `Group::members` only has the second kind, so finding the first kind
+    /// means looking at every user. For `OSUsers`, that's a scan of the whole
+    /// users database.
+    fn get_group_members(&mut self, gid: gid_t) -> Vec<User>;
+
+    /// Return the ID of every user who belongs to the group with the given
+    /// ID, in the same two ways as `get_group_members`: each name in its
+    /// member list, then each user whose primary group it is. Each ID
+    /// appears only once, and member names that don't belong to any user
+    /// are skipped. This is for passing to system calls that take user IDs.
+    ///
+    /// For `OSUsers`, each skipped name is reported to the trace hook as
+    /// an `UnresolvedMember` event.
+    fn get_group_member_uids(&mut self, gid: gid_t) -> Vec<uid_t> {
+        group_member_uids(self, gid, |_| {})
+    }
+
+    /// Return the users who are members of the named group, following any
+    /// member entries that name other groups down to the given depth, where
+    /// a depth of 0 only looks at the group's own member list. Each user is
+    /// only returned once, and a group that's reached again through a cycle
+    /// isn't expanded a second time.
+    ///
+    /// Nesting groups isn't something the system knows about: the kernel
+    /// and libc treat every member as a username. This is for setups that
+    /// follow the convention of listing one group inside another. Since
+    /// most users have a group with the same name as them, a member is only
+    /// treated as a group when no user has that name. Primary-group members
+    /// aren't included, as they aren't in the member list.
+    fn get_users_in_group_recursive(&mut self, group_name: &str, max_depth: usize) -> Vec<User> {
+        let mut users: Vec<User> = Vec::new();
+        let mut seen = vec![ group_name.to_owned() ];
+        let mut pending = match self.get_group_by_name(group_name) {
+            Some(group) => vec![ (group, 0) ],
+            None => return users,
+        };
+
+        while let Some((group, depth)) = pending.pop() {
+            for member in &group.members {
+                if let Some(user) = self.get_user_by_name(member) {
+                    if !users.iter().any(|u| u.uid == user.uid) {
+                        users.push(user);
+                    }
+                }
+                else if depth < max_depth && !seen.contains(member) {
+                    seen.push(member.clone());
+                    if let Some(nested) = self.get_group_by_name(member) {
+                        pending.push((nested, depth + 1));
+                    }
+                }
+            }
+        }
+
+        users
+    }
+
+    /// Return whether the given user belongs to the given group, either
+    /// because it's their primary group or because they're listed as one of
+    /// its members. Return false if either of them doesn't exist.
+    fn is_user_in_group(&mut self, username: &str, group_name: &str) -> bool {
+        let user = match self.get_user_by_name(username) {
+            Some(u) => u,
+            None => return false,
+        };
+
+        match self.get_group_by_name(group_name) {
+            Some(group) => group.gid == user.primary_group || group.members.contains(&user.name),
+            None => false,
+        }
+    }
+
+    /// Return the group that a file created in the given directory would
+    /// belong to. This is the effective group of the process, unless the
+    /// directory has its setgid bit set, in which case new files take the
+    /// directory's group instead.
+    ///
+    /// Fails if the directory can't be examined, or if the group it
+    /// determines isn't in the groups database.
+    fn effective_group_for_new_file(&mut self, parent_dir: &Path) -> io::Result<Group> {
+        let metadata = parent_dir.metadata()?;
+        let gid = if metadata.mode() & S_ISGID != 0 { metadata.gid() }
+                  else { self.get_effective_gid() };
+
+        self.get_group_by_gid(gid)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such group"))
+    }
+
+    /// Return a group that both of the given users belong to, whether as
+    /// their primary group or as a listed member, or None if they share
+    /// none or either of them doesn't exist. When they share several, this
+    /// is the first of the first user's groups, as `get_user_groups` orders
+    /// them.
+    fn users_share_group(&mut self, a: &str, b: &str) -> Option<Group> {
+        let user_a = self.get_user_by_name(a)?;
+        let user_b = self.get_user_by_name(b)?;
+        let groups_a = self.get_user_groups(a, user_a.primary_group)?;
+        let groups_b = self.get_user_groups(b, user_b.primary_group)?;
+
+        groups_a.into_iter().find(|g| groups_b.iter().any(|other| other.gid == g.gid))
+    }
+
+    /// Spawn the given command as the user with the given name, with their
+    /// user ID, their primary group, and their supplementary groups.
+    /// Requires root access, and fails with `NotFound` if there's no such
+    /// user.
+    ///
+    /// The child's groups are changed before its user ID, since that isn't
+    /// allowed afterwards. That rules out `CommandExt::uid` and `gid`: the
+    /// child applies them before it runs any `pre_exec` closure, so a
+    /// closure that only called `setgroups` would run once the user ID had
+    /// already been dropped, and fail. Instead, one closure changes all
+    /// three, in order.
+    ///
+    /// Don't call `uid` or `gid` on the command as well. They'd still take
+    /// effect first, and once the user ID isn't root, changing the groups
+    /// fails with `EPERM`, and so does the spawn.
+    fn spawn_as_user(&mut self, username: &str, command: &mut Command) -> io::Result<Child> {
+        let user = self.get_user_by_name(username)
+                       .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such user"))?;
+        let groups = self.supplementary_gids_for(username, user.primary_group);
+        let (uid, gid) = (user.uid, user.primary_group);
+
+        // Everything the child needs is set up here, as it shouldn't
+        // allocate between forking and exec-ing.
+        let switch = move || {
+            unsafe {
+                if setgroups(groups.len() as _, groups.as_ptr()) == -1
+                || setgid(gid) == -1
+                || setuid(uid) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        };
+
+        unsafe { command.pre_exec(switch) }.spawn()
+    }
+
+    /// Return whether the user with the given name may log in: they exist,
+    /// their account isn't locked, and their shell isn't one that refuses
+    /// logins. Return false if any of these doesn't hold.
+    ///
+    /// This is only a quick gate, and doesn't check their password.
+    fn is_login_allowed(&mut self, username: &str) -> bool {
+        match self.get_user_by_name(username) {
+            Some(user) => !user.is_locked() && user.can_login(),
+            None => false,
+        }
+    }
+
     /// Return the user ID for the user running the process.
     fn get_current_uid(&mut self) -> uid_t;
 
     /// Return the username of the user running the process.
     fn get_current_username(&mut self) -> Option<String>;
 
+    /// Return the User object for the user running the process, if they
+    /// exist in the users database.
+    fn get_current_user(&mut self) -> Option<User> {
+        let uid = self.get_current_uid();
+        self.get_user_by_uid(uid)
+    }
+
+    /// Return every group the user running the process belongs to, counting
+    /// the current group as well as their supplementary groups. This is
+    /// empty if the current user isn't in the users database.
+    fn get_current_user_groups(&mut self) -> Vec<Group> {
+        let gid = self.get_current_gid();
+        match self.get_current_username() {
+            Some(username) => self.get_user_groups(&username, gid).unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the real and effective IDs of the running process, the name
+    /// of its user, and its supplementary groups, all at once, as for
+    /// printing what `id` does.
+    fn whoami(&mut self) -> Identity {
+        let gids = self.current_process_groups();
+        Identity {
+            uid:      self.get_current_uid(),
+            gid:      self.get_current_gid(),
+            euid:     self.get_effective_uid(),
+            egid:     self.get_effective_gid(),
+            username: self.get_current_username(),
+            groups:   self.get_groups_by_gids(&gids).into_iter().flatten().collect(),
+        }
+    }
+
     /// Return the group ID for the user running the process.
     fn get_current_gid(&mut self) -> gid_t;
 
     /// Return the group name of the user running the process.
     fn get_current_groupname(&mut self) -> Option<String>;
 
-    /// Return the effective user id.
+    /// Return the effective user id, which differs from the current user ID
+    /// when running a setuid binary.
     fn get_effective_uid(&mut self) -> uid_t;
 
-    /// Return the effective group id.
+    /// Return the effective group id, which differs from the current group ID
+    /// when running a setgid binary.
     fn get_effective_gid(&mut self) -> gid_t;
 
-    /// Return the effective username.
+    /// Return the username of the effective user.
     fn get_effective_username(&mut self) -> Option<String>;
 
-    /// Return the effective group name.
+    /// Return the User object for the effective user, if they exist in the
+    /// users database.
+    fn get_effective_user(&mut self) -> Option<User> {
+        let uid = self.get_effective_uid();
+        self.get_user_by_uid(uid)
+    }
+
+    /// Return the group name of the effective group.
     fn get_effective_groupname(&mut self) -> Option<String>;
+
+    /// Return whether the process is running with root privileges, which
+    /// is the case when the effective user ID is 0.
+    fn is_root(&mut self) -> bool {
+        self.get_effective_uid() == 0
+    }
+
+    /// Return whether the process could switch to the user with the given
+    /// ID, which is the case when it's running as root, or as that user
+    /// already. This is what the kernel allows; it doesn't look at sudo's
+    /// or anything else's policy.
+    fn can_switch_to(&mut self, target_uid: uid_t) -> bool {
+        let euid = self.get_effective_uid();
+        euid == 0 || euid == target_uid
+    }
+
+    /// Return when the user with the given ID last logged in, from the
+    /// system's `lastlog` file, or None if they never have or it can't be
+    /// read.
+    #[cfg(all(feature = "lastlog", target_os = "linux"))]
+    fn get_last_login(&mut self, uid: uid_t) -> Option<lastlog::LastLogin> {
+        lastlog::get_last_login(uid)
+    }
+
+    /// Return the ranges of subordinate user IDs that the user with the
+    /// given name has been given in `/etc/subuid`, whether by name or by
+    /// ID. This is empty if they have none, or the file can't be read.
+    fn get_subuid_ranges(&mut self, username: &str) -> Vec<IdRange> {
+        match self.get_user_by_name(username) {
+            Some(user) => subid::read_subid_ranges(subid::SUBUID_PATH, username, user.uid).unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the ranges of subordinate group IDs that the user with the
+    /// given name has been given in `/etc/subgid`, whether by name or by
+    /// user ID. This is empty if they have none, or the file can't be read.
+    fn get_subgid_ranges(&mut self, username: &str) -> Vec<IdRange> {
+        match self.get_user_by_name(username) {
+            Some(user) => subid::read_subid_ranges(subid::SUBGID_PATH, username, user.uid).unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the user that daemons conventionally run as when they don't
+    /// need any privileges: the one named `nobody` if there is one, or the
+    /// one with the ID `NOBODY_UID` otherwise.
+    fn get_nobody_user(&mut self) -> Option<User> {
+        self.get_user_by_name("nobody").or_else(|| self.get_user_by_uid(NOBODY_UID))
+    }
+
+    /// Return the ID of the user returned by `get_nobody_user`, or
+    /// `NOBODY_UID` if there isn't one.
+    fn get_nobody_uid(&mut self) -> uid_t {
+        self.get_nobody_user().map_or(NOBODY_UID, |u| u.uid)
+    }
+
+    /// Return the group that goes with `get_nobody_user`: the one named
+    /// `nogroup`, as on Debian, or `nobody`, as on Red Hat, or the one with
+    /// the ID `NOGROUP_GID` if neither exists.
+    fn get_nogroup(&mut self) -> Option<Group> {
+        self.get_group_by_name("nogroup")
+            .or_else(|| self.get_group_by_name("nobody"))
+            .or_else(|| self.get_group_by_gid(NOGROUP_GID))
+    }
+
+    /// Return the ID of the group returned by `get_nogroup`, or
+    /// `NOGROUP_GID` if there isn't one.
+    fn get_nogroup_gid(&mut self) -> gid_t {
+        self.get_nogroup().map_or(NOGROUP_GID, |g| g.gid)
+    }
+
+    /// Return the IDs of the running process's supplementary groups, as
+    /// printed by `id -G`. Unlike `get_user_groups`, this is the list the
+    /// process actually has, which needn't match the groups database.
+    fn current_process_groups(&mut self) -> Vec<gid_t>;
+
+    /// Return the name of the user logged in on the process's controlling
+    /// terminal, as `getlogin` reports it, or None if there isn't one, such
+    /// as for a daemon.
+    ///
+    /// This is recorded when the user logs in, so it can differ from
+    /// `get_current_username`, which looks up the process's user ID: after
+    /// `su` or `sudo`, this is still the user who logged in, not the one
+    /// they switched to.
+    fn get_login_name(&mut self) -> Option<String>;
+
+    /// Return the ID of the user who originally logged in to start this
+    /// process, however many times they've used `su` or `sudo` since, for
+    /// recording in audit logs, or None if it can't be found out.
+    ///
+    /// On Linux, `OSUsers` reads this from `/proc/self/loginuid`, which the
+    /// kernel's audit system sets when a user logs in and which the process
+    /// can't change afterwards, unlike `SUDO_UID` and other environment
+    /// variables. Elsewhere, or when it's not set, such as for a daemon
+    /// started at boot, this falls back to looking up `get_login_name`,
+    /// which is all the default implementation does.
+    fn get_audit_login_uid(&mut self) -> Option<uid_t> {
+        let name = self.get_login_name()?;
+        self.get_user_id_by_name(&name).map(|id| id.uid)
+    }
+
+    /// Return the names of the running process's supplementary groups,
+    /// leaving out any that aren't in the groups database.
+    fn current_process_group_names(&mut self) -> Vec<String> {
+        self.current_process_groups().into_iter()
+            .filter_map(|gid| self.get_group_by_gid(gid))
+            .map(|g| g.name)
+            .collect()
+    }
+
+    /// Return a User object if one exists for the given user ID, `Ok(None)`
+    /// if it doesn't, or the reason the lookup failed.
+    ///
+    /// `get_user_by_uid` returns None on failure instead. The default
+    /// implementation never fails, which suits users objects that don't
+    /// read from anywhere.
+    fn try_get_user_by_uid(&mut self, uid: uid_t) -> Result<Option<User>, Error> {
+        Ok(self.get_user_by_uid(uid))
+    }
+
+    /// Return a User object if one exists for the given username,
+    /// `Ok(None)` if it doesn't, or the reason the lookup failed.
+    fn try_get_user_by_name(&mut self, username: &str) -> Result<Option<User>, Error> {
+        Ok(self.get_user_by_name(username))
+    }
+
+    /// Return a Group object if one exists for the given group ID,
+    /// `Ok(None)` if it doesn't, or the reason the lookup failed.
+    fn try_get_group_by_gid(&mut self, gid: gid_t) -> Result<Option<Group>, Error> {
+        Ok(self.get_group_by_gid(gid))
+    }
+
+    /// Return a Group object if one exists for the given groupname,
+    /// `Ok(None)` if it doesn't, or the reason the lookup failed.
+    fn try_get_group_by_name(&mut self, group_name: &str) -> Result<Option<Group>, Error> {
+        Ok(self.get_group_by_name(group_name))
+    }
+
+    /// Return the groups that the given user is a member of, `Ok(None)` if
+    /// there's no such user, or the reason a lookup failed.
+    fn try_get_user_groups(&mut self, username: &str, gid: gid_t) -> Result<Option<Vec<Group>>, Error> {
+        Ok(self.get_user_groups(username, gid))
+    }
+
+    /// Return the username of the user running the process, failing with
+    /// `NotFound` if there's no user with its ID, or `InvalidUtf8` if their
+    /// name isn't valid UTF-8.
+    fn try_get_current_username(&mut self) -> Result<String, Error> {
+        let uid = self.get_current_uid();
+        user_name_string(self.try_get_user_by_uid(uid)?)
+    }
+
+    /// Return the group name of the group running the process, failing as
+    /// `try_get_current_username` does.
+    fn try_get_current_groupname(&mut self) -> Result<String, Error> {
+        let gid = self.get_current_gid();
+        group_name_string(self.try_get_group_by_gid(gid)?)
+    }
+
+    /// Return the username of the effective user, failing as
+    /// `try_get_current_username` does.
+    fn try_get_effective_username(&mut self) -> Result<String, Error> {
+        let uid = self.get_effective_uid();
+        user_name_string(self.try_get_user_by_uid(uid)?)
+    }
+
+    /// Return the group name of the effective group, failing as
+    /// `try_get_current_username` does.
+    fn try_get_effective_groupname(&mut self) -> Result<String, Error> {
+        let gid = self.get_effective_gid();
+        group_name_string(self.try_get_group_by_gid(gid)?)
+    }
+}
+
+fn user_name_string(user: Option<User>) -> Result<String, Error> {
+    let user = user.ok_or(Error::NotFound)?;
+    user.name_os.into_string().map_err(|_| Error::InvalidUtf8)
+}
+
+/// Look up the IDs of a group's members for `Users::get_group_member_uids`,
+/// calling the given function with the group ID for each member name that
+/// isn't a user.
+fn group_member_uids<U, F>(users: &mut U, gid: gid_t, mut unresolved: F) -> Vec<uid_t>
+where U: Users + ?Sized, F: FnMut(gid_t) {
+    let mut uids = Vec::new();
+    let members = users.get_group_by_gid(gid).map(|g| g.members).unwrap_or_default();
+    for member in &members {
+        match users.get_user_id_by_name(member) {
+            Some(id) => if !uids.contains(&id.uid) { uids.push(id.uid) },
+            None => unresolved(gid),
+        }
+    }
+
+    for user in users.get_group_members(gid) {
+        if !uids.contains(&user.uid) {
+            uids.push(user.uid);
+        }
+    }
+
+    uids
+}
+
+fn group_name_string(group: Option<Group>) -> Result<String, Error> {
+    let group = group.ok_or(Error::NotFound)?;
+    group.name_os.into_string().map_err(|_| Error::InvalidUtf8)
+}
+
+/// A version of the `Users` trait whose methods take `&self` rather than
+/// `&mut self`.
+///
+/// Prefer this trait for read-mostly workloads, or when a users object needs
+/// to be stored behind an `Rc` or shared between several owners. Mock users
+/// are never modified by a lookup, and `OSUsers` still caches every result
+/// using interior mutability, so nothing is lost by using it. The `Users`
+/// trait is kept for code that already uses it.
+pub trait UsersRead {
+
+    /// Return a User object if one exists for the given user ID; otherwise, return None.
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<User>;
+
+    /// Return a User object if one exists for the given user ID, lending
+    /// it where the users object can and cloning it where it can't;
+    /// otherwise, return None.
+    ///
+    /// Users objects that hold every user themselves, such as `MockUsers`
+    /// and `PasswdFile`, lend theirs, so a lookup costs no allocation. The
+    /// default implementation always returns an owned user, and so do
+    /// `OSUsers` and `SharedUsers`, whose caches can't lend a reference
+    /// from behind their interior mutability.
+    fn get_user_by_uid_cow(&self, uid: uid_t) -> Option<Cow<'_, User>> {
+        self.get_user_by_uid(uid).map(Cow::Owned)
+    }
+
+    /// Return a User object if one exists for the given username; otherwise, return None.
+    fn get_user_by_name(&self, username: &str) -> Option<User>;
+
+    /// Return a Group object if one exists for the given group ID; otherwise, return None.
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Group>;
+
+    /// Return a Group object if one exists for the given groupname; otherwise, return None.
+    fn get_group_by_name(&self, group_name: &str) -> Option<Group>;
+
+    /// Return every group the given user belongs to, as with
+    /// `Users::get_user_groups`.
+    fn get_user_groups(&self, username: &str, gid: gid_t) -> Option<Vec<Group>>;
+
+    /// Return the user ID for the user running the process.
+    fn get_current_uid(&self) -> uid_t;
+
+    /// Return the username of the user running the process.
+    fn get_current_username(&self) -> Option<String>;
+
+    /// Return the group ID for the user running the process.
+    fn get_current_gid(&self) -> gid_t;
+
+    /// Return the group name of the user running the process.
+    fn get_current_groupname(&self) -> Option<String>;
+
+    /// Return the effective user id.
+    fn get_effective_uid(&self) -> uid_t;
+
+    /// Return the effective group id.
+    fn get_effective_gid(&self) -> gid_t;
+
+    /// Return the username of the effective user.
+    fn get_effective_username(&self) -> Option<String>;
+
+    /// Return the group name of the effective group.
+    fn get_effective_groupname(&self) -> Option<String>;
 }
 
 #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly"))]
@@ -204,12 +1124,31 @@ struct c_group {
     pub gr_mem:    *const *const c_char,  // names of users in the group
 }
 
-extern {
-    fn getpwuid(uid: uid_t) -> *const c_passwd;
-    fn getpwnam(user_name: *const c_char) -> *const c_passwd;
+extern "C" {
+    fn getpwuid_r(uid: uid_t, pwd: *mut c_passwd, buf: *mut c_char, buflen: size_t, result: *mut *mut c_passwd) -> c_int;
+    fn getpwnam_r(user_name: *const c_char, pwd: *mut c_passwd, buf: *mut c_char, buflen: size_t, result: *mut *mut c_passwd) -> c_int;
+
+    fn setpwent();
+    fn getpwent() -> *const c_passwd;
+    fn endpwent();
+
+    fn getgrgid_r(gid: gid_t, grp: *mut c_group, buf: *mut c_char, buflen: size_t, result: *mut *mut c_group) -> c_int;
+    fn getgrnam_r(group_name: *const c_char, grp: *mut c_group, buf: *mut c_char, buflen: size_t, result: *mut *mut c_group) -> c_int;
 
-    fn getgrgid(gid: gid_t) -> *const c_group;
-    fn getgrnam(group_name: *const c_char) -> *const c_group;
+    fn setgrent();
+    fn getgrent() -> *const c_group;
+    fn endgrent();
+
+    fn getgroups(size: c_int, list: *mut gid_t) -> c_int;
+
+    #[cfg(target_os = "linux")]
+    fn getgrouplist(user: *const c_char, group: gid_t, groups: *mut gid_t, ngroups: *mut c_int) -> c_int;
+    #[cfg(not(target_os = "linux"))]
+    fn getgrouplist(user: *const c_char, group: c_int, groups: *mut c_int, ngroups: *mut c_int) -> c_int;
+
+    fn getlogin_r(buf: *mut c_char, bufsize: size_t) -> c_int;
+
+    fn umask(mask: mode_t) -> mode_t;
 
     fn getuid() -> uid_t;
     fn geteuid() -> uid_t;
@@ -225,492 +1164,2047 @@ extern {
 
     fn setreuid(ruid: uid_t, euid: uid_t) -> c_int;
     fn setregid(rgid: gid_t, egid: gid_t) -> c_int;
+
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+    fn getresuid(ruid: *mut uid_t, euid: *mut uid_t, suid: *mut uid_t) -> c_int;
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+    fn getresgid(rgid: *mut gid_t, egid: *mut gid_t, sgid: *mut gid_t) -> c_int;
+
+    #[cfg(target_os = "linux")]
+    fn setgroups(size: size_t, list: *const gid_t) -> c_int;
+    #[cfg(not(target_os = "linux"))]
+    fn setgroups(size: c_int, list: *const gid_t) -> c_int;
 }
 
-#[derive(Clone)]
 /// Information about a particular user.
+///
+/// Users are ordered by user ID, and then by name for users that share an
+/// ID; any remaining fields only break ties between otherwise-identical
+/// users, so that the ordering agrees with equality. Where the user was read
+/// from doesn't count towards either.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct User {
 
     /// This user's ID
     pub uid: uid_t,
 
-    /// This user's name
+    /// This user's name, with any bytes that aren't valid UTF-8 replaced
     pub name: String,
 
+    /// This user's name, exactly as it appears in the users database
+    pub name_os: OsString,
+
     /// The ID of this user's primary group
     pub primary_group: gid_t,
 
     /// This user's home directory
-    pub home_dir: String,
+    pub home_dir: PathBuf,
 
     /// This user's shell
-    pub shell: String,
-}
+    pub shell: PathBuf,
 
-/// Information about a particular group.
-#[derive(Clone)]
-pub struct Group {
+    /// This user's real name, as the first comma-separated component of
+    /// the GECOS field
+    pub full_name: String,
 
-    /// This group's ID
-    pub gid: uid_t,
+    /// The raw GECOS field, which conventionally holds the full name,
+    /// office, and phone numbers separated by commas
+    pub gecos: String,
 
-    /// This group's name
-    pub name: String,
+    /// This user's password field, which is usually `x` to indicate that the
+    /// real hash is in the shadow file
+    pub passwd: String,
 
-    /// Vector of the names of the users who belong to this group as a non-primary member
-    pub members: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    raw_line: Option<String>,
 }
 
-/// A producer of user and group instances that caches every result.
-#[derive(Clone)]
-pub struct OSUsers {
-    users: HashMap<uid_t, Option<User>>,
-    users_back: HashMap<String, Option<uid_t>>,
+impl User {
+    /// Whether this user's account has been locked, which is signalled by a
+    /// password field of `*`, `!`, or `!!`.
+    pub fn is_locked(&self) -> bool {
+        matches!(&self.passwd[..], "*" | "!" | "!!")
+    }
 
-    groups: HashMap<gid_t, Option<Group>>,
-    groups_back: HashMap<String, Option<gid_t>>,
+    /// Whether this user can log in: their account isn't locked, and their
+    /// shell isn't one of the well-known ones that refuse logins, such as
+    /// `/usr/sbin/nologin` or `/bin/false`.
+    ///
+    /// This doesn't check the shell against `/etc/shells`; use
+    /// `valid_login_shells` for that.
+    pub fn can_login(&self) -> bool {
+        !self.is_locked() && !is_nologin_shell(&self.shell)
+    }
 
-    uid: Option<uid_t>,
-    gid: Option<gid_t>,
-    euid: Option<uid_t>,
-    egid: Option<gid_t>,
-}
+    /// Whether this is a system account rather than a person's: its user ID
+    /// is below the one returned by `uid_min`.
+    pub fn is_system_account(&self) -> bool {
+        self.is_system_account_below(uid_min())
+    }
 
-unsafe fn from_raw_buf(p: *const i8) -> String {
-    from_utf8_unchecked(CStr::from_ptr(p).to_bytes()).to_string()
-}
+    /// Whether this is a system account, counting every user ID below
+    /// `uid_min` as one.
+    pub fn is_system_account_below(&self, uid_min: uid_t) -> bool {
+        self.uid < uid_min
+    }
 
-unsafe fn passwd_to_user(pointer: *const c_passwd) -> Option<User> {
-    if !pointer.is_null() {
-        let pw = read(pointer);
-        Some(User {
-            uid: pw.pw_uid as uid_t,
-            name: from_raw_buf(pw.pw_name as *const i8),
-            primary_group: pw.pw_gid as gid_t,
-            home_dir: from_raw_buf(pw.pw_dir as *const i8),
-            shell: from_raw_buf(pw.pw_shell as *const i8)
-        })
+    /// Whether this is a person's account: it isn't a system account, and
+    /// it isn't the `nobody` user, whose ID is usually above `uid_min`.
+    pub fn is_human_account(&self) -> bool {
+        self.is_human_account_above(uid_min())
     }
-    else {
-        None
+
+    /// Whether this is a person's account, counting every user ID from
+    /// `uid_min` upwards as one, apart from `nobody`'s.
+    pub fn is_human_account_above(&self, uid_min: uid_t) -> bool {
+        !self.is_system_account_below(uid_min) && self.uid != NOBODY_UID && self.name != "nobody"
     }
-}
 
-unsafe fn struct_to_group(pointer: *const c_group) -> Option<Group> {
-    if !pointer.is_null() {
-        let gr = read(pointer);
-        let name = from_raw_buf(gr.gr_name as *const i8);
-        let members = members(gr.gr_mem);
-        Some(Group { gid: gr.gr_gid, name: name, members: members })
+    /// The line of the passwd file that this user was read from, without
+    /// its newline, or None if they weren't read from a file, such as when
+    /// they came from libc. This is for working out where a surprising
+    /// entry came from.
+    pub fn raw_line(&self) -> Option<&str> {
+        self.raw_line.as_ref().map(|l| &l[..])
     }
-    else {
-        None
+
+    /// This user's GECOS field, split into its conventional subfields.
+    pub fn gecos(&self) -> Gecos {
+        Gecos::parse(&self.gecos)
     }
 }
 
-unsafe fn members(groups: *const *const c_char) -> Vec<String> {
-    let mut i = 0;
-    let mut members = vec![];
+/// The subfields of a GECOS field, as read by `finger` and `chfn`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Default)]
+pub struct Gecos {
 
-    // The list of members is a pointer to a pointer of characters, terminated
-    // by a null pointer.
-    loop {
-        let username = groups.offset(i);
+    /// The user's real name
+    pub full_name: String,
 
-        // The first null check here should be unnecessary, but if libc sends
-        // us bad data, it's probably better to continue on than crashing...
-        if username.is_null() || (*username).is_null() {
-            return members;
-        }
+    /// The user's building and room number
+    pub room: String,
 
-        members.push(from_raw_buf(*username));
-        i += 1;
-    }
-}
+    /// The user's office telephone number
+    pub work_phone: String,
 
-impl Users for OSUsers {
-    fn get_user_by_uid(&mut self, uid: uid_t) -> Option<User> {
-        match self.users.entry(uid) {
-            Vacant(entry) => {
-                let user = unsafe { passwd_to_user(getpwuid(uid)) };
-                match user {
-                    Some(user) => {
-                        entry.insert(Some(user.clone()));
-                        self.users_back.insert(user.name.clone(), Some(user.uid));
-                        Some(user)
-                    },
-                    None => {
-                        entry.insert(None);
-                        None
-                    }
-                }
-            },
-            Occupied(entry) => entry.get().clone(),
-        }
-    }
+    /// The user's home telephone number
+    pub home_phone: String,
 
-    fn get_user_by_name(&mut self, username: &str) -> Option<User> {
-        // to_owned() could change here:
-        // https://github.com/rust-lang/rfcs/blob/master/text/0509-collections-reform-part-2.md#alternatives-to-toowned-on-entries
-        match self.users_back.entry(username.to_owned()) {
-            Vacant(entry) => {
-                let username_c = CString::new(username);
-
-                if !username_c.is_ok() {
-                    // This usually means the given username contained a '\0' already
-                    // It is debatable what to do here
-                    return None;
-                }
+    /// Anything after the home phone number, commas included
+    pub other: String,
+}
 
-                let user = unsafe { passwd_to_user(getpwnam(username_c.unwrap().as_ptr())) };
-                match user {
-                    Some(user) => {
-                        entry.insert(Some(user.uid));
-                        self.users.insert(user.uid, Some(user.clone()));
-                        Some(user)
-                    },
-                    None => {
-                        entry.insert(None);
-                        None
-                    }
-                }
-            },
-            Occupied(entry) => match entry.get() {
-                &Some(uid) => self.users[&uid].clone(),
-                &None => None,
-            }
+impl Gecos {
+    /// Split a GECOS field on its commas. Subfields missing from the end are
+    /// left empty.
+    pub fn parse(gecos: &str) -> Gecos {
+        let mut fields = gecos.splitn(5, ',').map(|f| f.to_owned());
+        Gecos {
+            full_name:  fields.next().unwrap_or_default(),
+            room:       fields.next().unwrap_or_default(),
+            work_phone: fields.next().unwrap_or_default(),
+            home_phone: fields.next().unwrap_or_default(),
+            other:      fields.next().unwrap_or_default(),
         }
     }
+}
 
-    fn get_group_by_gid(&mut self, gid: gid_t) -> Option<Group> {
-        match self.groups.entry(gid) {
-            Vacant(entry) => {
-                let group = unsafe { struct_to_group(getgrgid(gid)) };
-                match group {
-                    Some(group) => {
-                        entry.insert(Some(group.clone()));
-                        self.groups_back.insert(group.name.clone(), Some(group.gid));
-                        Some(group)
-                    },
-                    None => {
-                        entry.insert(None);
-                        None
-                    }
-                }
-            },
-            Occupied(entry) => entry.get().clone(),
-        }
-    }
+/// Just the IDs of a user, for when the rest of their details aren't
+/// needed, such as when checking whether they own a file.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct UserId {
 
-    fn get_group_by_name(&mut self, group_name: &str) -> Option<Group> {
-        // to_owned() could change here:
-        // https://github.com/rust-lang/rfcs/blob/master/text/0509-collections-reform-part-2.md#alternatives-to-toowned-on-entries
-        match self.groups_back.entry(group_name.to_owned()) {
-            Vacant(entry) => {
-                let group_name_c = CString::new(group_name);
-
-                if !group_name_c.is_ok() {
-                    // This usually means the given username contained a '\0' already
-                    // It is debatable what to do here
-                    return None;
-                }
+    /// The user's ID
+    pub uid: uid_t,
 
-                let user = unsafe { struct_to_group(getgrnam(group_name_c.unwrap().as_ptr())) };
-                match user {
-                    Some(group) => {
-                        entry.insert(Some(group.gid));
-                        self.groups.insert(group.gid, Some(group.clone()));
-                        Some(group)
-                    },
-                    None => {
-                        entry.insert(None);
-                        None
-                    }
-                }
-            },
-            Occupied(entry) => match entry.get() {
-                &Some(gid) => self.groups[&gid].clone(),
-                &None => None,
-            }
-        }
-    }
+    /// The ID of the user's primary group
+    pub gid: gid_t,
+}
 
-    fn get_current_uid(&mut self) -> uid_t {
-        match self.uid {
-            Some(uid) => uid,
-            None => {
-                let uid = unsafe { getuid() };
-                self.uid = Some(uid);
-                uid
-            }
-        }
+impl<'a> From<&'a User> for UserId {
+    fn from(user: &'a User) -> UserId {
+        UserId { uid: user.uid, gid: user.primary_group }
     }
+}
 
-    /// Return the username of the user running the process.
-    fn get_current_username(&mut self) -> Option<String> {
-        let uid = self.get_current_uid();
-        self.get_user_by_uid(uid).map(|u| u.name)
-    }
+/// Everything about who the running process is, as printed by `id`,
+/// returned by `Users::whoami`.
+#[derive(Clone)]
+pub struct Identity {
 
-    fn get_current_gid(&mut self) -> gid_t {
-        match self.gid {
-            Some(gid) => gid,
-            None => {
-                let gid = unsafe { getgid() };
-                self.gid = Some(gid);
-                gid
-            }
-        }
-    }
+    /// The real user ID
+    pub uid: uid_t,
 
-    fn get_current_groupname(&mut self) -> Option<String> {
-        let gid = self.get_current_gid();
-        self.get_group_by_gid(gid).map(|g| g.name)
-    }
+    /// The real group ID
+    pub gid: gid_t,
 
-    fn get_effective_gid(&mut self) -> gid_t {
-        match self.egid {
-            Some(gid) => gid,
-            None => {
-                let gid = unsafe { getegid() };
-                self.egid = Some(gid);
-                gid
-            }
-        }
-    }
+    /// The effective user ID
+    pub euid: uid_t,
 
-    fn get_effective_groupname(&mut self) -> Option<String> {
-        let gid = self.get_effective_gid();
-        self.get_group_by_gid(gid).map(|g| g.name)
-    }
+    /// The effective group ID
+    pub egid: gid_t,
 
-    fn get_effective_uid(&mut self) -> uid_t {
-        match self.euid {
-            Some(uid) => uid,
-            None => {
-                let uid = unsafe { geteuid() };
-                self.euid = Some(uid);
-                uid
-            }
-        }
-    }
+    /// The name of the real user, or None if they aren't in the users
+    /// database
+    pub username: Option<String>,
 
-    fn get_effective_username(&mut self) -> Option<String> {
-        let uid = self.get_effective_uid();
-        self.get_user_by_uid(uid).map(|u| u.name)
-    }
+    /// The process's supplementary groups, leaving out any that aren't in
+    /// the groups database
+    pub groups: Vec<Group>,
 }
 
-impl OSUsers {
-    /// Create a new empty OS Users object.
-    pub fn empty_cache() -> OSUsers {
-        OSUsers {
-            users:       HashMap::new(),
-            users_back:  HashMap::new(),
-            groups:      HashMap::new(),
-            groups_back: HashMap::new(),
-            uid:         None,
-            gid:         None,
-            euid:        None,
-            egid:        None,
-        }
-    }
-}
+/// Shells that exist only to stop a user from logging in.
+const NOLOGIN_SHELLS: &[&str] = &[
+    "/bin/false", "/usr/bin/false",
+    "/sbin/nologin", "/usr/sbin/nologin", "/bin/nologin", "/usr/bin/nologin",
+];
 
-/// Return a User object if one exists for the given user ID; otherwise, return None.
-pub fn get_user_by_uid(uid: uid_t) -> Option<User> {
-    OSUsers::empty_cache().get_user_by_uid(uid)
+fn is_nologin_shell(shell: &Path) -> bool {
+    NOLOGIN_SHELLS.iter().any(|s| shell == Path::new(s))
 }
 
-/// Return a User object if one exists for the given username; otherwise, return None.
-pub fn get_user_by_name(username: &str) -> Option<User> {
-    OSUsers::empty_cache().get_user_by_name(username)
+/// Return the shells listed in `/etc/shells`, which are the ones that are
+/// allowed to be used as login shells. This is empty if the file can't be
+/// read.
+pub fn valid_login_shells() -> Vec<PathBuf> {
+    let mut contents = String::new();
+    match File::open("/etc/shells").and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => parse_shells(&contents),
+        Err(_) => Vec::new(),
+    }
 }
 
-/// Return a Group object if one exists for the given group ID; otherwise, return None.
-pub fn get_group_by_gid(gid: gid_t) -> Option<Group> {
-    OSUsers::empty_cache().get_group_by_gid(gid)
+fn parse_shells(contents: &str) -> Vec<PathBuf> {
+    contents.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(PathBuf::from)
+            .collect()
 }
 
-/// Return a Group object if one exists for the given groupname; otherwise, return None.
-pub fn get_group_by_name(group_name: &str) -> Option<Group> {
-    OSUsers::empty_cache().get_group_by_name(group_name)
-}
+/// The set-group-ID bit of a file's mode, which makes a directory's new
+/// files take its group.
+const S_ISGID: u32 = 0o2000;
 
-/// Return the user ID for the user running the process.
-pub fn get_current_uid() -> uid_t {
-    OSUsers::empty_cache().get_current_uid()
-}
+/// The user ID that the `nobody` user usually has on Linux, used when
+/// there's no user by that name. Other systems differ: macOS uses -2.
+pub const NOBODY_UID: uid_t = 65534;
 
-/// Return the username of the user running the process.
-pub fn get_current_username() -> Option<String> {
-    OSUsers::empty_cache().get_current_username()
-}
+/// The group ID that the `nogroup` or `nobody` group usually has on Linux,
+/// used when there's no group by either name.
+pub const NOGROUP_GID: gid_t = 65534;
 
-/// Return the user ID for the effective user running the process.
-pub fn get_effective_uid() -> uid_t {
-    OSUsers::empty_cache().get_effective_uid()
+/// The lowest user ID given to a person's account when `/etc/login.defs`
+/// doesn't say otherwise.
+pub const DEFAULT_UID_MIN: uid_t = 1000;
+
+/// The highest user ID given to a person's account when `/etc/login.defs`
+/// doesn't say otherwise.
+pub const DEFAULT_UID_MAX: uid_t = 60000;
+
+/// Return the lowest user ID that `useradd` gives to a person's account, as
+/// set by `UID_MIN` in `/etc/login.defs`, or `DEFAULT_UID_MIN` if it can't be
+/// read. Accounts below this are usually for system services.
+pub fn uid_min() -> uid_t {
+    login_defs_uid("UID_MIN").unwrap_or(DEFAULT_UID_MIN)
 }
 
-/// Return the username of the effective user running the process.
-pub fn get_effective_username() -> Option<String> {
-    OSUsers::empty_cache().get_effective_username()
+/// Return the highest user ID that `useradd` gives to a person's account, as
+/// set by `UID_MAX` in `/etc/login.defs`, or `DEFAULT_UID_MAX` if it can't be
+/// read.
+pub fn uid_max() -> uid_t {
+    login_defs_uid("UID_MAX").unwrap_or(DEFAULT_UID_MAX)
 }
 
-/// Return the group ID for the user running the process.
-pub fn get_current_gid() -> gid_t {
-    OSUsers::empty_cache().get_current_gid()
+fn login_defs_uid(key: &str) -> Option<uid_t> {
+    let mut contents = String::new();
+    File::open("/etc/login.defs").and_then(|mut f| f.read_to_string(&mut contents)).ok()?;
+    parse_login_defs(&contents, key).and_then(|value| value.parse().ok())
 }
 
-/// Return the groupname of the user running the process.
-pub fn get_current_groupname() -> Option<String> {
-    OSUsers::empty_cache().get_current_groupname()
+fn parse_login_defs<'a>(contents: &'a str, key: &str) -> Option<&'a str> {
+    // Later settings override earlier ones, so search from the end.
+    contents.lines().rev()
+            .map(|line| line.trim())
+            .filter(|line| !line.starts_with('#'))
+            .find_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next()) {
+                    (Some(k), Some(value)) if k == key => Some(value),
+                    _ => None,
+                }
+            })
 }
 
-/// Return the group ID for the effective user running the process.
-pub fn get_effective_gid() -> gid_t {
-    OSUsers::empty_cache().get_effective_gid()
+/// Information about a particular group.
+///
+/// Groups are ordered by group ID, and then by name, in the same way as
+/// users.
+#[derive(PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Group {
+
+    /// This group's ID
+    pub gid: uid_t,
+
+    /// This group's name, with any bytes that aren't valid UTF-8 replaced
+    pub name: String,
+
+    /// This group's name, exactly as it appears in the groups database
+    pub name_os: OsString,
+
+    /// Vector of the names of the users who belong to this group as a non-primary member
+    pub members: Vec<String>,
+
+    /// This group's password field, which is usually `x` or empty
+    pub passwd: String,
 }
 
-/// Return the groupname of the effective user running the process.
-pub fn get_effective_groupname() -> Option<String> {
-    OSUsers::empty_cache().get_effective_groupname()
+impl Group {
+    /// Whether this group has a password that `newgrp` will accept. That's
+    /// any password field apart from `x`, which points to the gshadow file,
+    /// an empty one, and the `*` and `!` that mean there isn't one.
+    pub fn has_password(&self) -> bool {
+        !matches!(&self.passwd[..], "" | "x" | "*" | "!")
+    }
 }
 
-/// Set current user for the running process, requires root priviledges.
-pub fn set_current_uid(uid: uid_t) -> Result<(), io::Error> {
-    match unsafe { setuid(uid) } {
-        0 => Ok(()),
-        -1 => Err(io::Error::last_os_error()),
-        _ => unreachable!()
+impl Ord for User {
+    fn cmp(&self, other: &User) -> Ordering {
+        (self.uid, &self.name).cmp(&(other.uid, &other.name))
+            .then_with(|| (&self.name_os, self.primary_group, &self.home_dir, &self.shell)
+                .cmp(&(&other.name_os, other.primary_group, &other.home_dir, &other.shell)))
+            .then_with(|| (&self.full_name, &self.gecos, &self.passwd)
+                .cmp(&(&other.full_name, &other.gecos, &other.passwd)))
     }
 }
 
-/// Set current group for the running process, requires root priviledges.
-pub fn set_current_gid(gid: gid_t) -> Result<(), io::Error> {
-    match unsafe { setgid(gid) } {
-        0 => Ok(()),
-        -1 => Err(io::Error::last_os_error()),
-        _ => unreachable!()
+impl PartialOrd for User {
+    fn partial_cmp(&self, other: &User) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-/// Set effective user for the running process, requires root priviledges.
-pub fn set_effective_uid(uid: uid_t) -> Result<(), io::Error> {
-    match unsafe { seteuid(uid) } {
-        0 => Ok(()),
-        -1 => Err(io::Error::last_os_error()),
-        _ => unreachable!()
+impl PartialEq for User {
+    fn eq(&self, other: &User) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
-/// Set effective user for the running process, requires root priviledges.
-pub fn set_effective_gid(gid: gid_t) -> Result<(), io::Error> {
-    match unsafe { setegid(gid) } {
-        0 => Ok(()),
-        -1 => Err(io::Error::last_os_error()),
-        _ => unreachable!()
+impl Eq for User {}
+
+impl Hash for User {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.uid, &self.name, &self.name_os, self.primary_group, &self.home_dir, &self.shell).hash(state);
+        (&self.full_name, &self.gecos, &self.passwd).hash(state);
     }
 }
 
-/// Atomically set current and effective user for the running process, requires root priviledges.
-pub fn set_both_uid(ruid: uid_t, euid: uid_t) -> Result<(), io::Error> {
-    match unsafe { setreuid(ruid, euid) } {
-        0 => Ok(()),
-        -1 => Err(io::Error::last_os_error()),
-        _ => unreachable!()
+impl Ord for Group {
+    fn cmp(&self, other: &Group) -> Ordering {
+        (self.gid, &self.name).cmp(&(other.gid, &other.name))
+            .then_with(|| (&self.name_os, &self.members, &self.passwd)
+                .cmp(&(&other.name_os, &other.members, &other.passwd)))
     }
 }
 
-/// Atomically set current and effective group for the running process, requires root priviledges.
-pub fn set_both_gid(rgid: gid_t, egid: gid_t) -> Result<(), io::Error> {
-    match unsafe { setregid(rgid, egid) } {
-        0 => Ok(()),
-        -1 => Err(io::Error::last_os_error()),
-        _ => unreachable!()
+impl PartialOrd for Group {
+    fn partial_cmp(&self, other: &Group) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-pub struct SwitchUserGuard {
-    uid: uid_t,
+/// Something an `OSUsers` object did to answer a lookup, reported to the
+/// hook given to `set_trace_hook`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CacheEvent {
+
+    /// A user was found in the cache, whether it was looked up by ID or by
+    /// name.
+    Hit { uid: uid_t },
+
+    /// A user ID wasn't in the cache, or its entry had expired, so it has
+    /// to be read from the database.
+    Miss { uid: uid_t },
+
+    /// A group was found in the cache.
+    GroupHit { gid: gid_t },
+
+    /// A group ID wasn't in the cache, or its entry had expired.
+    GroupMiss { gid: gid_t },
+
+    /// The database was asked for an entry, using the libc function with
+    /// this name.
+    LibcCall { which: &'static str },
+
+    /// A member of the group with this ID doesn't name any user, so
+    /// `get_group_member_uids` left them out.
+    UnresolvedMember { gid: gid_t },
+}
+
+/// A caching users object over any source, as a clearer name for the same
+/// type when the source isn't the operating system's: the caching, expiry,
+/// and negative caching are the same whatever the entries come from.
+///
+/// A `MockUsers` or a `PasswdFile` can be the source, as well as any custom
+/// `PasswdSource`. Everything is asked of the source, including the lists
+/// of every user and group, each user's groups, and the current user and
+/// group IDs, so nothing is read from the system unless the source reads
+/// it.
+pub type CachingUsers<S> = OSUsers<S>;
+
+/// A cached lookup result, along with when it was read from the database.
+type Cached<T> = (T, Instant);
+
+/// A group as it's kept in the cache, with its name and the names of its
+/// members as shared strings. With interning on, a name that's in many
+/// groups is only stored once. It's turned back into a `Group` whenever
+/// it's asked for.
+#[derive(Clone)]
+struct CachedGroup {
     gid: gid_t,
+    name: Rc<str>,
+    name_os: OsString,
+    members: Vec<Rc<str>>,
+    passwd: String,
 }
 
-impl Drop for SwitchUserGuard {
-    fn drop(&mut self) {
-        // Panic on error here, as failing to set values back
-        // is a possible security breach.
-        set_effective_uid(self.uid).unwrap();
-        set_effective_gid(self.gid).unwrap();
+impl CachedGroup {
+    fn to_group(&self) -> Group {
+        Group {
+            gid:     self.gid,
+            name:    self.name.to_string(),
+            name_os: self.name_os.clone(),
+            members: self.members.iter().map(|m| m.to_string()).collect(),
+            passwd:  self.passwd.clone(),
+        }
     }
 }
 
-/// Safely switch user and group for the current scope.
-/// Requires root access.
-///
-/// ```ignore
-/// {
-///     let _guard = switch_user_group(1001, 1001);
-///     // current and effective user and group ids are 1001
-/// }
-/// // back to the old values
-/// ```
+/// A producer of user and group instances that caches every result.
 ///
-/// Use with care! Possible security issues can happen, as Rust doesn't
-/// guarantee running the destructor! If in doubt run `drop()` method
-/// on the guard value manually!
-pub fn switch_user_group(uid: uid_t, gid: gid_t) -> Result<SwitchUserGuard, io::Error> {
-    let current_state = SwitchUserGuard {
-        uid: get_effective_uid(),
-        gid: get_effective_gid(),
-    };
+/// The users and groups come from libc unless the object is created with
+/// `with_source`, which is mostly useful for tests.
+#[derive(Clone)]
+pub struct OSUsers<S = LibcSource> {
+    source: S,
 
-    try!(set_effective_uid(uid));
-    try!(set_effective_gid(gid));
-    Ok(current_state)
+    users: RefCell<HashMap<uid_t, Cached<Option<Rc<User>>>>>,
+    users_back: RefCell<HashMap<Rc<str>, Cached<Option<uid_t>>>>,
+
+    groups: RefCell<HashMap<gid_t, Cached<Option<CachedGroup>>>>,
+    groups_back: RefCell<HashMap<Rc<str>, Cached<Option<gid_t>>>>,
+
+    ttl: Option<Duration>,
+    negative_caching: bool,
+    trace_hook: Option<Rc<dyn Fn(CacheEvent)>>,
+    names: Option<RefCell<HashSet<Rc<str>>>>,
+
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    watch: Option<watch::Watch>,
+
+    uid: Cell<Option<uid_t>>,
+    gid: Cell<Option<gid_t>>,
+    euid: Cell<Option<uid_t>>,
+    egid: Cell<Option<gid_t>>,
 }
 
-#[cfg(test)]
-mod test {
-    use super::{Users, OSUsers, get_current_username};
+unsafe fn from_raw_buf(p: *const c_char) -> String {
+    if p.is_null() {
+        return String::new();
+    }
 
-    #[test]
-    fn uid() {
-        OSUsers::empty_cache().get_current_uid();
+    String::from_utf8_lossy(CStr::from_ptr(p).to_bytes()).into_owned()
+}
+
+unsafe fn from_raw_os(p: *const c_char) -> OsString {
+    if p.is_null() {
+        return OsString::new();
     }
 
-    #[test]
+    OsStr::from_bytes(CStr::from_ptr(p).to_bytes()).to_os_string()
+}
+
+unsafe fn from_raw_path(p: *const c_char) -> PathBuf {
+    if p.is_null() {
+        return PathBuf::new();
+    }
+
+    PathBuf::from(from_raw_os(p))
+}
+
+/// Extract the user's display name from a GECOS field, which is the part
+/// before the first comma.
+fn gecos_full_name(gecos: &str) -> String {
+    gecos.split(',').next().unwrap_or("").to_owned()
+}
+
+unsafe fn passwd_to_user(pointer: *const c_passwd) -> Option<User> {
+    if !pointer.is_null() {
+        let pw = read(pointer);
+        let gecos = from_raw_buf(pw.pw_gecos);
+        Some(User {
+            uid: pw.pw_uid as uid_t,
+            name: from_raw_buf(pw.pw_name),
+            name_os: from_raw_os(pw.pw_name),
+            primary_group: pw.pw_gid as gid_t,
+            home_dir: from_raw_path(pw.pw_dir),
+            shell: from_raw_path(pw.pw_shell),
+            full_name: gecos_full_name(&gecos),
+            gecos,
+            passwd: from_raw_buf(pw.pw_passwd),
+            raw_line: None,
+        })
+    }
+    else {
+        None
+    }
+}
+
+unsafe fn struct_to_group(pointer: *const c_group) -> Option<Group> {
+    if !pointer.is_null() {
+        let gr = read(pointer);
+        Some(Group {
+            gid: gr.gr_gid,
+            name: from_raw_buf(gr.gr_name),
+            name_os: from_raw_os(gr.gr_name),
+            members: members(gr.gr_mem),
+            passwd: from_raw_buf(gr.gr_passwd),
+        })
+    }
+    else {
+        None
+    }
+}
+
+unsafe fn members(groups: *const *const c_char) -> Vec<String> {
+    let mut i = 0;
+    let mut members = vec![];
+
+    // The list of members is a pointer to a pointer of characters, terminated
+    // by a null pointer.
+    loop {
+        let username = groups.offset(i);
+
+        // The first null check here should be unnecessary, but if libc sends
+        // us bad data, it's probably better to continue on than crashing...
+        if username.is_null() || (*username).is_null() {
+            return members;
+        }
+
+        members.push(from_raw_buf(*username));
+        i += 1;
+    }
+}
+
+/// Return the IDs of the running process's supplementary groups, or an empty
+/// list if they can't be read.
+fn process_groups() -> Vec<gid_t> {
+    loop {
+        // The first call asks how many groups there are, and the second
+        // fills in a list of that size.
+        let count = unsafe { getgroups(0, ptr::null_mut()) };
+        if count < 0 {
+            return Vec::new();
+        }
+
+        let mut buffer = vec![0; count as usize];
+        let filled = unsafe { getgroups(count, buffer.as_mut_ptr()) };
+        if filled >= 0 {
+            buffer.truncate(filled as usize);
+            return buffer;
+        }
+
+        // EINVAL means the list grew between the two calls, so try again.
+        if io::Error::last_os_error().raw_os_error() != Some(EINVAL) {
+            return Vec::new();
+        }
+    }
+}
+
+/// The longest login name `getlogin_r` is expected to return, including the
+/// terminating NUL, which is `LOGIN_NAME_MAX` on Linux. The buffer grows if
+/// it needs to.
+const LOGIN_NAME_SIZE: usize = 256;
+
+fn login_name() -> Option<String> {
+    let name = with_growing_buffer(LOGIN_NAME_SIZE, |buffer| {
+        match unsafe { getlogin_r(buffer.as_mut_ptr(), buffer.len() as size_t) } {
+            0   => Ok(Some(unsafe { from_raw_buf(buffer.as_ptr()) })),
+            ret => Err(io::Error::from_raw_os_error(ret)),
+        }
+    });
+
+    name.unwrap_or(None).filter(|n| !n.is_empty())
+}
+
+/// The audit login UID of a process that never had one set, such as one
+/// started at boot rather than by a login.
+#[cfg(target_os = "linux")]
+const AUDIT_UID_UNSET: uid_t = 4294967295;
+
+/// Read an audit login UID from a file in the format of
+/// `/proc/self/loginuid`, or return None if it can't be read or was never
+/// set.
+#[cfg(target_os = "linux")]
+fn read_loginuid(path: &Path) -> Option<uid_t> {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut contents)).ok()?;
+    contents.trim().parse().ok().filter(|&uid| uid != AUDIT_UID_UNSET)
+}
+
+/// Return how many groups the given user belongs to, including the given
+/// group ID, without reading the groups themselves when glibc can say. Return
+/// 0 if the username can't be passed to C.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn group_count(username: &str, gid: gid_t) -> usize {
+    let username_c = match CString::new(username) {
+        Ok(c) => c,
+        Err(_) => return 0,
+    };
+
+    // Passing a zero-length list makes glibc fail but report how many
+    // groups there are in the count, all in one call. If it failed without
+    // reporting any, fetch the whole list instead.
+    let mut count: c_int = 0;
+    let result = unsafe {
+        getgrouplist(username_c.as_ptr(), gid as _, ptr::null_mut(), &mut count)
+    };
+
+    if result < 0 && count <= 0 {
+        group_list(username, gid).map_or(0, |groups| groups.len())
+    }
+    else {
+        count as usize
+    }
+}
+
+/// Return how many groups the given user belongs to, including the given
+/// group ID. Other C libraries, musl among them, don't report the count when
+/// the list is too short, so this has to fetch the whole list.
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn group_count(username: &str, gid: gid_t) -> usize {
+    group_list(username, gid).map_or(0, |groups| groups.len())
+}
+
+/// Return the IDs of every group the given user belongs to, including the
+/// given group ID, in the order `getgrouplist` produces them. Return None if
+/// the username can't be passed to C, or if the groups still don't fit in a
+/// list of `MAX_GROUPS` entries.
+fn group_list(username: &str, gid: gid_t) -> Option<Vec<gid_t>> {
+    let username_c = match CString::new(username) {
+        Ok(c) => c,
+        Err(_) => return None,
+    };
+
+    let mut buffer = vec![0; 32];
+
+    loop {
+        let mut count = buffer.len() as c_int;
+        let result = unsafe {
+            getgrouplist(username_c.as_ptr(), gid as _, buffer.as_mut_ptr() as *mut _, &mut count)
+        };
+
+        if result >= 0 {
+            buffer.truncate(count as usize);
+            return Some(buffer.into_iter().map(|g| g as gid_t).collect());
+        }
+
+        // glibc reports how many groups there are when the buffer is too
+        // small, but other platforms don't, so grow it until they fit.
+        if buffer.len() >= MAX_GROUPS {
+            return None;
+        }
+
+        let len = ::std::cmp::max(count as usize, buffer.len() * 2);
+        buffer.resize(::std::cmp::min(len, MAX_GROUPS), 0);
+    }
+}
+
+/// The most groups to make room for when listing a user's groups before
+/// giving up. This is far beyond any kernel's `NGROUPS_MAX`, but stops a
+/// misbehaving libc from making us allocate forever.
+const MAX_GROUPS: usize = 65536;
+
+/// Return the buffer size to use for the `get*_r` functions, as suggested by
+/// `sysconf` with the given name.
+fn buffer_size(name: c_int) -> usize {
+    match unsafe { sysconf(name) } {
+        n if n > 0 => n as usize,
+        _ => 1024,
+    }
+}
+
+/// Turn the return value and result pointer of `getpwuid_r` or `getpwnam_r`
+/// into a user. Not finding the user isn't an error.
+unsafe fn passwd_result(ret: c_int, result: *mut c_passwd) -> io::Result<Option<User>> {
+    if !result.is_null() {
+        Ok(passwd_to_user(result))
+    }
+    else if ret == 0 || ret == ENOENT {
+        Ok(None)
+    }
+    else {
+        Err(io::Error::from_raw_os_error(ret))
+    }
+}
+
+/// Turn the return value and result pointer of `getgrgid_r` or `getgrnam_r`
+/// into a group. Not finding the group isn't an error.
+unsafe fn group_result(ret: c_int, result: *mut c_group) -> io::Result<Option<Group>> {
+    if !result.is_null() {
+        Ok(struct_to_group(result))
+    }
+    else if ret == 0 || ret == ENOENT {
+        Ok(None)
+    }
+    else {
+        Err(io::Error::from_raw_os_error(ret))
+    }
+}
+
+/// The largest buffer to offer the `get*_r` functions before giving up. This
+/// is far beyond any real entry, but stops a misbehaving libc from making us
+/// allocate forever.
+const MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Call one of the `get*_r` functions with a buffer of the given initial
+/// size, doubling it and trying again for as long as the call fails with
+/// `ERANGE`.
+fn with_growing_buffer<T, F>(initial_size: usize, mut call: F) -> io::Result<Option<T>>
+where F: FnMut(&mut [c_char]) -> io::Result<Option<T>> {
+    let mut size = initial_size;
+
+    loop {
+        let mut buffer = vec![0; size];
+
+        match call(&mut buffer) {
+            Err(ref e) if e.raw_os_error() == Some(ERANGE) && size < MAX_BUFFER_SIZE => {
+                size = ::std::cmp::min(size * 2, MAX_BUFFER_SIZE);
+            },
+            result => return result,
+        }
+    }
+}
+
+fn lookup_user_by_uid<S: PasswdSource>(source: &S, uid: uid_t) -> io::Result<Option<User>> {
+    with_growing_buffer(buffer_size(_SC_GETPW_R_SIZE_MAX), |buffer| source.user_by_uid(uid, buffer))
+}
+
+fn lookup_user_by_name<S: PasswdSource>(source: &S, username: &OsStr) -> io::Result<Option<User>> {
+    // A name containing a '\0' can't be in the database.
+    let username_c = match CString::new(username.as_bytes()) {
+        Ok(c) => c,
+        Err(_) => return Ok(None),
+    };
+
+    with_growing_buffer(buffer_size(_SC_GETPW_R_SIZE_MAX), |buffer| source.user_by_name(&username_c, buffer))
+}
+
+fn lookup_group_by_gid<S: PasswdSource>(source: &S, gid: gid_t) -> io::Result<Option<Group>> {
+    with_growing_buffer(buffer_size(_SC_GETGR_R_SIZE_MAX), |buffer| source.group_by_gid(gid, buffer))
+}
+
+fn lookup_group_by_name<S: PasswdSource>(source: &S, group_name: &OsStr) -> io::Result<Option<Group>> {
+    // A name containing a '\0' can't be in the database.
+    let group_name_c = match CString::new(group_name.as_bytes()) {
+        Ok(c) => c,
+        Err(_) => return Ok(None),
+    };
+
+    with_growing_buffer(buffer_size(_SC_GETGR_R_SIZE_MAX), |buffer| source.group_by_name(&group_name_c, buffer))
+}
+
+impl<S: PasswdSource> OSUsers<S> {
+    /// Whether an entry read from the database at the given time can still
+    /// be used.
+    fn is_fresh(&self, read_at: Instant) -> bool {
+        match self.ttl {
+            Some(ttl) => read_at.elapsed() < ttl,
+            None => true,
+        }
+    }
+
+    /// Forget the cached users or groups if the file they came from has
+    /// changed since the last lookup, when the files are being watched.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    fn check_watch(&self) {
+        if let Some(ref watch) = self.watch {
+            if watch.passwd_changed() {
+                self.users.borrow_mut().clear();
+                self.users_back.borrow_mut().clear();
+            }
+
+            if watch.group_changed() {
+                self.groups.borrow_mut().clear();
+                self.groups_back.borrow_mut().clear();
+            }
+        }
+    }
+
+    #[cfg(not(all(feature = "watch", target_os = "linux")))]
+    fn check_watch(&self) {}
+
+    /// Return a shared copy of the given name. With interning on, this is
+    /// the same copy every time the same name is asked for.
+    fn intern(&self, name: &str) -> Rc<str> {
+        let names = match self.names {
+            Some(ref names) => names,
+            None => return Rc::from(name),
+        };
+
+        if let Some(interned) = names.borrow().get(name) {
+            return interned.clone();
+        }
+
+        let interned: Rc<str> = Rc::from(name);
+        names.borrow_mut().insert(interned.clone());
+        interned
+    }
+
+    /// Turn a group read from the database into the form it's cached in.
+    fn cache_group(&self, group: Group) -> CachedGroup {
+        CachedGroup {
+            gid:     group.gid,
+            name:    self.intern(&group.name),
+            name_os: group.name_os,
+            members: group.members.iter().map(|m| self.intern(m)).collect(),
+            passwd:  group.passwd,
+        }
+    }
+
+    /// Tell the trace hook about an event, if there is one.
+    fn trace(&self, event: CacheEvent) {
+        if let Some(ref hook) = self.trace_hook {
+            hook(event);
+        }
+    }
+
+    fn cached_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Rc<User>>> {
+        self.check_watch();
+        if let Some(&(ref user, read_at)) = self.users.borrow().get(&uid) {
+            if self.is_fresh(read_at) {
+                self.trace(CacheEvent::Hit { uid });
+                return Ok(user.clone());
+            }
+        }
+
+        self.trace(CacheEvent::Miss { uid });
+        self.trace(CacheEvent::LibcCall { which: "getpwuid_r" });
+        let user = lookup_user_by_uid(&self.source, uid)?.map(Rc::new);
+        let now = Instant::now();
+        if let Some(ref user) = user {
+            self.users_back.borrow_mut().insert(self.intern(&user.name), (Some(user.uid), now));
+        }
+
+        if user.is_some() || self.negative_caching {
+            self.users.borrow_mut().insert(uid, (user.clone(), now));
+        }
+        Ok(user)
+    }
+
+    fn cached_user_by_name(&self, username: &str) -> io::Result<Option<Rc<User>>> {
+        self.check_watch();
+        if let Some(&(uid, read_at)) = self.users_back.borrow().get(username) {
+            match uid {
+                None if self.is_fresh(read_at) => return Ok(None),
+                None => {},
+                Some(uid) => if let Some(&(ref user, read_at)) = self.users.borrow().get(&uid) {
+                    if self.is_fresh(read_at) {
+                        self.trace(CacheEvent::Hit { uid });
+                        return Ok(user.clone());
+                    }
+                },
+            }
+        }
+
+        self.trace(CacheEvent::LibcCall { which: "getpwnam_r" });
+        let user = lookup_user_by_name(&self.source, username.as_ref())?.map(Rc::new);
+        let now = Instant::now();
+        match user {
+            Some(ref user) => {
+                self.users.borrow_mut().insert(user.uid, (Some(user.clone()), now));
+                self.users_back.borrow_mut().insert(self.intern(username), (Some(user.uid), now));
+            },
+            None if self.negative_caching => {
+                self.users_back.borrow_mut().insert(self.intern(username), (None, now));
+            },
+            None => {},
+        }
+
+        Ok(user)
+    }
+
+    fn cached_group_by_gid(&self, gid: gid_t) -> io::Result<Option<CachedGroup>> {
+        self.check_watch();
+        if let Some(&(ref group, read_at)) = self.groups.borrow().get(&gid) {
+            if self.is_fresh(read_at) {
+                self.trace(CacheEvent::GroupHit { gid });
+                return Ok(group.clone());
+            }
+        }
+
+        self.trace(CacheEvent::GroupMiss { gid });
+        self.trace(CacheEvent::LibcCall { which: "getgrgid_r" });
+        let group = lookup_group_by_gid(&self.source, gid)?.map(|g| self.cache_group(g));
+        let now = Instant::now();
+        if let Some(ref group) = group {
+            self.groups_back.borrow_mut().insert(group.name.clone(), (Some(group.gid), now));
+        }
+
+        if group.is_some() || self.negative_caching {
+            self.groups.borrow_mut().insert(gid, (group.clone(), now));
+        }
+        Ok(group)
+    }
+
+    fn cached_group_by_name(&self, group_name: &str) -> io::Result<Option<CachedGroup>> {
+        self.check_watch();
+        if let Some(&(gid, read_at)) = self.groups_back.borrow().get(group_name) {
+            match gid {
+                None if self.is_fresh(read_at) => return Ok(None),
+                None => {},
+                Some(gid) => if let Some(&(ref group, read_at)) = self.groups.borrow().get(&gid) {
+                    if self.is_fresh(read_at) {
+                        self.trace(CacheEvent::GroupHit { gid });
+                        return Ok(group.clone());
+                    }
+                },
+            }
+        }
+
+        self.trace(CacheEvent::LibcCall { which: "getgrnam_r" });
+        let group = lookup_group_by_name(&self.source, group_name.as_ref())?.map(|g| self.cache_group(g));
+        let now = Instant::now();
+        match group {
+            Some(ref group) => {
+                self.groups.borrow_mut().insert(group.gid, (Some(group.clone()), now));
+                self.groups_back.borrow_mut().insert(self.intern(group_name), (Some(group.gid), now));
+            },
+            None if self.negative_caching => {
+                self.groups_back.borrow_mut().insert(self.intern(group_name), (None, now));
+            },
+            None => {},
+        }
+
+        Ok(group)
+    }
+
+    /// Return a Group object if one exists for the given group ID, reading
+    /// it from the database even if it's already cached, and replacing the
+    /// cached entry with the result. This picks up a change to just one
+    /// group, such as a member being added, without flushing everything.
+    pub fn get_group_by_gid_fresh(&mut self, gid: gid_t) -> Option<Group> {
+        self.forget_group(gid);
+        self.get_group_by_gid(gid)
+    }
+}
+
+impl<S: PasswdSource> UsersRead for OSUsers<S> {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<User> {
+        self.cached_user_by_uid(uid).unwrap_or(None).map(|u| (*u).clone())
+    }
+
+    fn get_user_by_name(&self, username: &str) -> Option<User> {
+        self.cached_user_by_name(username).unwrap_or(None).map(|u| (*u).clone())
+    }
+
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Group> {
+        self.cached_group_by_gid(gid).unwrap_or(None).map(|g| g.to_group())
+    }
+
+    fn get_group_by_name(&self, group_name: &str) -> Option<Group> {
+        self.cached_group_by_name(group_name).unwrap_or(None).map(|g| g.to_group())
+    }
+
+    fn get_user_groups(&self, username: &str, gid: gid_t) -> Option<Vec<Group>> {
+        UsersRead::get_user_by_name(self, username)?;
+
+        let mut groups: Vec<Group> = Vec::new();
+        for gid in self.source.group_list(username, gid).unwrap_or_default() {
+            if groups.iter().any(|g| g.gid == gid) {
+                continue;
+            }
+
+            if let Some(group) = UsersRead::get_group_by_gid(self, gid) {
+                groups.push(group);
+            }
+        }
+
+        Some(groups)
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        match self.uid.get() {
+            Some(uid) => uid,
+            None => {
+                let uid = self.source.current_uid();
+                self.uid.set(Some(uid));
+                uid
+            }
+        }
+    }
+
+    fn get_current_username(&self) -> Option<String> {
+        let uid = UsersRead::get_current_uid(self);
+        UsersRead::get_user_by_uid(self, uid).map(|u| u.name)
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        match self.gid.get() {
+            Some(gid) => gid,
+            None => {
+                let gid = self.source.current_gid();
+                self.gid.set(Some(gid));
+                gid
+            }
+        }
+    }
+
+    fn get_current_groupname(&self) -> Option<String> {
+        let gid = UsersRead::get_current_gid(self);
+        UsersRead::get_group_by_gid(self, gid).map(|g| g.name)
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        match self.egid.get() {
+            Some(gid) => gid,
+            None => {
+                let gid = self.source.effective_gid();
+                self.egid.set(Some(gid));
+                gid
+            }
+        }
+    }
+
+    fn get_effective_groupname(&self) -> Option<String> {
+        let gid = UsersRead::get_effective_gid(self);
+        UsersRead::get_group_by_gid(self, gid).map(|g| g.name)
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        match self.euid.get() {
+            Some(uid) => uid,
+            None => {
+                let uid = self.source.effective_uid();
+                self.euid.set(Some(uid));
+                uid
+            }
+        }
+    }
+
+    fn get_effective_username(&self) -> Option<String> {
+        let uid = UsersRead::get_effective_uid(self);
+        UsersRead::get_user_by_uid(self, uid).map(|u| u.name)
+    }
+}
+
+impl<S: PasswdSource> Users for OSUsers<S> {
+    fn try_get_user_by_uid(&mut self, uid: uid_t) -> Result<Option<User>, Error> {
+        Ok(self.cached_user_by_uid(uid)?.map(|u| (*u).clone()))
+    }
+
+    fn try_get_user_by_name(&mut self, username: &str) -> Result<Option<User>, Error> {
+        Ok(self.cached_user_by_name(username)?.map(|u| (*u).clone()))
+    }
+
+    fn try_get_group_by_gid(&mut self, gid: gid_t) -> Result<Option<Group>, Error> {
+        Ok(self.cached_group_by_gid(gid)?.map(|g| g.to_group()))
+    }
+
+    fn try_get_group_by_name(&mut self, group_name: &str) -> Result<Option<Group>, Error> {
+        Ok(self.cached_group_by_name(group_name)?.map(|g| g.to_group()))
+    }
+
+    fn try_get_user_groups(&mut self, username: &str, gid: gid_t) -> Result<Option<Vec<Group>>, Error> {
+        if self.try_get_user_by_name(username)?.is_none() {
+            return Ok(None);
+        }
+
+        let mut groups: Vec<Group> = Vec::new();
+        for gid in self.source.group_list(username, gid).unwrap_or_default() {
+            if groups.iter().any(|g| g.gid == gid) {
+                continue;
+            }
+
+            if let Some(group) = self.try_get_group_by_gid(gid)? {
+                groups.push(group);
+            }
+        }
+
+        Ok(Some(groups))
+    }
+
+    fn get_user_by_uid_rc(&mut self, uid: uid_t) -> Option<Rc<User>> {
+        self.cached_user_by_uid(uid).unwrap_or(None)
+    }
+
+    fn get_user_by_name_rc(&mut self, username: &str) -> Option<Rc<User>> {
+        self.cached_user_by_name(username).unwrap_or(None)
+    }
+
+    fn get_user_by_name_os(&mut self, username: &OsStr) -> Option<User> {
+        // The by-name cache is keyed by String, so only the by-ID cache
+        // can hold on to this result.
+        self.trace(CacheEvent::LibcCall { which: "getpwnam_r" });
+        let user = lookup_user_by_name(&self.source, username).unwrap_or(None).map(Rc::new);
+        if let Some(ref user) = user {
+            self.users.borrow_mut().insert(user.uid, (Some(user.clone()), Instant::now()));
+        }
+
+        user.map(|u| (*u).clone())
+    }
+
+    fn get_group_by_name_os(&mut self, group_name: &OsStr) -> Option<Group> {
+        self.trace(CacheEvent::LibcCall { which: "getgrnam_r" });
+        let group = lookup_group_by_name(&self.source, group_name).unwrap_or(None);
+        if let Some(ref group) = group {
+            let cached = self.cache_group(group.clone());
+            self.groups.borrow_mut().insert(group.gid, (Some(cached), Instant::now()));
+        }
+
+        group
+    }
+
+    fn get_group_by_gid(&mut self, gid: gid_t) -> Option<Group> {
+        UsersRead::get_group_by_gid(self, gid)
+    }
+
+    fn get_group_by_name(&mut self, group_name: &str) -> Option<Group> {
+        UsersRead::get_group_by_name(self, group_name)
+    }
+
+    fn get_user_groups(&mut self, username: &str, gid: gid_t) -> Option<Vec<Group>> {
+        UsersRead::get_user_groups(self, username, gid)
+    }
+
+    fn get_all_users_by_uid(&mut self, uid: uid_t) -> Vec<User> {
+        self.all_users().filter(|u| u.uid == uid).collect()
+    }
+
+    fn get_user_by_name_ci(&mut self, username: &str) -> Option<User> {
+        self.get_user_by_name(username).or_else(|| {
+            self.all_users().find(|u| u.name.eq_ignore_ascii_case(username))
+        })
+    }
+
+    fn count_user_groups(&mut self, username: &str, gid: gid_t) -> usize {
+        self.source.group_count(username, gid)
+    }
+
+    fn supplementary_gids_for(&mut self, username: &str, primary_gid: gid_t) -> Vec<gid_t> {
+        self.source.group_list(username, primary_gid).unwrap_or_else(|| vec![ primary_gid ])
+    }
+
+    fn users_in_range(&mut self, range: Range<uid_t>) -> Vec<User> {
+        self.all_users().filter(|u| range.contains(&u.uid)).collect()
+    }
+
+    fn users_with_shell(&mut self, shell: &Path) -> Vec<User> {
+        self.all_users().filter(|u| u.shell == shell).collect()
+    }
+
+    fn validate_integrity(&mut self) -> Vec<IntegrityIssue> {
+        let users: Vec<User> = self.all_users().collect();
+        let groups: Vec<Group> = self.all_groups().collect();
+        check_integrity(&users, &groups)
+    }
+
+    fn users_without_login_shell(&mut self) -> Vec<User> {
+        self.all_users().filter(|u| is_nologin_shell(&u.shell)).collect()
+    }
+
+    fn snapshot_users(&mut self) -> HashMap<uid_t, User> {
+        let mut users = HashMap::new();
+        for user in self.all_users() {
+            users.entry(user.uid).or_insert(user);
+        }
+        users
+    }
+
+    fn snapshot_groups(&mut self) -> HashMap<gid_t, Group> {
+        let mut groups = HashMap::new();
+        for group in self.all_groups() {
+            groups.entry(group.gid).or_insert(group);
+        }
+        groups
+    }
+
+    fn get_all_usernames(&mut self) -> Vec<String> {
+        self.all_users().map(|u| u.name).collect()
+    }
+
+    fn get_all_group_names(&mut self) -> Vec<String> {
+        self.all_groups().map(|g| g.name).collect()
+    }
+
+    fn get_group_members(&mut self, gid: gid_t) -> Vec<User> {
+        let members = UsersRead::get_group_by_gid(self, gid).map(|g| g.members).unwrap_or_default();
+        self.all_users().filter(|u| u.primary_group == gid || members.contains(&u.name)).collect()
+    }
+
+    fn get_group_member_uids(&mut self, gid: gid_t) -> Vec<uid_t> {
+        let hook = self.trace_hook.clone();
+        group_member_uids(self, gid, |gid| if let Some(ref hook) = hook { hook(CacheEvent::UnresolvedMember { gid }) })
+    }
+
+    fn is_user_in_group(&mut self, username: &str, group_name: &str) -> bool {
+        let user = match self.get_user_by_name_rc(username) {
+            Some(u) => u,
+            None => return false,
+        };
+
+        let gid = match UsersRead::get_group_by_name(self, group_name) {
+            Some(g) => g.gid,
+            None => return false,
+        };
+
+        // The source already knows about every group the user's in, so
+        // there's no need to look at each group's member list.
+        self.source.group_list(username, user.primary_group).is_some_and(|gids| gids.contains(&gid))
+    }
+
+    fn get_current_uid(&mut self) -> uid_t {
+        UsersRead::get_current_uid(self)
+    }
+
+    fn get_current_username(&mut self) -> Option<String> {
+        UsersRead::get_current_username(self)
+    }
+
+    fn get_current_gid(&mut self) -> gid_t {
+        UsersRead::get_current_gid(self)
+    }
+
+    fn get_current_groupname(&mut self) -> Option<String> {
+        UsersRead::get_current_groupname(self)
+    }
+
+    fn get_effective_uid(&mut self) -> uid_t {
+        UsersRead::get_effective_uid(self)
+    }
+
+    fn get_effective_gid(&mut self) -> gid_t {
+        UsersRead::get_effective_gid(self)
+    }
+
+    fn get_effective_username(&mut self) -> Option<String> {
+        UsersRead::get_effective_username(self)
+    }
+
+    fn get_effective_groupname(&mut self) -> Option<String> {
+        UsersRead::get_effective_groupname(self)
+    }
+
+    fn current_process_groups(&mut self) -> Vec<gid_t> {
+        self.source.process_groups()
+    }
+
+    fn get_login_name(&mut self) -> Option<String> {
+        self.source.login_name()
+    }
+
+    fn get_audit_login_uid(&mut self) -> Option<uid_t> {
+        if let Some(uid) = self.source.audit_login_uid() {
+            return Some(uid);
+        }
+
+        let name = self.source.login_name()?;
+        self.get_user_id_by_name(&name).map(|id| id.uid)
+    }
+}
+
+/// An iterator over every user in the users database, returned by
+/// `OSUsers::all_users`.
+///
+/// With libc as the source, this uses `getpwent`, which keeps its position
+/// in global state, so only one of these iterators should be alive at once.
+/// Borrowing the `OSUsers` object mutably enforces this for a single cache,
+/// but nothing stops two separate caches from iterating at the same time.
+pub struct AllUsers<'a> {
+    users: Box<dyn Iterator<Item = User> + 'a>,
+}
+
+impl<'a> Iterator for AllUsers<'a> {
+    type Item = User;
+
+    fn next(&mut self) -> Option<User> {
+        self.users.next()
+    }
+}
+
+/// An iterator over every group in the groups database, returned by
+/// `OSUsers::all_groups`.
+///
+/// As with `AllUsers`, libc uses `getgrent` for this, so only one of these
+/// iterators should be alive at once.
+pub struct AllGroups<'a> {
+    groups: Box<dyn Iterator<Item = Group> + 'a>,
+}
+
+impl<'a> Iterator for AllGroups<'a> {
+    type Item = Group;
+
+    fn next(&mut self) -> Option<Group> {
+        self.groups.next()
+    }
+}
+
+/// Pair each user with their groups: their primary group first, if it
+/// exists, and then every other group that lists them as a member.
+///
+/// The groups are indexed by ID and by member name in one pass beforehand,
+/// so this takes time in proportion to the number of users plus the number
+/// of group memberships, rather than looking up each user's groups in turn.
+fn pair_with_groups<I>(users: I, groups: Vec<Group>) -> Vec<(User, Vec<Group>)>
+where I: Iterator<Item = User> {
+    let mut by_gid: HashMap<gid_t, usize> = HashMap::new();
+    let mut by_member: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, group) in groups.iter().enumerate() {
+        by_gid.entry(group.gid).or_insert(index);
+        for member in &group.members {
+            by_member.entry(&member[..]).or_default().push(index);
+        }
+    }
+
+    users.map(|user| {
+        let mut indices: Vec<usize> = by_gid.get(&user.primary_group).cloned().into_iter().collect();
+        for &index in by_member.get(&user.name[..]).map_or(&[][..], |v| &v[..]) {
+            if !indices.iter().any(|&i| groups[i].gid == groups[index].gid) {
+                indices.push(index);
+            }
+        }
+
+        let user_groups = indices.into_iter().map(|i| groups[i].clone()).collect();
+        (user, user_groups)
+    }).collect()
+}
+
+impl<S: PasswdSource> OSUsers<S> {
+    /// Return an iterator over every user in the users database.
+    ///
+    /// The users aren't added to the cache.
+    pub fn all_users(&mut self) -> AllUsers<'_> {
+        AllUsers { users: self.source.all_users() }
+    }
+
+    /// Return an iterator over every group in the groups database, with
+    /// their member lists.
+    ///
+    /// The groups aren't added to the cache.
+    pub fn all_groups(&mut self) -> AllGroups<'_> {
+        AllGroups { groups: self.source.all_groups() }
+    }
+
+    /// Return every user in the users database, each with the groups
+    /// they're a member of, primary group first.
+    ///
+    /// This reads the users and groups databases once each, rather than
+    /// asking for every user's groups in turn, which is much faster when
+    /// reporting on all of them. Neither users nor groups are added to the
+    /// cache.
+    pub fn users_with_groups(&mut self) -> Vec<(User, Vec<Group>)> {
+        let groups = self.all_groups().collect();
+        pair_with_groups(self.all_users(), groups)
+    }
+
+    /// Set whether lookups that find nothing are cached, so that looking up
+    /// the same missing user or group again doesn't go back to the
+    /// database. This is off by default, so users added while the object is
+    /// in use are still found. Turn it on when the same IDs are looked up
+    /// over and over, such as when listing files owned by users that have
+    /// since been deleted. Cached misses expire with the TTL in the same way
+    /// as other entries.
+    pub fn with_negative_caching(mut self, enabled: bool) -> OSUsers<S> {
+        self.negative_caching = enabled;
+        self
+    }
+
+    /// Set whether the names in the cache are interned, so that each user
+    /// or group name is stored once, however many groups list it, and every
+    /// `Rc` returned by `get_username_rc` and `get_group_name_rc` for the
+    /// same name points to the same string, however many times it's asked
+    /// for. This is off by default. It's for programs that keep the name of
+    /// every user's group for a large number of users, where most of them
+    /// will be in the same few groups. The interned names are kept until
+    /// `flush` is called.
+    ///
+    /// Either way, groups are cached with their names and member lists as
+    /// shared strings, and are only turned into `Group` values, with
+    /// `String` copies of the names, when they're returned.
+    pub fn with_interning(mut self, enabled: bool) -> OSUsers<S> {
+        self.names = if enabled { Some(RefCell::new(HashSet::new())) } else { None };
+        self
+    }
+
+    /// Return the name of the user with the given ID as a shared string if
+    /// they exist; otherwise, return None. See `with_interning`.
+    pub fn get_username_rc(&mut self, uid: uid_t) -> Option<Rc<str>> {
+        self.get_user_by_uid_rc(uid).map(|u| self.intern(&u.name))
+    }
+
+    /// Return the name of the group with the given ID as a shared string if
+    /// it exists; otherwise, return None. See `with_interning`.
+    pub fn get_group_name_rc(&mut self, gid: gid_t) -> Option<Rc<str>> {
+        self.cached_group_by_gid(gid).unwrap_or(None).map(|g| g.name)
+    }
+
+    /// Call the given function with every cache hit and miss, and every
+    /// call to libc, from now on. This is for finding out why lookups are
+    /// slow, such as when the same entries keep being read from a network
+    /// service. Without a hook, nothing is recorded.
+    pub fn set_trace_hook(&mut self, hook: Box<dyn Fn(CacheEvent)>) {
+        self.trace_hook = Some(Rc::from(hook));
+    }
+
+    /// Stop calling the trace hook.
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    /// Create a new empty OS Users object that reads users and groups
+    /// from the given source instead of from libc.
+    pub fn with_source(source: S) -> OSUsers<S> {
+        OSUsers {
+            source,
+            users:            RefCell::new(HashMap::new()),
+            users_back:       RefCell::new(HashMap::new()),
+            groups:           RefCell::new(HashMap::new()),
+            groups_back:      RefCell::new(HashMap::new()),
+            ttl:              None,
+            negative_caching: false,
+            trace_hook:       None,
+            names:            None,
+            #[cfg(all(feature = "watch", target_os = "linux"))]
+            watch:            None,
+            uid:              Cell::new(None),
+            gid:              Cell::new(None),
+            euid:             Cell::new(None),
+            egid:             Cell::new(None),
+        }
+    }
+
+    /// Forget every user and group in the cache, including the ones that
+    /// were looked up and not found. The next lookup of each of them reads
+    /// it from the database again.
+    pub fn flush(&mut self) {
+        self.users.borrow_mut().clear();
+        self.users_back.borrow_mut().clear();
+        self.groups.borrow_mut().clear();
+        self.groups_back.borrow_mut().clear();
+        if let Some(ref names) = self.names {
+            names.borrow_mut().clear();
+        }
+    }
+
+    /// Forget the user with the given ID, so the next lookup reads it from
+    /// the database again, whether by ID or by name.
+    pub fn forget_user(&mut self, uid: uid_t) {
+        self.users.borrow_mut().remove(&uid);
+        self.users_back.borrow_mut().retain(|_, &mut (u, _)| u != Some(uid));
+    }
+
+    /// Watch `/etc/passwd` and `/etc/group` for changes, and forget every
+    /// cached user or group when the file they came from changes, so that
+    /// edits made with tools such as `usermod` are picked up without
+    /// calling `flush`.
+    ///
+    /// The watching is done by a background thread using inotify, and is
+    /// checked at the start of each lookup. Only changes to the files are
+    /// noticed: users and groups that come from another NSS source, such as
+    /// LDAP or `systemd-userdbd`, can change without this knowing.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub fn watch(&mut self) -> io::Result<()> {
+        self.watch_files(Path::new("/etc/passwd"), Path::new("/etc/group"))
+    }
+
+    /// Watch the given passwd- and group-format files for changes, as
+    /// `watch` does for the system ones.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub fn watch_files(&mut self, passwd: &Path, group: &Path) -> io::Result<()> {
+        self.watch = Some(watch::Watch::start(passwd, group)?);
+        Ok(())
+    }
+
+    /// Forget the group with the given ID, so the next lookup reads it from
+    /// the database again, whether by ID or by name.
+    pub fn forget_group(&mut self, gid: gid_t) {
+        self.groups.borrow_mut().remove(&gid);
+        self.groups_back.borrow_mut().retain(|_, &mut (g, _)| g != Some(gid));
+    }
+}
+
+impl OSUsers {
+    /// Create a new empty OS Users object whose entries expire once they've
+    /// been in the cache for longer than the given duration. Looking up an
+    /// expired entry reads it from the database again.
+    pub fn with_ttl(ttl: Duration) -> OSUsers {
+        OSUsers { ttl: Some(ttl), .. OSUsers::empty_cache() }
+    }
+
+    /// Create a new empty OS Users object.
+    pub fn empty_cache() -> OSUsers {
+        OSUsers::with_source(LibcSource)
+    }
+}
+
+impl OSUsers<TimeoutSource<LibcSource>> {
+    /// Create a new empty OS Users object that stops waiting for libc once
+    /// a lookup has taken longer than the given duration. See
+    /// `TimeoutSource` for what happens to the lookup after that.
+    ///
+    /// A lookup that times out isn't cached, so the next one for the same
+    /// user or group tries again.
+    pub fn with_lookup_timeout(timeout: Duration) -> OSUsers<TimeoutSource<LibcSource>> {
+        OSUsers::with_source(TimeoutSource::new(LibcSource, timeout))
+    }
+}
+
+#[cfg(feature = "files")]
+impl OSUsers<PreferFiles> {
+    /// Create a new empty OS Users object that reads `/etc/passwd` and
+    /// `/etc/group` itself, and only uses libc if they're missing. See
+    /// `PreferFiles` for when this is useful, and what it misses.
+    pub fn prefer_files() -> OSUsers<PreferFiles> {
+        OSUsers::with_source(PreferFiles::open("/etc/passwd", "/etc/group"))
+    }
+}
+
+/// Return a User object if one exists for the given user ID; otherwise, return None.
+pub fn get_user_by_uid(uid: uid_t) -> Option<User> {
+    OSUsers::empty_cache().get_user_by_uid(uid)
+}
+
+/// Return a User object if one exists for the given username; otherwise, return None.
+pub fn get_user_by_name(username: &str) -> Option<User> {
+    OSUsers::empty_cache().get_user_by_name(username)
+}
+
+/// Return a Group object if one exists for the given group ID; otherwise, return None.
+pub fn get_group_by_gid(gid: gid_t) -> Option<Group> {
+    OSUsers::empty_cache().get_group_by_gid(gid)
+}
+
+/// Return a Group object if one exists for the given groupname; otherwise, return None.
+pub fn get_group_by_name(group_name: &str) -> Option<Group> {
+    OSUsers::empty_cache().get_group_by_name(group_name)
+}
+
+/// Return the user for a user ID or username, as given to a tool such as
+/// `chown`; otherwise, return None. See `Users::resolve_user` for which wins
+/// when a number could be either.
+pub fn resolve_user(spec: &str) -> Option<User> {
+    OSUsers::empty_cache().resolve_user(spec)
+}
+
+/// Return the group for a group ID or group name, as given to a tool such
+/// as `chgrp`; otherwise, return None. A number that isn't any group's ID,
+/// or the name of one, gives None rather than an error.
+pub fn resolve_group(spec: &str) -> Option<Group> {
+    OSUsers::empty_cache().resolve_group(spec)
+}
+
+/// Return the user ID for the user running the process.
+pub fn get_current_uid() -> uid_t {
+    OSUsers::empty_cache().get_current_uid()
+}
+
+/// Return the username of the user running the process.
+pub fn get_current_username() -> Option<String> {
+    OSUsers::empty_cache().get_current_username()
+}
+
+/// Return the user ID for the effective user running the process.
+pub fn get_effective_uid() -> uid_t {
+    OSUsers::empty_cache().get_effective_uid()
+}
+
+/// Return the username of the effective user running the process.
+pub fn get_effective_username() -> Option<String> {
+    OSUsers::empty_cache().get_effective_username()
+}
+
+/// Return the name of the user logged in on the process's controlling
+/// terminal. See `Users::get_login_name` for how this differs from
+/// `get_current_username`.
+pub fn get_login_name() -> Option<String> {
+    login_name()
+}
+
+/// Return the process's file mode creation mask, the permission bits that
+/// are taken away from every file or directory it creates. A file created
+/// with mode `0o666` ends up with `0o666 & !current_umask()`.
+///
+/// There's no way to read the mask without setting it, so this sets it to
+/// zero and then straight back. The mask belongs to the whole process, so
+/// any file another thread creates in between gets created without one;
+/// don't call this while other threads might be creating files.
+///
+/// ```
+/// let mode = 0o666 & !users::current_umask();
+/// println!("New files get mode {:o}", mode);
+/// ```
+pub fn current_umask() -> u32 {
+    unsafe {
+        let mask = umask(0);
+        umask(mask);
+        mask as u32
+    }
+}
+
+/// Return whether the process is running with root privileges.
+pub fn running_as_root() -> bool {
+    get_effective_uid() == 0
+}
+
+/// Return the group ID for the user running the process.
+pub fn get_current_gid() -> gid_t {
+    OSUsers::empty_cache().get_current_gid()
+}
+
+/// Return the groupname of the user running the process.
+pub fn get_current_groupname() -> Option<String> {
+    OSUsers::empty_cache().get_current_groupname()
+}
+
+/// Return the group ID for the effective user running the process.
+pub fn get_effective_gid() -> gid_t {
+    OSUsers::empty_cache().get_effective_gid()
+}
+
+/// Return the groupname of the effective user running the process.
+pub fn get_effective_groupname() -> Option<String> {
+    OSUsers::empty_cache().get_effective_groupname()
+}
+
+/// Set current user for the running process, requires root priviledges.
+pub fn set_current_uid(uid: uid_t) -> Result<(), io::Error> {
+    match unsafe { setuid(uid) } {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        _ => unreachable!()
+    }
+}
+
+/// Set current group for the running process, requires root priviledges.
+pub fn set_current_gid(gid: gid_t) -> Result<(), io::Error> {
+    match unsafe { setgid(gid) } {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        _ => unreachable!()
+    }
+}
+
+/// Set effective user for the running process, requires root priviledges.
+pub fn set_effective_uid(uid: uid_t) -> Result<(), io::Error> {
+    match unsafe { seteuid(uid) } {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        _ => unreachable!()
+    }
+}
+
+/// Set effective user for the running process, requires root priviledges.
+pub fn set_effective_gid(gid: gid_t) -> Result<(), io::Error> {
+    match unsafe { setegid(gid) } {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        _ => unreachable!()
+    }
+}
+
+/// Atomically set current and effective user for the running process, requires root priviledges.
+pub fn set_both_uid(ruid: uid_t, euid: uid_t) -> Result<(), io::Error> {
+    match unsafe { setreuid(ruid, euid) } {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        _ => unreachable!()
+    }
+}
+
+/// Atomically set current and effective group for the running process, requires root priviledges.
+pub fn set_both_gid(rgid: gid_t, egid: gid_t) -> Result<(), io::Error> {
+    match unsafe { setregid(rgid, egid) } {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        _ => unreachable!()
+    }
+}
+
+pub struct SwitchUserGuard {
+    uid: uid_t,
+    gid: gid_t,
+}
+
+impl Drop for SwitchUserGuard {
+    fn drop(&mut self) {
+        // Panic on error here, as failing to set values back
+        // is a possible security breach.
+        set_effective_uid(self.uid).unwrap();
+        set_effective_gid(self.gid).unwrap();
+    }
+}
+
+/// Safely switch user and group for the current scope.
+/// Requires root access.
+///
+/// ```ignore
+/// {
+///     let _guard = switch_user_group(1001, 1001);
+///     // current and effective user and group ids are 1001
+/// }
+/// // back to the old values
+/// ```
+///
+/// Use with care! Possible security issues can happen, as Rust doesn't
+/// guarantee running the destructor! If in doubt run `drop()` method
+/// on the guard value manually!
+pub fn switch_user_group(uid: uid_t, gid: gid_t) -> Result<SwitchUserGuard, io::Error> {
+    let current_state = SwitchUserGuard {
+        uid: get_effective_uid(),
+        gid: get_effective_gid(),
+    };
+
+    // The group has to be changed first, because changing it after giving
+    // up root isn't allowed.
+    set_effective_gid(gid)?;
+    set_effective_uid(uid)?;
+    Ok(current_state)
+}
+
+/// A guard that puts the effective user and group IDs back to what they
+/// were before `raise_privileges` was called when it's dropped.
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+pub struct PrivilegeGuard {
+    uid: uid_t,
+    gid: gid_t,
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+impl Drop for PrivilegeGuard {
+    fn drop(&mut self) {
+        // The group goes back first, while the process still has the
+        // privileges to change it. Panic on error, as staying privileged
+        // is a possible security breach.
+        set_effective_gid(self.gid).unwrap();
+        set_effective_uid(self.uid).unwrap();
+    }
+}
+
+/// Temporarily raise the effective user and group IDs of the running
+/// process to its saved set-user-ID and set-group-ID, for the lifetime of
+/// the returned guard.
+///
+/// This is for setuid programs that give up their privileges early on, but
+/// need them back for short stretches:
+///
+/// ```ignore
+/// {
+///     let _guard = raise_privileges()?;
+///     // effective user and group IDs are the saved ones
+/// }
+/// // back to the unprivileged ones
+/// ```
+///
+/// The same caveat applies as for `switch_user_group`: the previous IDs are
+/// only restored if the guard's destructor runs, which it does on early
+/// returns and panics, but not if the guard is leaked.
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+pub fn raise_privileges() -> Result<PrivilegeGuard, io::Error> {
+    let (mut ruid, mut euid, mut suid) = (0, 0, 0);
+    let (mut rgid, mut egid, mut sgid) = (0, 0, 0);
+
+    unsafe {
+        if getresuid(&mut ruid, &mut euid, &mut suid) == -1 || getresgid(&mut rgid, &mut egid, &mut sgid) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let guard = PrivilegeGuard { uid: euid, gid: egid };
+
+    // The user ID has to be raised first, as raising the group ID may need
+    // the privileges it brings.
+    set_effective_uid(suid)?;
+    if let Err(e) = set_effective_gid(sgid) {
+        set_effective_uid(euid)?;
+        mem::forget(guard);
+        return Err(e);
+    }
+
+    Ok(guard)
+}
+
+/// Permanently switch the current and effective user and group of the
+/// running process to the given IDs. Requires root access.
+///
+/// Unlike `switch_user_group`, there's no way back: once the user ID has
+/// been changed away from root, the process can't regain its privileges.
+/// The group is changed first, since that isn't allowed afterwards.
+pub fn switch_user_group_permanently(uid: uid_t, gid: gid_t) -> Result<(), io::Error> {
+    set_current_gid(gid)?;
+    set_current_uid(uid)
+}
+
+/// Permanently drop the privileges of the running process to those of the
+/// user with the given name: their user ID, their primary group, and their
+/// supplementary groups. Requires root access.
+///
+/// This can't be undone. It's meant for daemons that start as root to do
+/// some setup, and then run as an unprivileged user.
+pub fn drop_privileges(username: &str) -> Result<(), io::Error> {
+    let mut users = OSUsers::empty_cache();
+    let user = match users.try_get_user_by_name(username)? {
+        Some(user) => user,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such user")),
+    };
+
+    let groups = users.supplementary_gids_for(username, user.primary_group);
+    if unsafe { setgroups(groups.len() as _, groups.as_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    switch_user_group_permanently(user.uid, user.primary_group)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Users, OSUsers, get_current_username};
+    use std::rc::Rc;
+
+    #[test]
+    fn uid() {
+        OSUsers::empty_cache().get_current_uid();
+    }
+
+    #[test]
+    fn cow_lookups_own() {
+        use super::UsersRead;
+        use std::borrow::Cow;
+
+        let users = OSUsers::empty_cache();
+        assert!(matches!(UsersRead::get_user_by_uid_cow(&users, 0), Some(Cow::Owned(ref u)) if u.uid == 0));
+    }
+
+    #[test]
+    fn effective_ids() {
+        // These only differ in a setuid or setgid process, so all we can
+        // check is that each one comes from its own call.
+        let mut users = OSUsers::empty_cache();
+        assert_eq!(users.get_current_uid(), unsafe { ::libc::getuid() });
+        assert_eq!(users.get_current_gid(), unsafe { ::libc::getgid() });
+        assert_eq!(users.get_effective_uid(), unsafe { ::libc::geteuid() });
+        assert_eq!(users.get_effective_gid(), unsafe { ::libc::getegid() });
+    }
+
+    #[test]
+    fn current_user() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let euid = users.get_effective_uid();
+        assert_eq!(users.get_current_user().map(|u| u.uid), Some(uid));
+        assert_eq!(users.get_effective_user().map(|u| u.uid), Some(euid));
+    }
+
+    #[test]
+    fn is_root() {
+        let mut users = OSUsers::empty_cache();
+        let euid = unsafe { ::libc::geteuid() };
+        assert_eq!(users.is_root(), euid == 0);
+        assert_eq!(super::running_as_root(), euid == 0);
+    }
+
+    #[test]
+    fn effective_username() {
+        let mut users = OSUsers::empty_cache();
+        let euid = users.get_effective_uid();
+        let name = users.get_effective_username().unwrap();
+        assert_eq!(users.users.borrow()[&euid].0.as_ref().map(|u| u.name.clone()), Some(name));
+    }
+
+    #[test]
     fn username() {
         let mut users = OSUsers::empty_cache();
         let uid = users.get_current_uid();
-        assert_eq!(get_current_username().unwrap(), users.get_user_by_uid(uid).unwrap().name);
+        assert_eq!(get_current_username().unwrap(), users.get_user_by_uid(uid).unwrap().name);
+    }
+
+    #[test]
+    fn uid_for_username() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let user = users.get_user_by_uid(uid).unwrap();
+        assert_eq!(user.uid, uid);
+    }
+
+    #[test]
+    fn username_for_uid_for_username() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let user = users.get_user_by_uid(uid).unwrap();
+        let user2 = users.get_user_by_uid(user.uid).unwrap();
+        assert_eq!(user2.uid, uid);
+    }
+
+    #[test]
+    fn shared_user() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let user = users.get_user_by_uid_rc(uid).unwrap();
+        let again = users.get_user_by_name_rc(&user.name).unwrap();
+        assert!(Rc::ptr_eq(&user, &again));
+    }
+
+    #[test]
+    fn read_only_lookups() {
+        use super::UsersRead;
+
+        let users = Rc::new(OSUsers::empty_cache());
+        let uid = users.get_current_uid();
+        let user = users.get_user_by_uid(uid).unwrap();
+        assert_eq!(users.get_user_by_name(&user.name).map(|u| u.uid), Some(uid));
+        assert!(users.users.borrow().contains_key(&uid));
+    }
+
+    #[test]
+    fn user_info() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let user = users.get_user_by_uid(uid).unwrap();
+        // Not a real test but can be used to verify correct results
+        // Use with --nocapture on test executable to show output
+        println!("HOME={}, SHELL={}", user.home_dir.display(), user.shell.display());
+        assert!(user.gecos.starts_with(&user.full_name));
     }
 
     #[test]
-    fn uid_for_username() {
+    fn gecos_full_name() {
+        assert_eq!(super::gecos_full_name("Fred Bobbins,Room 1,555-1234"), "Fred Bobbins");
+        assert_eq!(super::gecos_full_name("Fred Bobbins"), "Fred Bobbins");
+        assert_eq!(super::gecos_full_name(""), "");
+    }
+
+    #[test]
+    fn locked() {
         let mut users = OSUsers::empty_cache();
         let uid = users.get_current_uid();
-        let user = users.get_user_by_uid(uid).unwrap();
-        assert_eq!(user.uid, uid);
+        let mut user = users.get_user_by_uid(uid).unwrap();
+
+        for passwd in &["*", "!", "!!"] {
+            user.passwd = passwd.to_string();
+            assert!(user.is_locked());
+        }
+
+        for passwd in &["x", "", "$6$salt$hash"] {
+            user.passwd = passwd.to_string();
+            assert!(!user.is_locked());
+        }
     }
 
     #[test]
-    fn username_for_uid_for_username() {
+    fn group_password() {
+        use super::Group;
+
+        let group: Group = "staff:$6$salt$hash:50:fred".parse().unwrap();
+        assert_eq!(group.passwd, "$6$salt$hash");
+        assert!(group.has_password());
+
+        for passwd in &["x", "", "*", "!"] {
+            assert!(!Group::builder(50, "staff").passwd(passwd).build().has_password());
+        }
+    }
+
+    #[test]
+    fn system_and_human_accounts() {
+        use super::{User, DEFAULT_UID_MIN, NOBODY_UID};
+
+        let root = User::builder(0, "root").build();
+        let www = User::builder(33, "www-data").build();
+        let fred = User::builder(1000, "fred").build();
+        let nobody = User::builder(NOBODY_UID, "nobody").build();
+
+        assert!(root.is_system_account());
+        assert!(root.is_system_account_below(DEFAULT_UID_MIN));
+        assert!(www.is_system_account_below(DEFAULT_UID_MIN));
+        assert!(!fred.is_system_account_below(DEFAULT_UID_MIN));
+
+        assert!(!root.is_human_account_above(DEFAULT_UID_MIN));
+        assert!(!www.is_human_account_above(DEFAULT_UID_MIN));
+        assert!(fred.is_human_account_above(DEFAULT_UID_MIN));
+        assert!(!nobody.is_human_account_above(DEFAULT_UID_MIN));
+
+        assert!(www.is_human_account_above(10));
+        assert!(!fred.is_human_account_above(2000));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        use super::User;
+        use super::mock::MockUsers;
+        use super::serde_json;
+
         let mut users = OSUsers::empty_cache();
         let uid = users.get_current_uid();
         let user = users.get_user_by_uid(uid).unwrap();
-        let user2 = users.get_user_by_uid(user.uid).unwrap();
-        assert_eq!(user2.uid, uid);
+
+        let json = serde_json::to_string(&user).unwrap();
+        assert!(json.contains(&format!("\"uid\":{}", uid)));
+
+        let mut mock = MockUsers::with_current_uid(uid);
+        mock.add_user(serde_json::from_str::<User>(&json).unwrap());
+        assert_eq!(mock.get_current_username(), Some(user.name));
     }
 
     #[test]
-    fn user_info() {
+    fn non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let raw = b"/home/fr\xe9d\0";
+        let path = unsafe { super::from_raw_path(raw.as_ptr() as *const _) };
+        assert_eq!(path.as_os_str().as_bytes(), b"/home/fr\xe9d");
+    }
+
+    #[test]
+    fn get_user_by_name_os() {
+        use std::ffi::OsStr;
+
         let mut users = OSUsers::empty_cache();
-        let uid = users.get_current_uid();
-        let user = users.get_user_by_uid(uid).unwrap();
-        // Not a real test but can be used to verify correct results
-        // Use with --nocapture on test executable to show output
-        println!("HOME={}, SHELL={}", user.home_dir, user.shell);
+        let name = users.get_current_username().unwrap();
+        let user = users.get_user_by_name_os(OsStr::new(&name)).unwrap();
+        assert_eq!(user.name_os, OsStr::new(&name));
+        assert!(users.get_user_by_name_os(OsStr::new("user\0")).is_none());
+    }
+
+    #[test]
+    fn non_utf8_name() {
+        let raw = b"fr\xffd\0";
+        assert_eq!(unsafe { super::from_raw_buf(raw.as_ptr() as *const _) }, "fr\u{FFFD}d");
+    }
+
+    #[test]
+    fn gecos_subfields() {
+        use super::Gecos;
+
+        let gecos = Gecos::parse("Fred B,Room 1,555-1234,,misc");
+        assert_eq!(gecos.full_name, "Fred B");
+        assert_eq!(gecos.room, "Room 1");
+        assert_eq!(gecos.work_phone, "555-1234");
+        assert_eq!(gecos.home_phone, "");
+        assert_eq!(gecos.other, "misc");
+
+        let gecos = Gecos::parse("Fred B,Room 1");
+        assert_eq!(gecos.room, "Room 1");
+        assert_eq!(gecos.work_phone, "");
+        assert_eq!(gecos.other, "");
+
+        assert_eq!(Gecos::parse("a,b,c,d,e,f").other, "e,f");
+        assert_eq!(Gecos::parse(""), Gecos::default());
+    }
+
+    #[test]
+    fn empty_gecos() {
+        assert_eq!(unsafe { super::from_raw_buf(::std::ptr::null()) }, "");
     }
 
     #[test]
@@ -728,6 +3222,525 @@ mod test {
         assert!(user.is_none());
     }
 
+    #[test]
+    fn get_user_primary_group() {
+        let mut users = OSUsers::empty_cache();
+        let cur_uid = users.get_current_uid();
+        let cur_user = users.get_user_by_uid(cur_uid).unwrap();
+        let group = users.get_user_primary_group(&cur_user);
+        assert_eq!(group.map(|g| g.gid), Some(cur_user.primary_group));
+    }
+
+    #[test]
+    fn get_group_members() {
+        let mut users = OSUsers::empty_cache();
+        let cur_uid = users.get_current_uid();
+        let cur_user = users.get_user_by_uid(cur_uid).unwrap();
+        let members = users.get_group_members(cur_user.primary_group);
+
+        assert_eq!(members.iter().filter(|u| u.uid == cur_uid).count(), 1);
+    }
+
+    #[test]
+    fn get_all_users_by_uid() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let all = users.get_all_users_by_uid(uid);
+        assert_eq!(all.first().map(|u| u.name.clone()), users.get_current_username());
+        assert!(all.iter().all(|u| u.uid == uid));
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let gid = users.get_current_gid();
+        assert_eq!(users.snapshot_users().get(&uid).map(|u| u.uid), Some(uid));
+        assert_eq!(users.snapshot_groups().get(&gid).map(|g| g.gid), Some(gid));
+    }
+
+    #[test]
+    fn get_user_by_name_ci() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let name = users.get_current_username().unwrap();
+        assert_eq!(users.get_user_by_name_ci(&name.to_ascii_uppercase()).map(|u| u.uid), Some(uid));
+        assert!(users.get_user_by_name_ci("no such user, surely").is_none());
+    }
+
+    #[test]
+    fn get_user_groups() {
+        let mut users = OSUsers::empty_cache();
+        let cur_uid = users.get_current_uid();
+        let cur_user = users.get_user_by_uid(cur_uid).unwrap();
+        let groups = users.get_user_groups(&cur_user.name, cur_user.primary_group).unwrap();
+
+        assert!(groups.iter().any(|g| g.gid == cur_user.primary_group));
+        assert_eq!(groups.iter().filter(|g| g.gid == cur_user.primary_group).count(), 1);
+    }
+
+    #[test]
+    fn get_user_groups_missing_user() {
+        let mut users = OSUsers::empty_cache();
+        assert!(users.get_user_groups("no such user, surely", 0).is_none());
+    }
+
+    #[test]
+    fn is_user_in_group() {
+        let mut users = OSUsers::empty_cache();
+        let cur_uid = users.get_current_uid();
+        let cur_user = users.get_user_by_uid(cur_uid).unwrap();
+        let cur_group = users.get_group_by_gid(cur_user.primary_group).unwrap();
+
+        assert!(users.is_user_in_group(&cur_user.name, &cur_group.name));
+        assert!(!users.is_user_in_group("no such user, surely", &cur_group.name));
+        assert!(!users.is_user_in_group(&cur_user.name, "no such group, surely"));
+    }
+
+    #[test]
+    fn flush() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let name = users.get_user_by_uid(uid).unwrap().name;
+        assert!(users.users.borrow().contains_key(&uid));
+
+        users.flush();
+        assert!(users.users.borrow().is_empty());
+        assert!(users.users_back.borrow().is_empty());
+
+        assert_eq!(users.get_user_by_name(&name).map(|u| u.uid), Some(uid));
+        assert!(users.users.borrow().contains_key(&uid));
+    }
+
+    #[test]
+    fn forget_user() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        let name = users.get_user_by_uid(uid).unwrap().name;
+        users.get_user_by_uid(0);
+
+        users.forget_user(uid);
+        assert!(!users.users.borrow().contains_key(&uid));
+        assert!(!users.users_back.borrow().contains_key(&*name));
+        assert!(uid == 0 || users.users.borrow().contains_key(&0));
+
+        assert_eq!(users.get_user_by_uid(uid).map(|u| u.name), Some(name));
+    }
+
+    #[test]
+    fn forget_group() {
+        let mut users = OSUsers::empty_cache();
+        let gid = users.get_current_gid();
+        let name = users.get_group_by_gid(gid).unwrap().name;
+
+        users.forget_group(gid);
+        assert!(!users.groups.borrow().contains_key(&gid));
+        assert!(!users.groups_back.borrow().contains_key(&*name));
+
+        assert_eq!(users.get_group_by_name(&name).map(|g| g.gid), Some(gid));
+    }
+
+    #[test]
+    fn ttl() {
+        use std::thread::sleep;
+        use std::time::Duration;
+
+        let mut users = OSUsers::with_ttl(Duration::from_millis(50));
+        let uid = users.get_current_uid();
+        users.get_user_by_uid(uid);
+        let first_read = users.users.borrow()[&uid].1;
+
+        users.get_user_by_uid(uid);
+        assert_eq!(users.users.borrow()[&uid].1, first_read);
+
+        sleep(Duration::from_millis(100));
+        assert_eq!(users.get_user_by_uid(uid).map(|u| u.uid), Some(uid));
+        assert!(users.users.borrow()[&uid].1 > first_read);
+    }
+
+    #[test]
+    fn trace_hook() {
+        use super::CacheEvent;
+        use std::cell::RefCell;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorder = events.clone();
+
+        let mut users = OSUsers::empty_cache();
+        users.set_trace_hook(Box::new(move |event| recorder.borrow_mut().push(event)));
+
+        let uid = users.get_current_uid();
+        users.get_user_by_uid(uid);
+        users.get_user_by_uid(uid);
+        assert_eq!(*events.borrow(), vec![
+            CacheEvent::Miss { uid },
+            CacheEvent::LibcCall { which: "getpwuid_r" },
+            CacheEvent::Hit { uid },
+        ]);
+
+        users.clear_trace_hook();
+        users.get_user_by_uid(uid);
+        assert_eq!(events.borrow().len(), 3);
+    }
+
+    #[test]
+    fn misses_not_cached_by_default() {
+        let mut users = OSUsers::empty_cache();
+        assert!(users.get_user_by_uid(4294967294).is_none());
+        assert!(users.get_user_by_name("no such user, surely").is_none());
+        assert!(users.get_group_by_gid(4294967294).is_none());
+        assert!(users.users.borrow().is_empty());
+        assert!(users.users_back.borrow().is_empty());
+        assert!(users.groups.borrow().is_empty());
+
+        let uid = users.get_current_uid();
+        assert!(users.get_user_by_uid(uid).is_some());
+        assert!(users.users.borrow().contains_key(&uid));
+    }
+
+    #[test]
+    fn ttl_by_name() {
+        use std::thread::sleep;
+        use std::time::Duration;
+
+        let mut users = OSUsers::with_ttl(Duration::from_millis(50));
+        let gid = users.get_current_gid();
+        let name = users.get_group_by_gid(gid).unwrap().name;
+        let first_read = users.groups_back.borrow()[&*name].1;
+
+        sleep(Duration::from_millis(100));
+        assert_eq!(users.get_group_by_name(&name).map(|g| g.gid), Some(gid));
+        assert!(users.groups_back.borrow()[&*name].1 > first_read);
+    }
+
+    #[test]
+    fn all_users() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        assert!(users.all_users().any(|u| u.uid == uid));
+
+        // Iterating again should start from the beginning.
+        let count = users.all_users().count();
+        assert_eq!(count, users.all_users().count());
+    }
+
+    #[test]
+    fn all_groups() {
+        let mut users = OSUsers::empty_cache();
+        let gid = users.get_current_gid();
+        assert!(users.all_groups().any(|g| g.gid == gid));
+    }
+
+    #[test]
+    fn try_get_user_by_uid() {
+        let mut users = OSUsers::empty_cache();
+        let uid = users.get_current_uid();
+        assert_eq!(users.try_get_user_by_uid(uid).unwrap().map(|u| u.uid), Some(uid));
+
+        // Picking a user ID that nobody has is tricky, but the largest one
+        // is reserved as an error value.
+        assert!(users.try_get_user_by_uid(!0).unwrap().is_none());
+
+        // Some systems give this one to nobody, and it has to reach libc
+        // unchanged whether or not this one does.
+        assert!(users.try_get_user_by_uid(4294967294).unwrap().is_none_or(|u| u.uid == 4294967294));
+    }
+
+    #[test]
+    fn try_get_group_by_name() {
+        let mut users = OSUsers::empty_cache();
+        let gid = users.get_current_gid();
+        let group = users.try_get_group_by_gid(gid).unwrap().unwrap();
+        assert_eq!(users.try_get_group_by_name(&group.name).unwrap().map(|g| g.gid), Some(gid));
+        assert!(users.try_get_group_by_name("users\0").unwrap().is_none());
+    }
+
+    #[test]
+    fn lookup_error() {
+        use super::passwd_result;
+        use libc::EIO;
+
+        let result = unsafe { passwd_result(EIO, ::std::ptr::null_mut()) };
+        assert_eq!(result.err().and_then(|e| e.raw_os_error()), Some(EIO));
+        assert!(unsafe { passwd_result(0, ::std::ptr::null_mut()) }.unwrap().is_none());
+    }
+
+    #[test]
+    fn buffer_growth() {
+        use super::with_growing_buffer;
+        use libc::ERANGE;
+        use std::io;
+
+        // Pretend there's an entry that needs 5000 bytes to fit.
+        let mut sizes = Vec::new();
+        let result = with_growing_buffer(16, |buffer| {
+            sizes.push(buffer.len());
+            if buffer.len() < 5000 { Err(io::Error::from_raw_os_error(ERANGE)) }
+            else { Ok(Some(buffer.len())) }
+        });
+
+        assert_eq!(result.unwrap(), Some(8192));
+        assert_eq!(sizes, vec![16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192]);
+    }
+
+    #[test]
+    fn buffer_growth_limit() {
+        use super::{with_growing_buffer, MAX_BUFFER_SIZE};
+        use libc::ERANGE;
+        use std::io;
+
+        let mut largest = 0;
+        let result: io::Result<Option<()>> = with_growing_buffer(1000, |buffer| {
+            largest = buffer.len();
+            Err(io::Error::from_raw_os_error(ERANGE))
+        });
+
+        assert_eq!(result.err().and_then(|e| e.raw_os_error()), Some(ERANGE));
+        assert_eq!(largest, MAX_BUFFER_SIZE);
+    }
+
+    // This changes the effective user ID of the whole test process, so it
+    // needs root and has to run on its own:
+    // cargo test raise_privileges -- --ignored --test-threads=1
+    #[test]
+    #[ignore]
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+    fn raise_privileges() {
+        use super::{raise_privileges, set_effective_uid, get_effective_uid};
+
+        assert_eq!(get_effective_uid(), 0);
+        set_effective_uid(65534).unwrap();
+
+        {
+            let _guard = raise_privileges().unwrap();
+            assert_eq!(get_effective_uid(), 0);
+        }
+
+        assert_eq!(get_effective_uid(), 65534);
+        set_effective_uid(0).unwrap();
+    }
+
+    // This changes the umask of the whole test process, which other tests
+    // creating files would see, so it has to run on its own:
+    // cargo test umask -- --ignored --test-threads=1
+    #[test]
+    #[ignore]
+    fn umask() {
+        use super::current_umask;
+
+        let original = unsafe { super::umask(0o027) };
+        assert_eq!(current_umask(), 0o027);
+        assert_eq!(current_umask(), 0o027);
+        unsafe { super::umask(original) };
+        assert_eq!(current_umask(), original as u32);
+    }
+
+    // Changing a child's user ID needs root, so this has to be asked for:
+    // sudo cargo test spawn_as_user -- --ignored
+    #[test]
+    #[ignore]
+    fn spawn_as_user() {
+        use super::running_as_root;
+        use std::process::{Command, Stdio};
+
+        assert!(running_as_root(), "spawn_as_user needs to be run as root");
+
+        let mut users = OSUsers::empty_cache();
+        let nobody = users.get_nobody_user().expect("spawn_as_user needs a nobody user");
+
+        let mut command = Command::new("id");
+        command.stdout(Stdio::piped());
+        let output = users.spawn_as_user(&nobody.name, &mut command).unwrap().wait_with_output().unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+
+        assert!(output.starts_with(&format!("uid={}(", nobody.uid)), "{}", output);
+        assert!(output.contains(&format!(" gid={}(", nobody.primary_group)), "{}", output);
+        assert!(!output.contains("groups=0("), "{}", output);
+    }
+
+    #[test]
+    fn spawn_as_missing_user() {
+        use std::io::ErrorKind;
+        use std::process::Command;
+
+        let mut users = OSUsers::empty_cache();
+        let result = users.spawn_as_user("no such user, surely", &mut Command::new("id"));
+        assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn drop_privileges_without_user() {
+        use super::drop_privileges;
+        use std::io::ErrorKind;
+
+        let result = drop_privileges("no such user, surely");
+        assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn can_login() {
+        use super::User;
+
+        assert!(User::builder(1000, "fred").shell("/bin/bash").build().can_login());
+        assert!(!User::builder(1000, "fred").shell("/bin/false").build().can_login());
+        assert!(!User::builder(1000, "fred").shell("/usr/sbin/nologin").build().can_login());
+        assert!(!User::builder(1000, "fred").shell("/bin/bash").passwd("!").build().can_login());
+    }
+
+    #[test]
+    fn parse_shells() {
+        use super::parse_shells;
+        use std::path::PathBuf;
+
+        let shells = parse_shells("# /etc/shells: valid login shells\n/bin/sh\n\n  /bin/bash\n");
+        assert_eq!(shells, vec![ PathBuf::from("/bin/sh"), PathBuf::from("/bin/bash") ]);
+    }
+
+    #[test]
+    fn user_ordering() {
+        use super::User;
+        use std::collections::BTreeSet;
+
+        let mut users = [
+            User::builder(1001, "sue").build(),
+            User::builder(1000, "fred").build(),
+            User::builder(0, "toor").build(),
+            User::builder(0, "root").build(),
+        ];
+        users.sort();
+        let names: Vec<&str> = users.iter().map(|u| &u.name[..]).collect();
+        assert_eq!(names, vec![ "root", "toor", "fred", "sue" ]);
+
+        let set: BTreeSet<User> = users.iter().cloned().chain(users.iter().cloned()).collect();
+        assert_eq!(set.len(), 4);
+        assert!(User::builder(1000, "fred").build() == User::builder(1000, "fred").build());
+        assert!(User::builder(1000, "fred").build() != User::builder(1000, "fred").shell("/bin/zsh").build());
+    }
+
+    #[test]
+    fn group_ordering() {
+        use super::Group;
+
+        let mut groups = [
+            Group::builder(100, "users").build(),
+            Group::builder(10, "wheel").build(),
+            Group::builder(10, "admin").build(),
+        ];
+        groups.sort();
+        let names: Vec<&str> = groups.iter().map(|g| &g.name[..]).collect();
+        assert_eq!(names, vec![ "admin", "wheel", "users" ]);
+    }
+
+    #[test]
+    fn supplementary_gids_for() {
+        let mut users = OSUsers::empty_cache();
+        let name = users.get_current_username().unwrap();
+        let gid = users.get_current_gid();
+        assert!(users.supplementary_gids_for(&name, gid).contains(&gid));
+        assert_eq!(users.supplementary_gids_for("no such user, surely", gid), vec![ gid ]);
+        assert_eq!(users.supplementary_gids_for("user\0", gid), vec![ gid ]);
+    }
+
+    #[test]
+    fn count_user_groups() {
+        let mut users = OSUsers::empty_cache();
+        let name = users.get_current_username().unwrap();
+        let gid = users.get_current_gid();
+        let groups = users.get_user_groups(&name, gid).unwrap();
+        assert_eq!(users.count_user_groups(&name, gid), groups.len());
+        assert_eq!(users.count_user_groups("no such user, surely", gid), 1);
+    }
+
+    #[test]
+    fn free_functions() {
+        use super::{get_current_uid, get_current_gid, get_user_by_uid, get_group_by_gid};
+
+        let mut users = OSUsers::empty_cache();
+        assert_eq!(get_current_uid(), users.get_current_uid());
+        assert_eq!(get_current_gid(), users.get_current_gid());
+        assert_eq!(get_current_username(), users.get_current_username());
+        assert!(get_user_by_uid(get_current_uid()) == users.get_user_by_uid(get_current_uid()));
+        assert!(get_group_by_gid(get_current_gid()) == users.get_group_by_gid(get_current_gid()));
+    }
+
+    #[test]
+    fn resolve_group() {
+        use super::{resolve_group, get_current_gid, get_current_groupname};
+
+        let gid = get_current_gid();
+        let name = get_current_groupname().unwrap();
+        assert_eq!(resolve_group(&gid.to_string()).map(|g| g.name), Some(name.clone()));
+        assert_eq!(resolve_group(&name).map(|g| g.gid), Some(gid));
+        assert!(resolve_group("4294967294").is_none());
+        assert!(resolve_group("no such group, surely").is_none());
+    }
+
+    #[test]
+    fn resolve_user() {
+        use super::{resolve_user, get_current_uid, get_current_username};
+
+        let uid = get_current_uid();
+        let name = get_current_username().unwrap();
+        assert_eq!(resolve_user(&uid.to_string()).map(|u| u.name), Some(name.clone()));
+        assert_eq!(resolve_user(&name).map(|u| u.uid), Some(uid));
+        assert!(resolve_user("4294967294").is_none());
+    }
+
+    #[test]
+    fn login_name() {
+        // There's no controlling terminal when the tests run under CI, so
+        // the name can only be checked when there is one.
+        if let Some(name) = super::get_login_name() {
+            assert!(!name.is_empty());
+            assert!(!name.contains('\0'));
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn read_loginuid() {
+        use super::read_loginuid;
+        use std::path::Path;
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/");
+        assert_eq!(read_loginuid(&Path::new(dir).join("loginuid")), Some(1000));
+        assert_eq!(read_loginuid(&Path::new(dir).join("loginuid-unset")), None);
+        assert_eq!(read_loginuid(&Path::new(dir).join("no-such-file")), None);
+    }
+
+    #[test]
+    fn all_names() {
+        let mut users = OSUsers::empty_cache();
+        let username = users.get_current_username().unwrap();
+        let groupname = users.get_current_groupname().unwrap();
+        assert!(users.get_all_usernames().contains(&username));
+        assert!(users.get_all_group_names().contains(&groupname));
+    }
+
+    #[test]
+    fn current_process_groups() {
+        let mut users = OSUsers::empty_cache();
+        let groups = users.current_process_groups();
+        let names = users.current_process_group_names();
+        assert!(names.len() <= groups.len());
+    }
+
+    #[test]
+    fn parse_login_defs() {
+        use super::parse_login_defs;
+
+        let contents = "# Min/max values for automatic uid selection in useradd\n#UID_MIN 500\nUID_MIN\t\t 1000\nUID_MAX 60000\nUID_MINIMUM 5\n";
+        assert_eq!(parse_login_defs(contents, "UID_MIN"), Some("1000"));
+        assert_eq!(parse_login_defs(contents, "UID_MAX"), Some("60000"));
+        assert_eq!(parse_login_defs(contents, "GID_MIN"), None);
+    }
+
+    #[test]
+    fn uid_range() {
+        use super::{uid_min, uid_max};
+        assert!(uid_min() <= uid_max());
+    }
+
     #[test]
     fn get_group_by_name() {
         // We cannot really test for arbitrary groups as they might not exist on the machine
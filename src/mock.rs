@@ -14,13 +14,14 @@
 //!
 //! The only thing a mock users object needs to know in advance is the UID of
 //! the current user. Aside from that, you can add users and groups with
-//! `add_user` and `add_group` to the object:
+//! `add_user` and `add_group` to the object. The `User::builder` and
+//! `Group::builder` functions fill in any fields you don't care about:
 //!
 //! ```
 //! use users::mock::{MockUsers, User, Group};
 //! let mut users = MockUsers::with_current_uid(1000);
-//! users.add_user(User { uid: 1000, name: "Bobbins".to_string(), primary_group: 100, home_dir: "/home/bobbins".to_string(), shell: "/bin/bash".to_string() });
-//! users.add_group(Group { gid: 100, name: "funkyppl".to_string(), members: vec![ "other_person".to_string() ] });
+//! users.add_user(User::builder(1000, "Bobbins").primary_group(100).home_dir("/home/bobbins").shell("/bin/bash").build());
+//! users.add_group(Group::builder(100, "funkyppl").member("other_person").build());
 //! ```
 //!
 //! The exports get re-exported into the mock module, for simpler `use` lines.
@@ -43,103 +44,550 @@
 //! }
 //!
 //! let mut users = MockUsers::with_current_uid(1001);
-//! users.add_user(User { uid: 1001, name: "fred".to_string(), primary_group: 101 , home_dir: "/home/fred".to_string(), shell: "/bin/bash".to_string()});
+//! users.add_user(User::builder(1001, "fred").primary_group(101).gecos("Fred").build());
 //! print_current_username(&mut users);
 //!
 //! let mut actual_users = OSUsers::empty_cache();
 //! print_current_username(&mut actual_users);
 //! ```
 
-pub use super::{Users, User, Group};
+pub use super::{Users, UsersRead, User, Group};
+use super::{PasswdSource, HomePolicy, ShellPolicy, IntegrityIssue, check_integrity};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use libc::{uid_t, gid_t};
+use std::ffi::{CStr, OsStr};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::vec;
+use std::os::unix::ffi::OsStrExt;
+use libc::{c_char, uid_t, gid_t};
 
 /// A mocking users object that you can add your own users and groups to.
 pub struct MockUsers {
-    users: HashMap<uid_t, User>,
+    users: HashMap<uid_t, Rc<User>>,
+    duplicates: Vec<Rc<User>>,
     groups: HashMap<gid_t, Group>,
     uid: uid_t,
+    gid: Option<gid_t>,
+    euid: Option<uid_t>,
+    egid: Option<gid_t>,
+    process_groups: Vec<gid_t>,
+    login_name: Option<String>,
+    home_policy: HomePolicy,
+    shell_policy: ShellPolicy,
 }
 
 impl MockUsers {
     /// Create a new, empty mock users object.
+    ///
+    /// The current group ID is taken from the primary group of the current
+    /// user once that user has been added, and falls back to the current user
+    /// ID until then.
     pub fn with_current_uid(current_uid: uid_t) -> MockUsers {
         MockUsers {
             users: HashMap::new(),
+            duplicates: Vec::new(),
             groups: HashMap::new(),
             uid: current_uid,
+            gid: None,
+            euid: None,
+            egid: None,
+            process_groups: Vec::new(),
+            login_name: None,
+            home_policy: HomePolicy::default(),
+            shell_policy: ShellPolicy::default(),
         }
     }
 
+    /// Create a new, empty mock users object with the given current user
+    /// and group IDs.
+    pub fn with_current_uid_and_gid(current_uid: uid_t, current_gid: gid_t) -> MockUsers {
+        MockUsers {
+            users: HashMap::new(),
+            duplicates: Vec::new(),
+            groups: HashMap::new(),
+            uid: current_uid,
+            gid: Some(current_gid),
+            euid: None,
+            egid: None,
+            process_groups: Vec::new(),
+            login_name: None,
+            home_policy: HomePolicy::default(),
+            shell_policy: ShellPolicy::default(),
+        }
+    }
+
+    /// Create a mock users object with the users and groups from the given
+    /// passwd- and group-format files, and a current user ID of 0. Blank
+    /// lines and comments are skipped, but any other line that can't be
+    /// parsed is an error that says which line it was.
+    ///
+    /// Use `set_current_uid` to pretend to be someone else afterwards.
+    pub fn from_files(passwd: &Path, group: &Path) -> io::Result<MockUsers> {
+        let mut users = MockUsers::with_current_uid(0);
+
+        for (line_number, line) in read_lines(passwd)? {
+            let user: User = line.parse().map_err(|e| parse_error(passwd, line_number, e))?;
+            if users.users.contains_key(&user.uid) {
+                users.add_duplicate_user(user);
+            }
+            else {
+                users.add_user(user);
+            }
+        }
+
+        for (line_number, line) in read_lines(group)? {
+            let group: Group = line.parse().map_err(|e| parse_error(group, line_number, e))?;
+            users.groups.entry(group.gid).or_insert(group);
+        }
+
+        Ok(users)
+    }
+
+    /// Set the supplementary group IDs that `current_process_groups` returns,
+    /// which are empty to begin with.
+    pub fn set_process_groups(&mut self, gids: Vec<gid_t>) {
+        self.process_groups = gids;
+    }
+
+    /// Set the name that `get_login_name` returns, which is None to begin
+    /// with. This doesn't have to match the current user, just as after
+    /// `su`.
+    pub fn set_login_name(&mut self, name: Option<&str>) {
+        self.login_name = name.map(|n| n.to_owned());
+    }
+
+    /// Set the current user ID, as though the process had switched to
+    /// another user. Every `get_current_*` method reflects the new user
+    /// afterwards, and so do the `get_effective_*` ones unless an
+    /// effective user ID has been set separately.
+    pub fn set_current_uid(&mut self, uid: uid_t) {
+        self.uid = uid;
+    }
+
+    /// Set the current group ID. Until this is called, the current group ID
+    /// follows the primary group of the current user.
+    pub fn set_current_gid(&mut self, gid: gid_t) {
+        self.gid = Some(gid);
+    }
+
+    /// Set the effective user ID, as though the process were running a
+    /// setuid binary or had dropped privileges. Until this is called, the
+    /// effective user ID is the same as the current one.
+    pub fn set_effective_uid(&mut self, euid: uid_t) {
+        self.euid = Some(euid);
+    }
+
+    /// Set the effective group ID. Until this is called, the effective
+    /// group ID is the same as the current one.
+    pub fn set_effective_gid(&mut self, egid: gid_t) {
+        self.egid = Some(egid);
+    }
+
+    /// Set the directory that `add_user_with_defaults` puts home
+    /// directories in, which is `/home` to begin with.
+    pub fn set_home_base(&mut self, base: PathBuf) {
+        self.home_policy = HomePolicy::new(base);
+    }
+
+    /// Set the shell that `add_user_with_defaults` gives users, which is
+    /// `/bin/bash` to begin with.
+    pub fn set_default_shell(&mut self, shell: PathBuf) {
+        self.shell_policy = ShellPolicy::new(shell);
+    }
+
+    /// Create a user with the given ID and name in the same way as
+    /// `User::with_defaults`, but following this object's home and shell
+    /// policies, and add them to the users table.
+    pub fn add_user_with_defaults(&mut self, uid: uid_t, name: &str) -> Option<User> {
+        let user = User::with_policies(uid, name, &self.home_policy, &self.shell_policy);
+        self.add_user(user)
+    }
+
     /// Add a user to the users table.
     pub fn add_user(&mut self, user: User) -> Option<User> {
-        self.users.insert(user.uid, user)
+        self.users.insert(user.uid, Rc::new(user))
+            .map(|u| Rc::try_unwrap(u).unwrap_or_else(|u| (*u).clone()))
+    }
+
+    /// Add a user that shares their user ID with one that's already in the
+    /// users table, such as `toor` alongside `root`. Lookups by ID keep
+    /// returning the user added with `add_user`, but this one can be found
+    /// by name, and with `get_all_users_by_uid`.
+    pub fn add_duplicate_user(&mut self, user: User) {
+        self.duplicates.push(Rc::new(user));
     }
 
     /// Add a group to the groups table.
     pub fn add_group(&mut self, group: Group) -> Option<Group> {
         self.groups.insert(group.gid, group)
     }
+
+    /// Remove the user with the given ID from the users table, returning it
+    /// if it was there. Any duplicate users with the same ID are removed too.
+    pub fn remove_user(&mut self, uid: uid_t) -> Option<User> {
+        self.duplicates.retain(|u| u.uid != uid);
+        self.users.remove(&uid)
+            .map(|u| Rc::try_unwrap(u).unwrap_or_else(|u| (*u).clone()))
+    }
+
+    /// Remove the group with the given ID from the groups table, returning
+    /// it if it was there.
+    pub fn remove_group(&mut self, gid: gid_t) -> Option<Group> {
+        self.groups.remove(&gid)
+    }
+
+    /// Add the given username to the members of the group with the given ID.
+    /// Return false if there's no such group, or if they're already a member.
+    pub fn add_user_to_group(&mut self, gid: gid_t, username: &str) -> bool {
+        match self.groups.get_mut(&gid) {
+            Some(group) if !group.members.iter().any(|m| m == username) => {
+                group.members.push(username.to_owned());
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Remove the given username from the members of the group with the
+    /// given ID. Return false if there's no such group, or if they weren't a
+    /// member.
+    pub fn remove_user_from_group(&mut self, gid: gid_t, username: &str) -> bool {
+        match self.groups.get_mut(&gid) {
+            Some(group) => {
+                let count = group.members.len();
+                group.members.retain(|m| m != username);
+                group.members.len() != count
+            },
+            None => false,
+        }
+    }
+
+    /// Return an iterator over every user in the users table, in order of
+    /// user ID.
+    pub fn all_users(&self) -> vec::IntoIter<User> {
+        let mut users: Vec<User> = self.entries().map(|u| (**u).clone()).collect();
+        users.sort_by_key(|u| u.uid);
+        users.into_iter()
+    }
+
+    /// Return an iterator over every group in the groups table, in order of
+    /// group ID.
+    pub fn all_groups(&self) -> vec::IntoIter<Group> {
+        let mut groups: Vec<Group> = self.groups.values().cloned().collect();
+        groups.sort_by_key(|g| g.gid);
+        groups.into_iter()
+    }
+
+    /// Return every user in the users table, in order of user ID, each with
+    /// the groups they're a member of, primary group first.
+    ///
+    /// Like `OSUsers::users_with_groups`, this goes through the groups table
+    /// only once.
+    pub fn users_with_groups(&self) -> Vec<(User, Vec<Group>)> {
+        super::pair_with_groups(self.all_users(), self.all_groups().collect())
+    }
+
+    /// Every user in the table, with the ones added by `add_user` first.
+    fn entries(&self) -> impl Iterator<Item = &Rc<User>> {
+        self.users.values().chain(self.duplicates.iter())
+    }
+
+    fn current_gid(&self) -> gid_t {
+        match self.gid {
+            Some(gid) => gid,
+            None => self.users.get(&self.uid).map(|u| u.primary_group).unwrap_or(self.uid),
+        }
+    }
+}
+
+/// Read the lines of the given file that aren't blank or comments, along
+/// with their line numbers.
+fn read_lines(path: &Path) -> io::Result<Vec<(usize, String)>> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+
+    Ok(contents.lines()
+               .enumerate()
+               .map(|(i, line)| (i + 1, line.trim_end().to_owned()))
+               .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+               .collect())
+}
+
+fn parse_error<E: fmt::Display>(path: &Path, line_number: usize, error: E) -> io::Error {
+    let message = format!("{}, line {}: {}", path.display(), line_number, error);
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A mock users object can be the source for an `OSUsers`, to test code that
+/// depends on how the cache behaves.
+impl PasswdSource for MockUsers {
+    fn user_by_uid(&self, uid: uid_t, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        Ok(self.users.get(&uid).map(|u| (**u).clone()))
+    }
+
+    fn user_by_name(&self, username: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        Ok(self.entries().find(|u| u.name_os.as_bytes() == username.to_bytes()).map(|u| (**u).clone()))
+    }
+
+    fn group_by_gid(&self, gid: gid_t, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        Ok(self.groups.get(&gid).cloned())
+    }
+
+    fn group_by_name(&self, group_name: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        Ok(self.groups.values().find(|g| g.name_os.as_bytes() == group_name.to_bytes()).cloned())
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+        Box::new(MockUsers::all_users(self))
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+        Box::new(MockUsers::all_groups(self))
+    }
+
+    fn current_uid(&self) -> uid_t {
+        self.uid
+    }
+
+    fn current_gid(&self) -> gid_t {
+        MockUsers::current_gid(self)
+    }
+
+    fn effective_uid(&self) -> uid_t {
+        UsersRead::get_effective_uid(self)
+    }
+
+    fn effective_gid(&self) -> gid_t {
+        UsersRead::get_effective_gid(self)
+    }
+
+    fn process_groups(&self) -> Vec<gid_t> {
+        self.process_groups.clone()
+    }
+
+    fn login_name(&self) -> Option<String> {
+        self.login_name.clone()
+    }
+
+    fn audit_login_uid(&self) -> Option<uid_t> {
+        None
+    }
+}
+
+impl UsersRead for MockUsers {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<User> {
+        self.users.get(&uid).map(|u| (**u).clone())
+    }
+
+    fn get_user_by_uid_cow(&self, uid: uid_t) -> Option<Cow<'_, User>> {
+        self.users.get(&uid).map(|u| Cow::Borrowed(&**u))
+    }
+
+    fn get_user_by_name(&self, username: &str) -> Option<User> {
+        self.entries().find(|u| u.name == username).map(|u| (**u).clone())
+    }
+
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Group> {
+        self.groups.get(&gid).cloned()
+    }
+
+    fn get_group_by_name(&self, group_name: &str) -> Option<Group> {
+        self.groups.values().find(|g| g.name == group_name).cloned()
+    }
+
+    fn get_user_groups(&self, username: &str, gid: gid_t) -> Option<Vec<Group>> {
+        if !self.entries().any(|u| u.name == username) {
+            return None;
+        }
+
+        let mut groups: Vec<Group> = self.groups.values()
+            .filter(|g| g.gid != gid && g.members.iter().any(|m| m == username))
+            .cloned()
+            .collect();
+        groups.sort_by_key(|g| g.gid);
+
+        if let Some(primary) = self.groups.get(&gid) {
+            groups.insert(0, primary.clone());
+        }
+
+        Some(groups)
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        self.uid
+    }
+
+    fn get_current_username(&self) -> Option<String> {
+        self.users.get(&self.uid).map(|u| u.name.clone())
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        self.current_gid()
+    }
+
+    fn get_current_groupname(&self) -> Option<String> {
+        self.groups.get(&self.current_gid()).map(|g| g.name.clone())
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        self.euid.unwrap_or(self.uid)
+    }
+
+    fn get_effective_username(&self) -> Option<String> {
+        self.users.get(&UsersRead::get_effective_uid(self)).map(|u| u.name.clone())
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        self.egid.unwrap_or_else(|| self.current_gid())
+    }
+
+    fn get_effective_groupname(&self) -> Option<String> {
+        self.groups.get(&UsersRead::get_effective_gid(self)).map(|g| g.name.clone())
+    }
 }
 
 impl Users for MockUsers {
-    fn get_user_by_uid(&mut self, uid: uid_t) -> Option<User> {
+    fn get_user_by_uid_rc(&mut self, uid: uid_t) -> Option<Rc<User>> {
         self.users.get(&uid).cloned()
     }
 
-    fn get_user_by_name(&mut self, username: &str) -> Option<User> {
-        self.users.values().find(|u| u.name == username).cloned()
+    fn get_user_by_name_rc(&mut self, username: &str) -> Option<Rc<User>> {
+        self.entries().find(|u| u.name == username).cloned()
+    }
+
+    fn get_user_by_name_os(&mut self, username: &OsStr) -> Option<User> {
+        self.entries().find(|u| u.name_os == username).map(|u| (**u).clone())
+    }
+
+    fn get_user_by_name_ci(&mut self, username: &str) -> Option<User> {
+        self.entries().find(|u| u.name == username)
+            .or_else(|| self.entries().find(|u| u.name.eq_ignore_ascii_case(username)))
+            .map(|u| (**u).clone())
+    }
+
+    fn get_group_by_name_os(&mut self, group_name: &OsStr) -> Option<Group> {
+        self.groups.values().find(|g| g.name_os == group_name).cloned()
     }
 
     fn get_group_by_gid(&mut self, gid: gid_t) -> Option<Group> {
-        self.groups.get(&gid).cloned()
+        UsersRead::get_group_by_gid(self, gid)
+    }
+
+    fn get_all_users_by_uid(&mut self, uid: uid_t) -> Vec<User> {
+        self.entries().filter(|u| u.uid == uid).map(|u| (**u).clone()).collect()
+    }
+
+    fn users_in_range(&mut self, range: Range<uid_t>) -> Vec<User> {
+        self.all_users().filter(|u| range.contains(&u.uid)).collect()
+    }
+
+    fn snapshot_users(&mut self) -> HashMap<uid_t, User> {
+        self.users.iter().map(|(&uid, u)| (uid, (**u).clone())).collect()
+    }
+
+    fn validate_integrity(&mut self) -> Vec<IntegrityIssue> {
+        let users: Vec<User> = self.all_users().collect();
+        let groups: Vec<Group> = self.all_groups().collect();
+        check_integrity(&users, &groups)
+    }
+
+    fn snapshot_groups(&mut self) -> HashMap<gid_t, Group> {
+        self.groups.clone()
+    }
+
+    fn get_all_usernames(&mut self) -> Vec<String> {
+        let mut users: Vec<&Rc<User>> = self.entries().collect();
+        users.sort_by_key(|u| u.uid);
+        users.into_iter().map(|u| u.name.clone()).collect()
+    }
+
+    fn get_all_group_names(&mut self) -> Vec<String> {
+        let mut groups: Vec<&Group> = self.groups.values().collect();
+        groups.sort_by_key(|g| g.gid);
+        groups.into_iter().map(|g| g.name.clone()).collect()
+    }
+
+    fn get_group_members(&mut self, gid: gid_t) -> Vec<User> {
+        let members = self.groups.get(&gid).map(|g| &g.members[..]).unwrap_or(&[]);
+        let mut users: Vec<User> = self.entries()
+            .filter(|u| u.primary_group == gid || members.contains(&u.name))
+            .map(|u| (**u).clone())
+            .collect();
+        users.sort_by_key(|u| u.uid);
+        users
     }
 
     fn get_group_by_name(&mut self, group_name: &str) -> Option<Group> {
-        self.groups.values().find(|g| g.name == group_name).cloned()
+        UsersRead::get_group_by_name(self, group_name)
+    }
+
+    fn get_user_groups(&mut self, username: &str, gid: gid_t) -> Option<Vec<Group>> {
+        UsersRead::get_user_groups(self, username, gid)
+    }
+
+    fn count_user_groups(&mut self, username: &str, gid: gid_t) -> usize {
+        let supplementary = self.groups.values()
+            .filter(|g| g.gid != gid && g.members.iter().any(|m| m == username))
+            .count();
+        supplementary + 1
     }
 
     fn get_current_uid(&mut self) -> uid_t {
-        self.uid
+        UsersRead::get_current_uid(self)
     }
 
     fn get_current_username(&mut self) -> Option<String> {
-        self.users.get(&self.uid).map(|u| u.name.clone())
+        UsersRead::get_current_username(self)
     }
 
-    fn get_current_gid(&mut self) -> uid_t {
-        self.uid
+    fn get_current_gid(&mut self) -> gid_t {
+        UsersRead::get_current_gid(self)
     }
 
     fn get_current_groupname(&mut self) -> Option<String> {
-        self.groups.get(&self.uid).map(|u| u.name.clone())
+        UsersRead::get_current_groupname(self)
     }
 
     fn get_effective_uid(&mut self) -> uid_t {
-        self.uid
+        UsersRead::get_effective_uid(self)
     }
 
     fn get_effective_username(&mut self) -> Option<String> {
-        self.users.get(&self.uid).map(|u| u.name.clone())
+        UsersRead::get_effective_username(self)
     }
 
-    fn get_effective_gid(&mut self) -> uid_t {
-        self.uid
+    fn get_effective_gid(&mut self) -> gid_t {
+        UsersRead::get_effective_gid(self)
     }
 
     fn get_effective_groupname(&mut self) -> Option<String> {
-        self.groups.get(&self.uid).map(|u| u.name.clone())
+        UsersRead::get_effective_groupname(self)
+    }
+
+    fn current_process_groups(&mut self) -> Vec<gid_t> {
+        self.process_groups.clone()
+    }
+
+    fn get_login_name(&mut self) -> Option<String> {
+        self.login_name.clone()
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::{Users, User, Group, MockUsers};
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
 
     #[test]
     fn current_username() {
         let mut users = MockUsers::with_current_uid(1337);
-        users.add_user(User { uid: 1337, name: "fred".to_string(), primary_group: 101, home_dir: "/home/fred".to_string(), shell: "/bin/bash".to_string() });
+        users.add_user(User::builder(1337, "fred").primary_group(101).gecos("Fred,,,").build());
         assert_eq!(Some("fred".to_string()), users.get_current_username())
     }
 
@@ -152,51 +600,1168 @@ mod test {
     #[test]
     fn uid() {
         let mut users = MockUsers::with_current_uid(0);
-        users.add_user(User { uid: 1337, name: "fred".to_string(), primary_group: 101, home_dir: "/home/fred".to_string(), shell: "/bin/bash".to_string() });
+        users.add_user(User::builder(1337, "fred").primary_group(101).gecos("Fred,,,").build());
         assert_eq!(Some("fred".to_string()), users.get_user_by_uid(1337).map(|u| u.name))
     }
 
     #[test]
     fn username() {
         let mut users = MockUsers::with_current_uid(1337);
-        users.add_user(User { uid: 1440, name: "fred".to_string(), primary_group: 101, home_dir: "/home/fred".to_string(), shell: "/bin/bash".to_string() });
+        users.add_user(User::builder(1440, "fred").primary_group(101).gecos("Fred,,,").build());
+        assert_eq!(Some(1440), users.get_user_by_name("fred").map(|u| u.uid))
+    }
+
+    #[test]
+    fn gid_from_primary_group() {
+        let mut users = MockUsers::with_current_uid(1000);
+        users.add_user(User::builder(1000, "fred").primary_group(100).gecos("Fred,,,").build());
+        assert_eq!(100, users.get_current_gid());
+        assert_eq!(100, users.get_effective_gid());
+    }
+
+    #[test]
+    fn gid_without_user() {
+        let mut users = MockUsers::with_current_uid(1000);
+        assert_eq!(1000, users.get_current_gid());
+    }
+
+    #[test]
+    fn explicit_gid() {
+        let mut users = MockUsers::with_current_uid_and_gid(1000, 100);
+        assert_eq!(100, users.get_current_gid());
+        assert_eq!(100, users.get_effective_gid());
+    }
+
+    #[test]
+    fn current_groupname() {
+        let mut users = MockUsers::with_current_uid_and_gid(1000, 100);
+        users.add_group(Group::builder(100, "funkyppl").build());
+        assert_eq!(Some("funkyppl".to_string()), users.get_current_groupname());
+        assert_eq!(Some("funkyppl".to_string()), users.get_effective_groupname());
+    }
+
+    #[test]
+    fn no_current_groupname() {
+        let mut users = MockUsers::with_current_uid_and_gid(1000, 100);
+        users.add_group(Group::builder(1000, "fred").build());
+        assert_eq!(None, users.get_current_groupname());
+    }
+
+    #[test]
+    fn shared_user() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1440, "fred").primary_group(101).gecos("Fred,,,").build());
+        let user = users.get_user_by_uid_rc(1440).unwrap();
+        let again = users.get_user_by_name_rc("fred").unwrap();
+        assert!(Rc::ptr_eq(&user, &again))
+    }
+
+    #[test]
+    fn read_only_lookups() {
+        use super::UsersRead;
+
+        let mut users = MockUsers::with_current_uid(1440);
+        users.add_user(User::builder(1440, "fred").primary_group(101).gecos("Fred,,,").build());
+
+        let users = Rc::new(users);
+        assert_eq!(Some("fred".to_string()), users.get_current_username());
         assert_eq!(Some(1440), users.get_user_by_name("fred").map(|u| u.uid))
     }
 
+    #[test]
+    fn non_utf8_username() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let raw = OsStr::from_bytes(b"fr\xffd");
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1440, "fred").name_os(raw).primary_group(101).gecos("Fred,,,").build());
+
+        let user = users.get_user_by_name_os(raw).unwrap();
+        assert_eq!(user.name_os.as_bytes(), b"fr\xffd");
+        assert_eq!(user.uid, 1440)
+    }
+
+    #[test]
+    fn try_get_current_username() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use super::super::Error;
+
+        let mut users = MockUsers::with_current_uid(1440);
+        assert!(matches!(users.try_get_current_username(), Err(Error::NotFound)));
+
+        users.add_user(User::builder(1440, "fred").name_os(OsStr::from_bytes(b"fr\xffd")).build());
+        assert!(matches!(users.try_get_current_username(), Err(Error::InvalidUtf8)));
+
+        let mut users = MockUsers::with_current_uid(1440);
+        users.add_user(User::builder(1440, "fred").build());
+        assert_eq!(users.try_get_current_username().unwrap(), "fred");
+        assert!(users.try_get_user_by_uid(1441).unwrap().is_none());
+    }
+
     #[test]
     fn no_username() {
         let mut users = MockUsers::with_current_uid(1337);
-        users.add_user(User { uid: 1440, name: "fred".to_string(), primary_group: 101, home_dir: "/home/fred".to_string(), shell: "/bin/bash".to_string() });
+        users.add_user(User::builder(1440, "fred").primary_group(101).gecos("Fred,,,").build());
         assert_eq!(None, users.get_user_by_name("criminy").map(|u| u.uid))
     }
 
+    #[test]
+    fn locked_user() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1440, "fred").primary_group(101).gecos("Fred,,,").passwd("!!").build());
+        assert_eq!(Some(true), users.get_user_by_uid(1440).map(|u| u.is_locked()))
+    }
+
     #[test]
     fn no_uid() {
         let mut users = MockUsers::with_current_uid(0);
         assert_eq!(None, users.get_user_by_uid(1337).map(|u| u.name))
     }
 
+    #[test]
+    fn all_users() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1440, "fred").primary_group(101).gecos("Fred,,,").build());
+        users.add_user(User::builder(1337, "bob").primary_group(101).gecos("Bob,,,").build());
+        users.add_user(User::builder(1500, "sue").primary_group(101).gecos("Sue,,,").build());
+
+        let uids: Vec<_> = users.all_users().map(|u| u.uid).collect();
+        assert_eq!(vec![ 1337, 1440, 1500 ], uids)
+    }
+
+    #[test]
+    fn all_groups() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_group(Group::builder(300, "audio").member("fred").member("bob").build());
+        users.add_group(Group::builder(100, "users").build());
+        users.add_group(Group::builder(200, "wheel").member("fred").build());
+
+        let groups: Vec<_> = users.all_groups().map(|g| (g.gid, g.members.len())).collect();
+        assert_eq!(vec![ (100, 0), (200, 1), (300, 2) ], groups)
+    }
+
+    #[test]
+    fn users_with_groups() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1440, "fred").primary_group(100).build());
+        users.add_user(User::builder(1337, "bob").primary_group(300).build());
+        users.add_user(User::builder(1500, "sue").primary_group(999).build());
+        users.add_group(Group::builder(300, "audio").member("fred").member("bob").build());
+        users.add_group(Group::builder(100, "users").build());
+        users.add_group(Group::builder(200, "wheel").member("fred").build());
+
+        let pairs: Vec<_> = users.users_with_groups().into_iter()
+            .map(|(u, gs)| (u.name, gs.into_iter().map(|g| g.gid).collect::<Vec<_>>()))
+            .collect();
+
+        assert_eq!(pairs, vec![
+            ("bob".to_string(),  vec![ 300 ]),
+            ("fred".to_string(), vec![ 100, 200, 300 ]),
+            ("sue".to_string(),  vec![]),
+        ]);
+    }
+
     #[test]
     fn gid() {
         let mut users = MockUsers::with_current_uid(0);
-        users.add_group(Group { gid: 1337, name: "fred".to_string(), members: vec![], });
+        users.add_group(Group::builder(1337, "fred").build());
         assert_eq!(Some("fred".to_string()), users.get_group_by_gid(1337).map(|g| g.name))
     }
 
     #[test]
     fn group_name() {
         let mut users = MockUsers::with_current_uid(0);
-        users.add_group(Group { gid: 1337, name: "fred".to_string(), members: vec![], });
+        users.add_group(Group::builder(1337, "fred").build());
         assert_eq!(Some(1337), users.get_group_by_name("fred").map(|g| g.gid))
     }
 
     #[test]
     fn no_group_name() {
         let mut users = MockUsers::with_current_uid(0);
-        users.add_group(Group { gid: 1337, name: "fred".to_string(), members: vec![], });
+        users.add_group(Group::builder(1337, "fred").build());
         assert_eq!(None, users.get_group_by_name("santa").map(|g| g.gid))
     }
 
+    #[test]
+    fn user_groups() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).gecos("Fred,,,").build());
+        users.add_group(Group::builder(100, "fred").member("fred").build());
+        users.add_group(Group::builder(200, "wheel").member("fred").build());
+        users.add_group(Group::builder(300, "audio").member("bob").build());
+
+        let gids = users.get_user_groups("fred", 100).map(|gs| gs.into_iter().map(|g| g.gid).collect::<Vec<_>>());
+        assert_eq!(Some(vec![ 100, 200 ]), gids)
+    }
+
+    #[test]
+    fn no_user_groups() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_group(Group::builder(200, "wheel").member("fred").build());
+        assert!(users.get_user_groups("fred", 100).is_none())
+    }
+
+    #[test]
+    fn group_for_new_file() {
+        use std::env;
+        use std::fs;
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = env::temp_dir().join(format!("users-setgid-test-{}", ::std::process::id()));
+        fs::create_dir(&dir).unwrap();
+        let dir_gid = fs::metadata(&dir).unwrap().gid();
+        let other_gid = dir_gid.wrapping_add(1);
+
+        let mut users = MockUsers::with_current_uid_and_gid(0, other_gid);
+        users.add_group(Group::builder(dir_gid, "dir-group").build());
+        users.add_group(Group::builder(other_gid, "process-group").build());
+        let without_setgid = users.effective_group_for_new_file(&dir).map(|g| g.name);
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o2755)).unwrap();
+        let with_setgid = users.effective_group_for_new_file(&dir).map(|g| g.name);
+        let missing = users.effective_group_for_new_file(&dir.join("missing")).is_err();
+        fs::remove_dir(&dir).unwrap();
+
+        assert_eq!(without_setgid.unwrap(), "process-group");
+        assert_eq!(with_setgid.unwrap(), "dir-group");
+        assert!(missing);
+    }
+
+    #[test]
+    fn login_name() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").build());
+        assert_eq!(None, users.get_login_name());
+
+        users.set_login_name(Some("fred"));
+        assert_eq!(Some("fred".to_string()), users.get_login_name());
+        assert_eq!(Some("root".to_string()), users.get_current_username());
+
+        users.set_login_name(None);
+        assert_eq!(None, users.get_login_name());
+    }
+
+    #[test]
+    fn home_base() {
+        let mut users = MockUsers::with_current_uid(501);
+        users.add_user_with_defaults(500, "bob");
+        users.set_home_base(PathBuf::from("/Users"));
+        users.add_user_with_defaults(501, "fred");
+
+        assert_eq!(users.get_user_by_uid(500).unwrap().home_dir, Path::new("/home/bob"));
+        assert_eq!(users.get_user_by_uid(501).unwrap().home_dir, Path::new("/Users/fred"));
+        assert_eq!(users.get_user_by_uid(501).unwrap().shell, Path::new("/bin/bash"));
+    }
+
+    #[test]
+    fn audit_login_uid() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").build());
+        users.add_user(User::builder(1000, "fred").build());
+        assert_eq!(None, users.get_audit_login_uid());
+
+        // After sudo, the process is root's, but fred logged in.
+        users.set_login_name(Some("fred"));
+        assert_eq!(Some(1000), users.get_audit_login_uid());
+
+        users.set_login_name(Some("ghost"));
+        assert_eq!(None, users.get_audit_login_uid());
+    }
+
+    #[test]
+    fn uid_str() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1337, "fred").build());
+
+        assert_eq!(Some("fred".to_string()), users.get_user_by_uid_str("1337").unwrap().map(|u| u.name));
+        assert!(users.get_user_by_uid_str("1338").unwrap().is_none());
+        assert!(users.get_user_by_uid_str("fred").is_err());
+        assert!(users.get_user_by_uid_str("-1").is_err());
+        assert!(users.get_user_by_uid_str("").is_err());
+    }
+
+    #[test]
+    fn largest_ids() {
+        let mut users = MockUsers::with_current_uid(4294967294);
+        users.add_user(User::builder(4294967294, "nobody").primary_group(4294967294).build());
+        users.add_user(User::builder(0xFFFFFFFF, "last").build());
+        users.add_group(Group::builder(4294967294, "nogroup").build());
+
+        assert_eq!(users.get_user_by_uid(4294967294).map(|u| u.name), Some("nobody".to_string()));
+        assert_eq!(users.get_user_by_uid(0xFFFFFFFF).map(|u| u.name), Some("last".to_string()));
+        assert_eq!(users.get_current_username(), Some("nobody".to_string()));
+        assert_eq!(users.get_current_groupname(), Some("nogroup".to_string()));
+        assert_eq!(users.get_user_by_name("last").map(|u| u.uid), Some(0xFFFFFFFF));
+
+        assert_eq!(users.get_user_by_uid_str("4294967294").unwrap().map(|u| u.uid), Some(4294967294));
+        assert_eq!(users.get_user_by_uid_str("4294967295").unwrap().map(|u| u.uid), Some(0xFFFFFFFF));
+        assert!(users.get_user_by_uid_str("4294967296").is_err());
+        assert!(users.get_user_by_uid_str("-2").is_err());
+        assert_eq!(users.resolve_user("4294967294").map(|u| u.name), Some("nobody".to_string()));
+        assert_eq!(users.username_or_uid(4294967293), "4294967293");
+        assert_eq!(users.groupname_or_gid(0xFFFFFFFF), "4294967295");
+    }
+
+    #[test]
+    fn share_supplementary_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_user(User::builder(1001, "bob").primary_group(101).build());
+        users.add_group(Group::builder(100, "fred").build());
+        users.add_group(Group::builder(101, "bob").build());
+        users.add_group(Group::builder(200, "wheel").member("fred").build());
+        users.add_group(Group::builder(300, "audio").member("fred").member("bob").build());
+
+        assert_eq!(Some(300), users.users_share_group("fred", "bob").map(|g| g.gid));
+        assert_eq!(Some(300), users.users_share_group("bob", "fred").map(|g| g.gid));
+    }
+
+    #[test]
+    fn share_primary_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_user(User::builder(1001, "bob").primary_group(200).build());
+        users.add_group(Group::builder(100, "users").member("bob").build());
+        users.add_group(Group::builder(200, "bob").build());
+
+        assert_eq!(Some(100), users.users_share_group("fred", "bob").map(|g| g.gid));
+    }
+
+    #[test]
+    fn share_nothing() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_user(User::builder(1001, "bob").primary_group(101).build());
+        users.add_group(Group::builder(100, "fred").build());
+        users.add_group(Group::builder(101, "bob").build());
+        users.add_group(Group::builder(200, "wheel").member("fred").build());
+
+        assert!(users.users_share_group("fred", "bob").is_none());
+        assert!(users.users_share_group("fred", "jim").is_none());
+    }
+
+    #[test]
+    fn group_member_uids() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_user(User::builder(1001, "sue").primary_group(1001).build());
+        users.add_user(User::builder(1002, "barney").primary_group(100).build());
+        users.add_group(Group::builder(100, "users").member("sue").member("ghost").member("fred").member("sue").build());
+
+        assert_eq!(users.get_group_member_uids(100), vec![ 1001, 1000, 1002 ]);
+        assert_eq!(users.get_group_member_uids(1001), vec![ 1001 ]);
+        assert!(users.get_group_member_uids(200).is_empty());
+    }
+
+    #[test]
+    fn unresolved_members_are_traced() {
+        use super::super::{CachingUsers, CacheEvent};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut mock = MockUsers::with_current_uid(0);
+        mock.add_user(User::builder(1000, "fred").build());
+        mock.add_user(User::builder(1001, "sue").primary_group(100).build());
+        mock.add_group(Group::builder(100, "team").member("fred").member("ghost").build());
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = events.clone();
+        let mut users = CachingUsers::with_source(mock);
+        users.set_trace_hook(Box::new(move |e| recorded.borrow_mut().push(e)));
+
+        assert_eq!(users.get_group_member_uids(100), vec![ 1000, 1001 ]);
+        assert!(events.borrow().contains(&CacheEvent::UnresolvedMember { gid: 100 }));
+    }
+
+    #[test]
+    fn nothing_comes_from_the_system_through_a_mock() {
+        use super::super::{CachingUsers, IntegrityIssue};
+
+        let mut mock = MockUsers::with_current_uid(5000);
+        mock.add_user(User::builder(5000, "fred").primary_group(0).build());
+        mock.add_group(Group::builder(100, "staff").member("fred").build());
+        mock.set_process_groups(vec![ 100 ]);
+        let mut users = CachingUsers::with_source(mock);
+
+        assert_eq!(users.get_all_usernames(), vec![ "fred".to_string() ]);
+        assert_eq!(users.get_all_group_names(), vec![ "staff".to_string() ]);
+        assert_eq!(users.snapshot_users().len(), 1);
+        assert_eq!(users.snapshot_groups().len(), 1);
+        assert_eq!(users.get_group_members(0).into_iter().map(|u| u.name).collect::<Vec<_>>(), vec![ "fred".to_string() ]);
+        assert_eq!(users.validate_integrity(), vec![
+            IntegrityIssue::DanglingPrimaryGroup { username: "fred".to_string(), uid: 5000, gid: 0 },
+        ]);
+
+        let groups = users.get_user_groups("fred", 0).unwrap();
+        assert_eq!(groups.into_iter().map(|g| g.gid).collect::<Vec<_>>(), vec![ 100 ]);
+        assert_eq!(users.supplementary_gids_for("fred", 0), vec![ 0, 100 ]);
+        assert_eq!(users.count_user_groups("fred", 0), 2);
+        assert!(users.is_user_in_group("fred", "staff"));
+
+        assert_eq!(users.get_current_uid(), 5000);
+        assert_eq!(users.get_current_gid(), 0);
+        assert_eq!(users.get_effective_uid(), 5000);
+        assert_eq!(users.get_current_username(), Some("fred".to_string()));
+        assert_eq!(users.current_process_groups(), vec![ 100 ]);
+        assert_eq!(users.get_login_name(), None);
+        assert_eq!(users.get_audit_login_uid(), None);
+    }
+
+    #[test]
+    fn interned_names() {
+        use super::super::CachingUsers;
+        use std::rc::Rc;
+
+        let mut mock = MockUsers::with_current_uid(0);
+        mock.add_user(User::builder(1000, "fred").primary_group(100).build());
+        mock.add_user(User::builder(1001, "barney").primary_group(100).build());
+        mock.add_group(Group::builder(100, "users").build());
+        mock.add_group(Group::builder(200, "bowling").member("fred").member("barney").build());
+        mock.add_group(Group::builder(300, "quarry").member("barney").member("fred").build());
+        mock.add_group(Group::builder(1000, "fred").build());
+
+        let mut users = CachingUsers::with_source(mock).with_interning(true);
+        let fred = users.get_user_by_uid(1000).unwrap();
+        let barney = users.get_user_by_uid(1001).unwrap();
+
+        // Reading the group again makes a new cache entry, but its name is
+        // still the interned one, and that's what both users get.
+        let fred_group = users.get_group_name_rc(fred.primary_group).unwrap();
+        users.forget_group(100);
+        let barney_group = users.get_group_name_rc(barney.primary_group).unwrap();
+        assert_eq!(&*fred_group, "users");
+        assert!(Rc::ptr_eq(&fred_group, &barney_group));
+        assert!(Rc::ptr_eq(&barney_group, &users.groups.borrow()[&100].0.as_ref().unwrap().name));
+
+        // Both users' names are stored once, however many cached groups
+        // list them.
+        users.get_group_by_gid(200);
+        users.get_group_by_gid(300);
+        {
+            let groups = users.groups.borrow();
+            let bowling = &groups[&200].0.as_ref().unwrap().members;
+            let quarry = &groups[&300].0.as_ref().unwrap().members;
+            assert!(Rc::ptr_eq(&bowling[0], &quarry[1]));
+            assert!(Rc::ptr_eq(&bowling[1], &quarry[0]));
+
+            let users_back = users.users_back.borrow();
+            assert!(Rc::ptr_eq(users_back.get_key_value("fred").unwrap().0, &bowling[0]));
+        }
+
+        // Names are shared between users and groups too.
+        assert!(Rc::ptr_eq(&users.get_username_rc(1000).unwrap(), &users.get_group_name_rc(1000).unwrap()));
+        assert!(users.get_username_rc(1002).is_none());
+
+        let mut users = users.with_interning(false);
+        users.flush();
+        users.get_group_by_gid(200);
+        users.get_group_by_gid(300);
+        let groups = users.groups.borrow();
+        assert!(!Rc::ptr_eq(&groups[&200].0.as_ref().unwrap().members[0], &groups[&300].0.as_ref().unwrap().members[1]));
+    }
+
+    #[test]
+    fn cached_over_mock() {
+        use super::super::{CachingUsers, CacheEvent};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut mock = MockUsers::with_current_uid(0);
+        mock.add_user(User::builder(1000, "fred").build());
+        mock.add_group(Group::builder(100, "users").build());
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = events.clone();
+        let mut users: CachingUsers<MockUsers> = CachingUsers::with_source(mock).with_negative_caching(true);
+        users.set_trace_hook(Box::new(move |e| recorded.borrow_mut().push(e)));
+
+        assert_eq!(Some(1000), users.get_user_by_name("fred").map(|u| u.uid));
+        assert_eq!(Some("fred".to_string()), users.get_user_by_uid(1000).map(|u| u.name));
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert_eq!(Some("users".to_string()), users.get_group_by_gid(100).map(|g| g.name));
+
+        assert_eq!(*events.borrow(), vec![
+            CacheEvent::LibcCall { which: "getpwnam_r" },
+            CacheEvent::Hit { uid: 1000 },
+            CacheEvent::Miss { uid: 1001 },
+            CacheEvent::LibcCall { which: "getpwuid_r" },
+            CacheEvent::Hit { uid: 1001 },
+            CacheEvent::GroupMiss { gid: 100 },
+            CacheEvent::LibcCall { which: "getgrgid_r" },
+        ]);
+    }
+
+    #[test]
+    fn all_names() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1440, "fred").build());
+        users.add_user(User::builder(1337, "bob").build());
+        users.add_group(Group::builder(300, "audio").member("fred").build());
+        users.add_group(Group::builder(100, "users").build());
+
+        assert_eq!(users.get_all_usernames(), vec![ "bob", "fred" ]);
+        assert_eq!(users.get_all_group_names(), vec![ "users", "audio" ]);
+    }
+
+    #[test]
+    fn names_or_ids() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1337, "fred").build());
+        users.add_group(Group::builder(100, "users").build());
+
+        assert_eq!(users.username_or_uid(1337), "fred");
+        assert_eq!(users.username_or_uid(1338), "1338");
+        assert_eq!(users.groupname_or_gid(100), "users");
+        assert_eq!(users.groupname_or_gid(101), "101");
+    }
+
+    #[test]
+    fn login_allowed() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").shell("/bin/bash").build());
+        users.add_user(User::builder(1001, "bob").shell("/bin/bash").passwd("!").build());
+        users.add_user(User::builder(1002, "sue").shell("/usr/sbin/nologin").build());
+
+        assert!(users.is_login_allowed("fred"));
+        assert!(!users.is_login_allowed("bob"));
+        assert!(!users.is_login_allowed("sue"));
+        assert!(!users.is_login_allowed("jim"));
+    }
+
+    #[test]
+    fn user_in_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).gecos("Fred,,,").build());
+        users.add_group(Group::builder(100, "fred").build());
+        users.add_group(Group::builder(200, "wheel").member("fred").build());
+        users.add_group(Group::builder(300, "audio").member("bob").build());
+
+        assert!(users.is_user_in_group("fred", "fred"));
+        assert!(users.is_user_in_group("fred", "wheel"));
+        assert!(!users.is_user_in_group("fred", "audio"));
+        assert!(!users.is_user_in_group("fred", "video"));
+        assert!(!users.is_user_in_group("bob", "audio"));
+    }
+
+    #[test]
+    fn remove_user() {
+        let mut users = MockUsers::with_current_uid(1337);
+        users.add_user(User::builder(1337, "fred").primary_group(101).gecos("Fred,,,").build());
+
+        assert_eq!(Some("fred".to_string()), users.remove_user(1337).map(|u| u.name));
+        assert!(users.get_user_by_uid(1337).is_none());
+        assert!(users.get_user_by_name("fred").is_none());
+        assert_eq!(None, users.get_current_username());
+        assert!(users.remove_user(1337).is_none());
+    }
+
+    #[test]
+    fn remove_shared_user() {
+        let mut users = MockUsers::with_current_uid(1337);
+        users.add_user(User::builder(1337, "fred").primary_group(101).gecos("Fred,,,").build());
+
+        // A user that's still shared elsewhere gets cloned on the way out.
+        let shared = users.get_user_by_uid_rc(1337).unwrap();
+        assert_eq!(Some(1337), users.remove_user(1337).map(|u| u.uid));
+        assert_eq!("fred", shared.name);
+    }
+
+    #[test]
+    fn remove_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_group(Group::builder(200, "wheel").build());
+
+        assert_eq!(Some("wheel".to_string()), users.remove_group(200).map(|g| g.name));
+        assert!(users.get_group_by_gid(200).is_none());
+        assert!(users.get_group_by_name("wheel").is_none());
+        assert!(users.remove_group(200).is_none());
+    }
+
+    #[test]
+    fn current_user_groups() {
+        let mut users = MockUsers::with_current_uid(1000);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(100, "users").build());
+        users.add_group(Group::builder(200, "wheel").build());
+        users.add_group(Group::builder(300, "audio").build());
+        users.add_user_to_group(300, "fred");
+
+        let gids: Vec<_> = users.get_current_user_groups().into_iter().map(|g| g.gid).collect();
+        assert_eq!(gids, vec![ 100, 300 ]);
+    }
+
+    #[test]
+    fn no_current_user_groups() {
+        let mut users = MockUsers::with_current_uid(1000);
+        users.add_group(Group::builder(100, "users").member("fred").build());
+        assert!(users.get_current_user_groups().is_empty());
+    }
+
+    #[test]
+    fn add_user_to_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(200, "wheel").build());
+
+        assert!(!users.is_user_in_group("fred", "wheel"));
+        assert!(users.add_user_to_group(200, "fred"));
+        assert!(users.is_user_in_group("fred", "wheel"));
+
+        assert!(!users.add_user_to_group(200, "fred"));
+        assert!(!users.add_user_to_group(300, "fred"));
+        assert_eq!(Some(vec![ "fred".to_string() ]), users.get_group_by_gid(200).map(|g| g.members));
+    }
+
+    #[test]
+    fn remove_user_from_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(200, "wheel").member("fred").member("bob").build());
+
+        assert!(users.remove_user_from_group(200, "fred"));
+        assert!(!users.is_user_in_group("fred", "wheel"));
+
+        assert!(!users.remove_user_from_group(200, "fred"));
+        assert!(!users.remove_user_from_group(300, "bob"));
+        assert_eq!(Some(vec![ "bob".to_string() ]), users.get_group_by_gid(200).map(|g| g.members));
+    }
+
+    #[test]
+    fn current_user() {
+        let mut users = MockUsers::with_current_uid(1337);
+        users.add_user(User::builder(1337, "fred").build());
+        assert_eq!(Some("fred".to_string()), users.get_current_user().map(|u| u.name));
+        assert_eq!(Some("fred".to_string()), users.get_effective_user().map(|u| u.name));
+    }
+
+    #[test]
+    fn no_current_user() {
+        let mut users = MockUsers::with_current_uid(1337);
+        users.add_user(User::builder(1000, "fred").build());
+        assert!(users.get_current_user().is_none());
+        assert!(users.get_effective_user().is_none());
+    }
+
+    #[test]
+    fn root() {
+        let mut users = MockUsers::with_current_uid(0);
+        assert!(users.is_root());
+    }
+
+    #[test]
+    fn not_root() {
+        let mut users = MockUsers::with_current_uid(1000);
+        assert!(!users.is_root());
+    }
+
+    #[test]
+    fn primary_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        let fred = User::builder(1000, "fred").primary_group(100).build();
+        users.add_group(Group::builder(100, "users").build());
+        assert_eq!(Some("users".to_string()), users.get_user_primary_group(&fred).map(|g| g.name));
+    }
+
+    #[test]
+    fn no_primary_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        let fred = User::builder(1000, "fred").primary_group(100).build();
+        assert!(users.get_user_primary_group(&fred).is_none());
+    }
+
+    #[test]
+    fn primary_group_name() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(100, "users").build());
+        assert_eq!(Some("users".to_string()), users.get_user_primary_group_name("fred"));
+        assert_eq!(None, users.get_user_primary_group_name("bob"));
+    }
+
+    #[test]
+    fn dangling_primary_group_name() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        assert_eq!(None, users.get_user_primary_group_name("fred"));
+    }
+
+    #[test]
+    fn group_members() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_user(User::builder(1001, "bob").primary_group(200).build());
+        users.add_user(User::builder(1002, "sue").primary_group(300).build());
+        users.add_group(Group::builder(100, "users").member("fred").member("bob").member("nobody").build());
+
+        let uids: Vec<_> = users.get_group_members(100).into_iter().map(|u| u.uid).collect();
+        assert_eq!(vec![ 1000, 1001 ], uids);
+    }
+
+    #[test]
+    fn group_members_without_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        assert_eq!(vec![ 1000 ], users.get_group_members(100).into_iter().map(|u| u.uid).collect::<Vec<_>>());
+        assert!(users.get_group_members(200).is_empty());
+    }
+
+    #[test]
+    fn effective_ids() {
+        let mut users = MockUsers::with_current_uid_and_gid(1000, 100);
+        users.add_user(User::builder(0, "root").build());
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(0, "root").build());
+        users.add_group(Group::builder(100, "users").build());
+        users.set_effective_uid(0);
+
+        assert_eq!(1000, users.get_current_uid());
+        assert_eq!(0, users.get_effective_uid());
+        assert_eq!(Some("fred".to_string()), users.get_current_username());
+        assert_eq!(Some("root".to_string()), users.get_effective_username());
+        assert!(users.is_root());
+
+        // The group stays the same until it's changed too.
+        assert_eq!(Some("users".to_string()), users.get_effective_groupname());
+        users.set_effective_gid(0);
+        assert_eq!(Some("users".to_string()), users.get_current_groupname());
+        assert_eq!(Some("root".to_string()), users.get_effective_groupname());
+    }
+
+    #[test]
+    fn can_switch_to() {
+        let mut users = MockUsers::with_current_uid(1000);
+        assert!(users.can_switch_to(1000));
+        assert!(!users.can_switch_to(1001));
+        assert!(!users.can_switch_to(0));
+
+        users.set_effective_uid(0);
+        assert!(users.can_switch_to(1001));
+        assert!(users.can_switch_to(0));
+
+        // Only the effective user ID counts.
+        users.set_current_uid(0);
+        users.set_effective_uid(1000);
+        assert!(!users.can_switch_to(1001));
+    }
+
+    #[test]
+    fn duplicate_uids() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").shell("/bin/bash").build());
+        users.add_duplicate_user(User::builder(0, "toor").shell("/bin/sh").build());
+
+        assert_eq!(Some("root".to_string()), users.get_user_by_uid(0).map(|u| u.name));
+        assert_eq!(Some(0), users.get_user_by_name("toor").map(|u| u.uid));
+
+        let names: Vec<_> = users.get_all_users_by_uid(0).into_iter().map(|u| u.name).collect();
+        assert_eq!(vec![ "root".to_string(), "toor".to_string() ], names);
+        assert_eq!(2, users.all_users().count());
+    }
+
+    #[test]
+    fn replace_user_keeps_duplicates() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").build());
+        users.add_duplicate_user(User::builder(0, "toor").build());
+
+        assert_eq!(Some("root".to_string()), users.add_user(User::builder(0, "admin").build()).map(|u| u.name));
+        let names: Vec<_> = users.get_all_users_by_uid(0).into_iter().map(|u| u.name).collect();
+        assert_eq!(vec![ "admin".to_string(), "toor".to_string() ], names);
+
+        users.remove_user(0);
+        assert!(users.get_all_users_by_uid(0).is_empty());
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").build());
+        users.add_duplicate_user(User::builder(0, "toor").build());
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_group(Group::builder(100, "users").build());
+
+        let snapshot = users.snapshot_users();
+        assert_eq!(2, snapshot.len());
+        assert_eq!(Some("root".to_string()), snapshot.get(&0).map(|u| u.name.clone()));
+        assert_eq!(vec![ 100 ], users.snapshot_groups().keys().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn from_files() {
+        use std::path::Path;
+
+        let passwd = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/mock/passwd"));
+        let group = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/mock/group"));
+        let mut users = MockUsers::from_files(passwd, group).unwrap();
+
+        assert_eq!(Some("root".to_string()), users.get_current_username());
+        assert_eq!(Some("toor".to_string()), users.get_all_users_by_uid(0).pop().map(|u| u.name));
+        assert!(users.is_user_in_group("fred", "wheel"));
+
+        users.set_current_uid(1000);
+        assert_eq!(Some("fred".to_string()), users.get_current_username());
+        assert_eq!(Some("users".to_string()), users.get_current_groupname());
+    }
+
+    #[test]
+    fn from_files_with_bad_line() {
+        use std::path::Path;
+
+        let passwd = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/passwd"));
+        let group = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/mock/group"));
+        let error = MockUsers::from_files(passwd, group).err().unwrap();
+        assert!(error.to_string().ends_with("passwd, line 6: passwd line has too few fields (1, expected 7)"));
+    }
+
+    #[test]
+    fn case_insensitive_name() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+
+        assert!(users.get_user_by_name("Fred").is_none());
+        assert_eq!(Some(1000), users.get_user_by_name_ci("Fred").map(|u| u.uid));
+        assert_eq!(Some(1000), users.get_user_by_name_ci("FRED").map(|u| u.uid));
+        assert!(users.get_user_by_name_ci("Fredd").is_none());
+    }
+
+    #[test]
+    fn case_insensitive_name_prefers_exact() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_user(User::builder(1001, "Fred").build());
+        assert_eq!(Some(1001), users.get_user_by_name_ci("Fred").map(|u| u.uid));
+    }
+
+    #[test]
+    fn resolve_user() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+
+        assert_eq!(Some("fred".to_string()), users.resolve_user("1000").map(|u| u.name));
+        assert_eq!(Some(1000), users.resolve_user("fred").map(|u| u.uid));
+        assert!(users.resolve_user("1001").is_none());
+        assert!(users.resolve_user("barney").is_none());
+    }
+
+    #[test]
+    fn resolve_user_numeric_name() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_user(User::builder(2000, "1000").build());
+        users.add_user(User::builder(3000, "1234").build());
+
+        // The ID wins over a user whose name is the same number...
+        assert_eq!(Some(1000), users.resolve_user("1000").map(|u| u.uid));
+        // ...but the name is still found if no user has that ID.
+        assert_eq!(Some(3000), users.resolve_user("1234").map(|u| u.uid));
+    }
+
+    #[test]
+    fn resolve_group() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_group(Group::builder(100, "users").build());
+        users.add_group(Group::builder(200, "100").build());
+
+        assert_eq!(Some("users".to_string()), users.resolve_group("100").map(|g| g.name));
+        assert_eq!(Some(100), users.resolve_group("users").map(|g| g.gid));
+        assert!(users.resolve_group("wheel").is_none());
+        assert!(users.resolve_group("300").is_none());
+    }
+
+    #[test]
+    fn validate_integrity() {
+        use super::super::IntegrityIssue;
+
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").primary_group(0).build());
+        users.add_duplicate_user(User::builder(0, "toor").primary_group(0).build());
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(0, "root").build());
+        assert_eq!(users.validate_integrity(), vec![
+            IntegrityIssue::DanglingPrimaryGroup { username: "fred".to_string(), uid: 1000, gid: 100 },
+            IntegrityIssue::DuplicateUid { uid: 0, usernames: vec![ "root".to_string(), "toor".to_string() ] },
+        ]);
+
+        users.add_group(Group::builder(100, "users").member("fred").build());
+        users.add_duplicate_user(User::builder(1000, "barney").primary_group(100).build());
+        assert_eq!(users.validate_integrity().len(), 2);
+    }
+
+    #[test]
+    fn users_by_shell() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").shell("/bin/bash").build());
+        users.add_user(User::builder(1, "daemon").shell("/usr/sbin/nologin").build());
+        users.add_user(User::builder(2, "sync").shell("/bin/sync").build());
+        users.add_user(User::builder(3, "games").shell("/bin/false").build());
+        users.add_user(User::builder(1000, "fred").shell("/bin/bash").build());
+        users.add_user(User::builder(1001, "sue").shell("/usr/bin/bash").build());
+
+        let names = |us: Vec<User>| us.into_iter().map(|u| u.name).collect::<Vec<_>>();
+        assert_eq!(names(users.users_with_shell(Path::new("/bin/bash"))), vec![ "root", "fred" ]);
+        assert_eq!(names(users.users_with_shell(Path::new("/usr/bin/bash"))), vec![ "sue" ]);
+        assert!(users.users_with_shell(Path::new("/bin/zsh")).is_empty());
+        assert_eq!(names(users.users_without_login_shell()), vec![ "daemon", "games" ]);
+    }
+
+    #[test]
+    fn users_in_range() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").build());
+        users.add_user(User::builder(999, "systemd-network").build());
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_user(User::builder(1001, "sue").build());
+        users.add_user(User::builder(60000, "last").build());
+        users.add_user(User::builder(65534, "nobody").build());
+
+        let names: Vec<String> = users.users_in_range(1000..60001).into_iter().map(|u| u.name).collect();
+        assert_eq!(names, vec![ "fred", "sue", "last" ]);
+        assert!(users.users_in_range(2000..3000).is_empty());
+    }
+
+    #[test]
+    fn count_user_groups() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(1000).build());
+        users.add_group(Group::builder(1000, "fred").build());
+        users.add_group(Group::builder(10, "wheel").member("fred").build());
+        users.add_group(Group::builder(20, "audio").member("sue").member("fred").build());
+        users.add_group(Group::builder(30, "video").member("fred").build());
+        users.add_group(Group::builder(40, "games").member("sue").build());
+
+        assert_eq!(users.count_user_groups("fred", 1000), 4);
+        assert_eq!(users.count_user_groups("fred", 10), 3);
+        assert_eq!(users.count_user_groups("barney", 1000), 1);
+    }
+
+    #[test]
+    fn cow_lookups_borrow() {
+        use super::UsersRead;
+        use std::borrow::Cow;
+
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+
+        match UsersRead::get_user_by_uid_cow(&users, 1000) {
+            Some(Cow::Borrowed(fred)) => assert!(::std::ptr::eq(fred, &*users.users[&1000])),
+            _ => panic!("expected a borrowed user"),
+        }
+
+        assert!(UsersRead::get_user_by_uid_cow(&users, 1001).is_none());
+    }
+
+    #[test]
+    fn whoami() {
+        let mut users = MockUsers::with_current_uid_and_gid(1000, 100);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(100, "users").build());
+        users.add_group(Group::builder(10, "wheel").build());
+        users.set_process_groups(vec![ 100, 10, 30 ]);
+        users.set_effective_uid(0);
+
+        let identity = users.whoami();
+        assert_eq!((identity.uid, identity.gid, identity.euid, identity.egid), (1000, 100, 0, 100));
+        assert_eq!(identity.username, Some("fred".to_string()));
+        assert_eq!(identity.groups.into_iter().map(|g| g.name).collect::<Vec<_>>(), vec![ "users", "wheel" ]);
+    }
+
+    #[test]
+    fn process_groups() {
+        let mut users = MockUsers::with_current_uid(1000);
+        users.add_group(Group::builder(10, "wheel").build());
+        users.add_group(Group::builder(20, "audio").build());
+        assert!(users.current_process_groups().is_empty());
+
+        users.set_process_groups(vec![ 20, 10, 30 ]);
+        assert_eq!(users.current_process_groups(), vec![ 20, 10, 30 ]);
+        assert_eq!(users.current_process_group_names(), vec![ "audio", "wheel" ]);
+    }
+
+    #[test]
+    fn get_users_by_uids() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_user(User::builder(1001, "sue").build());
+
+        let names: Vec<Option<String>> = users.get_users_by_uids(&[ 1001, 1000, 1002, 1001 ])
+            .into_iter().map(|u| u.map(|u| u.name)).collect();
+        assert_eq!(names, vec![ Some("sue".to_string()), Some("fred".to_string()), None, Some("sue".to_string()) ]);
+        assert!(users.get_users_by_uids(&[]).is_empty());
+    }
+
+    #[test]
+    fn filter_orphan_uids() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_user(User::builder(1001, "sue").build());
+
+        assert_eq!(users.filter_orphan_uids(&[ 1003, 1000, 1002, 1003, 1001, 1002 ]), vec![ 1003, 1002 ]);
+        assert!(users.filter_orphan_uids(&[ 1000, 1001 ]).is_empty());
+        assert!(users.filter_orphan_uids(&[]).is_empty());
+    }
+
+    #[test]
+    fn get_groups_by_gids() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_group(Group::builder(10, "wheel").build());
+
+        let gids: Vec<Option<u32>> = users.get_groups_by_gids(&[ 10, 20, 10 ])
+            .into_iter().map(|g| g.map(|g| g.gid)).collect();
+        assert_eq!(gids, vec![ Some(10), None, Some(10) ]);
+    }
+
+    #[test]
+    fn users_in_nested_groups() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_user(User::builder(1001, "sue").build());
+        users.add_user(User::builder(1002, "barney").build());
+        users.add_group(Group::builder(10, "staff").member("fred").member("admins").build());
+        users.add_group(Group::builder(20, "admins").member("sue").member("ops").build());
+        users.add_group(Group::builder(30, "ops").member("barney").member("fred").build());
+
+        let uids = |list: Vec<User>| list.into_iter().map(|u| u.uid).collect::<Vec<_>>();
+        assert_eq!(uids(users.get_users_in_group_recursive("staff", 0)), vec![ 1000 ]);
+        assert_eq!(uids(users.get_users_in_group_recursive("staff", 1)), vec![ 1000, 1001 ]);
+        assert_eq!(uids(users.get_users_in_group_recursive("staff", 2)), vec![ 1000, 1001, 1002 ]);
+        assert!(users.get_users_in_group_recursive("nobody", 5).is_empty());
+    }
+
+    #[test]
+    fn users_in_group_cycle() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_user(User::builder(1001, "sue").build());
+        users.add_group(Group::builder(10, "left").member("fred").member("right").build());
+        users.add_group(Group::builder(20, "right").member("sue").member("left").build());
+
+        let mut uids: Vec<_> = users.get_users_in_group_recursive("left", 100).into_iter().map(|u| u.uid).collect();
+        uids.sort();
+        assert_eq!(uids, vec![ 1000, 1001 ]);
+    }
+
+    #[test]
+    fn users_in_group_prefers_users() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").build());
+        users.add_group(Group::builder(1000, "fred").member("sue").build());
+        users.add_group(Group::builder(10, "wheel").member("fred").build());
+
+        let names: Vec<_> = users.get_users_in_group_recursive("wheel", 3).into_iter().map(|u| u.name).collect();
+        assert_eq!(names, vec![ "fred" ]);
+    }
+
+    #[test]
+    fn supplementary_gids_for() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_user(User::builder(1001, "sue").primary_group(100).build());
+        users.add_group(Group::builder(100, "users").build());
+        users.add_group(Group::builder(30, "video").member("fred").build());
+        users.add_group(Group::builder(10, "wheel").member("fred").build());
+
+        assert_eq!(users.supplementary_gids_for("fred", 100), vec![ 100, 10, 30 ]);
+        assert_eq!(users.supplementary_gids_for("sue", 100), vec![ 100 ]);
+        assert_eq!(users.supplementary_gids_for("barney", 555), vec![ 555 ]);
+    }
+
+    #[test]
+    fn switch_current_user() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(0, "root").build());
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+        users.add_group(Group::builder(0, "root").build());
+        users.add_group(Group::builder(100, "users").build());
+        users.add_group(Group::builder(10, "wheel").build());
+        assert_eq!(Some("root".to_string()), users.get_current_username());
+
+        users.set_current_uid(1000);
+        assert_eq!(1000, users.get_current_uid());
+        assert_eq!(Some("fred".to_string()), users.get_current_username());
+        assert_eq!(Some("fred".to_string()), users.get_effective_username());
+        assert_eq!(Some("users".to_string()), users.get_current_groupname());
+        assert!(!users.is_root());
+
+        users.set_current_gid(10);
+        assert_eq!(10, users.get_current_gid());
+        assert_eq!(Some("wheel".to_string()), users.get_current_groupname());
+        assert_eq!(Some("wheel".to_string()), users.get_effective_groupname());
+    }
+
+    #[test]
+    fn user_id_by_name() {
+        use super::super::UserId;
+
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").primary_group(100).build());
+
+        let full = users.get_user_by_name("fred").unwrap();
+        let ids = users.get_user_id_by_name("fred").unwrap();
+        assert_eq!(ids, UserId { uid: 1000, gid: 100 });
+        assert_eq!(ids, UserId::from(&full));
+        assert!(users.get_user_id_by_name("sue").is_none());
+    }
+
+    #[test]
+    fn home_dir_and_shell() {
+        use std::path::PathBuf;
+
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(1000, "fred").home_dir("/home/fred").shell("/bin/zsh").build());
+
+        assert_eq!(users.get_home_dir("fred"), Some(PathBuf::from("/home/fred")));
+        assert_eq!(users.get_shell("fred"), Some(PathBuf::from("/bin/zsh")));
+        assert_eq!(users.get_home_dir("sue"), None);
+        assert_eq!(users.get_shell("sue"), None);
+    }
+
+    #[test]
+    fn expand_tilde() {
+        use std::path::PathBuf;
+
+        let mut users = MockUsers::with_current_uid(1001);
+        users.add_user(User::builder(1000, "fred").home_dir("/home/fred").build());
+        users.add_user(User::builder(1001, "sue").home_dir("/home/sue").build());
+
+        assert_eq!(users.expand_tilde("~"), Some(PathBuf::from("/home/sue")));
+        assert_eq!(users.expand_tilde("~/notes"), Some(PathBuf::from("/home/sue/notes")));
+        assert_eq!(users.expand_tilde("~fred"), Some(PathBuf::from("/home/fred")));
+        assert_eq!(users.expand_tilde("~fred/x"), Some(PathBuf::from("/home/fred/x")));
+        assert_eq!(users.expand_tilde("~nobody"), None);
+        assert_eq!(users.expand_tilde("~nobody/x"), None);
+        assert_eq!(users.expand_tilde("/etc/~fred"), Some(PathBuf::from("/etc/~fred")));
+    }
+
+    #[test]
+    fn nobody() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::builder(99, "nobody").build());
+        users.add_group(Group::builder(99, "nobody").build());
+
+        assert_eq!(users.get_nobody_user().map(|u| u.uid), Some(99));
+        assert_eq!(users.get_nobody_uid(), 99);
+        assert_eq!(users.get_nogroup().map(|g| g.name), Some("nobody".to_string()));
+
+        users.add_group(Group::builder(65534, "nogroup").build());
+        assert_eq!(users.get_nogroup_gid(), 65534);
+    }
+
+    #[test]
+    fn nobody_fallback() {
+        use super::super::{NOBODY_UID, NOGROUP_GID};
+
+        let mut users = MockUsers::with_current_uid(0);
+        assert!(users.get_nobody_user().is_none());
+        assert_eq!(users.get_nobody_uid(), NOBODY_UID);
+        assert_eq!(users.get_nogroup_gid(), NOGROUP_GID);
+
+        users.add_user(User::builder(NOBODY_UID, "unprivileged").build());
+        assert_eq!(users.get_nobody_user().map(|u| u.name), Some("unprivileged".to_string()));
+    }
+
     #[test]
     fn no_gid() {
         let mut users = MockUsers::with_current_uid(0);
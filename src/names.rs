@@ -0,0 +1,136 @@
+//! Checking whether a string can be used as a user or group name.
+//!
+//! These follow the rules `useradd` and `groupadd` apply by default: a name
+//! starts with a lowercase letter or an underscore, continues with lowercase
+//! letters, digits, underscores, or hyphens, may end with a `$` (as Samba
+//! machine accounts do), and is at most 32 characters long.
+
+use std::error::Error;
+use std::fmt;
+
+/// The longest user or group name that's accepted.
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// The reason a user or group name isn't valid.
+#[derive(PartialEq, Debug, Clone)]
+pub enum NameError {
+
+    /// The name is empty.
+    Empty,
+
+    /// The name is longer than `MAX_NAME_LENGTH` characters.
+    TooLong(usize),
+
+    /// The name starts with a character that isn't a lowercase letter or an
+    /// underscore.
+    InvalidFirstCharacter(char),
+
+    /// The name contains a character that isn't allowed anywhere in it.
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NameError::Empty                    => write!(f, "name is empty"),
+            NameError::TooLong(len)             => write!(f, "name is {} characters long, but the limit is {}", len, MAX_NAME_LENGTH),
+            NameError::InvalidFirstCharacter(c) => write!(f, "name can't start with {:?}", c),
+            NameError::InvalidCharacter(c)      => write!(f, "name can't contain {:?}", c),
+        }
+    }
+}
+
+impl Error for NameError {}
+
+/// Check that the given string can be used as a username, explaining why
+/// not if it can't.
+pub fn validate_username(name: &str) -> Result<(), NameError> {
+    validate_name(name)
+}
+
+/// Check that the given string can be used as a group name, explaining why
+/// not if it can't.
+pub fn validate_groupname(name: &str) -> Result<(), NameError> {
+    validate_name(name)
+}
+
+/// Return whether the given string can be used as a username.
+pub fn is_valid_username(name: &str) -> bool {
+    validate_username(name).is_ok()
+}
+
+/// Return whether the given string can be used as a group name.
+pub fn is_valid_groupname(name: &str) -> bool {
+    validate_groupname(name).is_ok()
+}
+
+fn validate_name(name: &str) -> Result<(), NameError> {
+    let len = name.chars().count();
+    if len == 0 {
+        return Err(NameError::Empty);
+    }
+    else if len > MAX_NAME_LENGTH {
+        return Err(NameError::TooLong(len));
+    }
+
+    let body = if len > 1 { name.strip_suffix('$').unwrap_or(name) } else { name };
+    let mut chars = body.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_lowercase() || c == '_' => {},
+        Some(c) => return Err(NameError::InvalidFirstCharacter(c)),
+        None => unreachable!(),
+    }
+
+    match chars.find(|&c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')) {
+        Some(c) => Err(NameError::InvalidCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NameError, validate_username, validate_groupname, is_valid_username, is_valid_groupname};
+
+    #[test]
+    fn valid() {
+        assert!(is_valid_username("fred"));
+        assert!(is_valid_username("_apt"));
+        assert!(is_valid_username("www-data"));
+        assert!(is_valid_username("user2"));
+        assert!(is_valid_username("host$"));
+        assert!(is_valid_groupname("wheel"));
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(validate_username(""), Err(NameError::Empty));
+        assert!(!is_valid_groupname(""));
+    }
+
+    #[test]
+    fn leading_digit() {
+        assert_eq!(validate_username("2fred"), Err(NameError::InvalidFirstCharacter('2')));
+        assert_eq!(validate_username("-fred"), Err(NameError::InvalidFirstCharacter('-')));
+        assert_eq!(validate_username("$"), Err(NameError::InvalidFirstCharacter('$')));
+    }
+
+    #[test]
+    fn too_long() {
+        let name = "a".repeat(33);
+        assert_eq!(validate_username(&name), Err(NameError::TooLong(33)));
+        assert!(is_valid_username(&name[1..]));
+    }
+
+    #[test]
+    fn embedded_space() {
+        assert_eq!(validate_username("fred flintstone"), Err(NameError::InvalidCharacter(' ')));
+        assert_eq!(validate_groupname("Wheel"), Err(NameError::InvalidFirstCharacter('W')));
+        assert_eq!(validate_groupname("wh$el"), Err(NameError::InvalidCharacter('$')));
+    }
+
+    #[test]
+    fn message() {
+        assert_eq!(NameError::TooLong(40).to_string(), "name is 40 characters long, but the limit is 32");
+    }
+}
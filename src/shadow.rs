@@ -0,0 +1,142 @@
+//! Account ageing information from the shadow password database.
+//!
+//! The shadow database is usually only readable by root. Reading it as any
+//! other user fails with `EACCES`, which the `try_` function reports as an
+//! error and `get_shadow_by_name` treats the same as a missing entry.
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::ptr::{self, read};
+
+use libc::{c_char, c_int, c_long, c_ulong, size_t, ENOENT};
+
+use super::{from_raw_buf, with_growing_buffer};
+
+#[repr(C)]
+struct c_spwd {
+    sp_namp:   *const c_char,  // user name
+    sp_pwdp:   *const c_char,  // password hash
+    sp_lstchg: c_long,         // day of last change
+    sp_min:    c_long,         // minimum days between changes
+    sp_max:    c_long,         // maximum days between changes
+    sp_warn:   c_long,         // days of warning before expiry
+    sp_inact:  c_long,         // days before an expired password locks the account
+    sp_expire: c_long,         // day the account expires
+    sp_flag:   c_ulong,        // reserved
+}
+
+extern "C" {
+    fn getspnam_r(name: *const c_char, spbuf: *mut c_spwd, buf: *mut c_char, buflen: size_t, spbufp: *mut *mut c_spwd) -> c_int;
+}
+
+/// A user's entry in the shadow password database.
+///
+/// Days are counted from the Unix epoch, and any field that isn't set in
+/// the database is -1.
+#[derive(Clone)]
+pub struct ShadowEntry {
+
+    /// The user's name
+    pub name: String,
+
+    /// The user's password hash, or a marker such as `!` or `*` for a
+    /// locked account
+    pub passwd: String,
+
+    /// The day the password was last changed
+    pub last_change: i64,
+
+    /// The number of days before the password may be changed again
+    pub min_days: i64,
+
+    /// The number of days before the password must be changed
+    pub max_days: i64,
+
+    /// The number of days before the password expires that the user is
+    /// warned about it
+    pub warn_days: i64,
+
+    /// The number of days after the password expires that the account is
+    /// disabled
+    pub inactive_days: i64,
+
+    /// The day the account expires
+    pub expire: i64,
+}
+
+unsafe fn spwd_to_entry(pointer: *const c_spwd) -> ShadowEntry {
+    let sp = read(pointer);
+    ShadowEntry {
+        name:          from_raw_buf(sp.sp_namp),
+        passwd:        from_raw_buf(sp.sp_pwdp),
+        last_change:   sp.sp_lstchg as i64,
+        min_days:      sp.sp_min as i64,
+        max_days:      sp.sp_max as i64,
+        warn_days:     sp.sp_warn as i64,
+        inactive_days: sp.sp_inact as i64,
+        expire:        sp.sp_expire as i64,
+    }
+}
+
+/// Return the shadow entry for the given username, `Ok(None)` if there
+/// isn't one, or the error from libc if the database couldn't be read. This
+/// is usually `EACCES` when not running as root.
+pub fn try_get_shadow_by_name(username: &str) -> io::Result<Option<ShadowEntry>> {
+    // A name containing a '\0' can't be in the database.
+    let username_c = match CString::new(username) {
+        Ok(c) => c,
+        Err(_) => return Ok(None),
+    };
+
+    with_growing_buffer(1024, |buffer| {
+        let mut spwd: c_spwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        unsafe {
+            let ret = getspnam_r(username_c.as_ptr(), &mut spwd, buffer.as_mut_ptr(), buffer.len() as size_t, &mut result);
+            if !result.is_null() {
+                Ok(Some(spwd_to_entry(result)))
+            }
+            else if ret == 0 || ret == ENOENT {
+                Ok(None)
+            }
+            else {
+                Err(io::Error::from_raw_os_error(ret))
+            }
+        }
+    })
+}
+
+/// Return the shadow entry for the given username if one exists and can be
+/// read; otherwise, return None.
+pub fn get_shadow_by_name(username: &str) -> Option<ShadowEntry> {
+    try_get_shadow_by_name(username).unwrap_or(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_shadow_by_name, try_get_shadow_by_name};
+    use libc::{geteuid, EACCES};
+
+    #[test]
+    fn root_entry() {
+        match try_get_shadow_by_name("root") {
+            Ok(entry) => {
+                let entry = entry.unwrap();
+                assert_eq!(entry.name, "root");
+                assert!(entry.last_change >= -1);
+            },
+            Err(e) => {
+                assert!(unsafe { geteuid() } != 0);
+                assert_eq!(e.raw_os_error(), Some(EACCES));
+            },
+        }
+    }
+
+    #[test]
+    fn missing_entry() {
+        assert!(get_shadow_by_name("no such user, surely").is_none());
+        assert!(get_shadow_by_name("user\0").is_none());
+    }
+}
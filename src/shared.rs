@@ -0,0 +1,351 @@
+//! A users cache that can be shared between threads.
+
+use std::borrow::ToOwned;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use libc::{uid_t, gid_t};
+
+use super::{UsersRead, User, Group};
+use super::{getuid, geteuid, getgid, getegid};
+use super::{lookup_user_by_uid, lookup_user_by_name, lookup_group_by_gid, lookup_group_by_name, group_list};
+use super::LibcSource;
+
+/// A producer of user and group instances that caches its results, and can
+/// be shared between threads.
+///
+/// An `OSUsers` object can't be used from more than one thread at once. A
+/// `SharedUsers` object can be cloned and sent to other threads, with every
+/// clone sharing the same cache. Its lookups come from the `UsersRead`
+/// trait:
+///
+/// ```
+/// use users::{UsersRead, SharedUsers};
+/// use std::thread;
+///
+/// let cache = SharedUsers::empty_cache();
+/// let other = cache.clone();
+/// thread::spawn(move || other.get_user_by_uid(0)).join().unwrap();
+/// println!("{:?}", cache.get_user_by_uid(0).map(|u| u.name));
+/// ```
+///
+/// Lookups that hit the cache only take a read lock. The write lock is taken
+/// to populate a miss, and the libc call is made while holding it. Users and
+/// groups that aren't found are only cached when negative caching is turned
+/// on, as with `OSUsers`.
+#[derive(Clone)]
+pub struct SharedUsers {
+    cache: Arc<RwLock<SharedCache>>,
+}
+
+struct SharedCache {
+    users: HashMap<uid_t, Option<Arc<User>>>,
+    users_back: HashMap<String, Option<uid_t>>,
+
+    groups: HashMap<gid_t, Option<Arc<Group>>>,
+    groups_back: HashMap<String, Option<gid_t>>,
+
+    negative_caching: bool,
+}
+
+impl SharedUsers {
+    /// Create a new empty shared users object.
+    pub fn empty_cache() -> SharedUsers {
+        let cache = SharedCache {
+            users:       HashMap::new(),
+            users_back:  HashMap::new(),
+            groups:      HashMap::new(),
+            groups_back: HashMap::new(),
+            negative_caching: false,
+        };
+
+        SharedUsers { cache: Arc::new(RwLock::new(cache)) }
+    }
+
+    /// Set whether lookups that find nothing are cached, so that looking up
+    /// the same missing user or group again doesn't go back to the
+    /// database. This is off by default, so users added while the object is
+    /// in use are still found. The setting is shared by every clone.
+    pub fn with_negative_caching(self, enabled: bool) -> SharedUsers {
+        self.cache.write().unwrap().negative_caching = enabled;
+        self
+    }
+
+    /// Forget every user and group in the cache, including the ones that
+    /// were looked up and not found, for this object and every clone of it.
+    /// The next lookup of each of them reads it from the database again.
+    pub fn flush(&self) {
+        let mut cache = self.cache.write().unwrap();
+        cache.users.clear();
+        cache.users_back.clear();
+        cache.groups.clear();
+        cache.groups_back.clear();
+    }
+
+    /// Returns the cached result for the given user ID, if there is one,
+    /// without ever looking it up.
+    #[cfg(feature = "async")]
+    pub(crate) fn cached_user_by_uid(&self, uid: uid_t) -> Option<Option<User>> {
+        self.cache.read().unwrap().users.get(&uid)
+            .map(|user| user.as_ref().map(|u| (**u).clone()))
+    }
+
+    /// Returns the cached result for the given username, if there is one,
+    /// without ever looking it up.
+    #[cfg(feature = "async")]
+    pub(crate) fn cached_user_by_name(&self, username: &str) -> Option<Option<User>> {
+        let cache = self.cache.read().unwrap();
+        cache.users_back.get(username)
+            .map(|uid| uid.and_then(|uid| cache.users[&uid].as_ref().map(|u| (**u).clone())))
+    }
+
+    /// Returns the cached result for the given group ID, if there is one,
+    /// without ever looking it up.
+    #[cfg(feature = "async")]
+    pub(crate) fn cached_group_by_gid(&self, gid: gid_t) -> Option<Option<Group>> {
+        self.cache.read().unwrap().groups.get(&gid)
+            .map(|group| group.as_ref().map(|g| (**g).clone()))
+    }
+
+    /// Returns the cached result for the given group name, if there is one,
+    /// without ever looking it up.
+    #[cfg(feature = "async")]
+    pub(crate) fn cached_group_by_name(&self, group_name: &str) -> Option<Option<Group>> {
+        let cache = self.cache.read().unwrap();
+        cache.groups_back.get(group_name)
+            .map(|gid| gid.and_then(|gid| cache.groups[&gid].as_ref().map(|g| (**g).clone())))
+    }
+}
+
+impl UsersRead for SharedUsers {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<User> {
+        if let Some(user) = self.cache.read().unwrap().users.get(&uid) {
+            return user.as_ref().map(|u| (**u).clone());
+        }
+
+        let user = self.cache.write().unwrap().get_user_by_uid(uid).unwrap_or(None);
+        user.map(|u| (*u).clone())
+    }
+
+    fn get_user_by_name(&self, username: &str) -> Option<User> {
+        {
+            let cache = self.cache.read().unwrap();
+            if let Some(uid) = cache.users_back.get(username) {
+                return uid.and_then(|uid| cache.users[&uid].as_ref().map(|u| (**u).clone()));
+            }
+        }
+
+        let user = self.cache.write().unwrap().get_user_by_name(username).unwrap_or(None);
+        user.map(|u| (*u).clone())
+    }
+
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Group> {
+        if let Some(group) = self.cache.read().unwrap().groups.get(&gid) {
+            return group.as_ref().map(|g| (**g).clone());
+        }
+
+        let group = self.cache.write().unwrap().get_group_by_gid(gid).unwrap_or(None);
+        group.map(|g| (*g).clone())
+    }
+
+    fn get_group_by_name(&self, group_name: &str) -> Option<Group> {
+        {
+            let cache = self.cache.read().unwrap();
+            if let Some(gid) = cache.groups_back.get(group_name) {
+                return gid.and_then(|gid| cache.groups[&gid].as_ref().map(|g| (**g).clone()));
+            }
+        }
+
+        let group = self.cache.write().unwrap().get_group_by_name(group_name).unwrap_or(None);
+        group.map(|g| (*g).clone())
+    }
+
+    fn get_user_groups(&self, username: &str, gid: gid_t) -> Option<Vec<Group>> {
+        self.get_user_by_name(username)?;
+
+        let mut groups: Vec<Group> = Vec::new();
+        for gid in group_list(username, gid).unwrap_or_default() {
+            if groups.iter().any(|g| g.gid == gid) {
+                continue;
+            }
+
+            if let Some(group) = self.get_group_by_gid(gid) {
+                groups.push(group);
+            }
+        }
+
+        Some(groups)
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        unsafe { getuid() }
+    }
+
+    fn get_current_username(&self) -> Option<String> {
+        self.get_user_by_uid(self.get_current_uid()).map(|u| u.name)
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        unsafe { getgid() }
+    }
+
+    fn get_current_groupname(&self) -> Option<String> {
+        self.get_group_by_gid(self.get_current_gid()).map(|g| g.name)
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        unsafe { geteuid() }
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        unsafe { getegid() }
+    }
+
+    fn get_effective_username(&self) -> Option<String> {
+        self.get_user_by_uid(self.get_effective_uid()).map(|u| u.name)
+    }
+
+    fn get_effective_groupname(&self) -> Option<String> {
+        self.get_group_by_gid(self.get_effective_gid()).map(|g| g.name)
+    }
+}
+
+impl SharedCache {
+    fn get_user_by_uid(&mut self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        if let Some(user) = self.users.get(&uid) {
+            return Ok(user.clone());
+        }
+
+        let user = lookup_user_by_uid(&LibcSource, uid)?.map(Arc::new);
+        if let Some(ref user) = user {
+            self.users_back.insert(user.name.clone(), Some(user.uid));
+        }
+
+        if user.is_some() || self.negative_caching {
+            self.users.insert(uid, user.clone());
+        }
+        Ok(user)
+    }
+
+    fn get_user_by_name(&mut self, username: &str) -> io::Result<Option<Arc<User>>> {
+        if let Some(uid) = self.users_back.get(username) {
+            return Ok(uid.and_then(|uid| self.users[&uid].clone()));
+        }
+
+        let user = lookup_user_by_name(&LibcSource, username.as_ref())?.map(Arc::new);
+        if let Some(ref user) = user {
+            self.users.insert(user.uid, Some(user.clone()));
+        }
+
+        if user.is_some() || self.negative_caching {
+            self.users_back.insert(username.to_owned(), user.as_ref().map(|u| u.uid));
+        }
+        Ok(user)
+    }
+
+    fn get_group_by_gid(&mut self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        if let Some(group) = self.groups.get(&gid) {
+            return Ok(group.clone());
+        }
+
+        let group = lookup_group_by_gid(&LibcSource, gid)?.map(Arc::new);
+        if let Some(ref group) = group {
+            self.groups_back.insert(group.name.clone(), Some(group.gid));
+        }
+
+        if group.is_some() || self.negative_caching {
+            self.groups.insert(gid, group.clone());
+        }
+        Ok(group)
+    }
+
+    fn get_group_by_name(&mut self, group_name: &str) -> io::Result<Option<Arc<Group>>> {
+        if let Some(gid) = self.groups_back.get(group_name) {
+            return Ok(gid.and_then(|gid| self.groups[&gid].clone()));
+        }
+
+        let group = lookup_group_by_name(&LibcSource, group_name.as_ref())?.map(Arc::new);
+        if let Some(ref group) = group {
+            self.groups.insert(group.gid, Some(group.clone()));
+        }
+
+        if group.is_some() || self.negative_caching {
+            self.groups_back.insert(group_name.to_owned(), group.as_ref().map(|g| g.gid));
+        }
+        Ok(group)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UsersRead, SharedUsers};
+    use std::thread;
+
+    #[test]
+    fn threads_agree() {
+        let users = SharedUsers::empty_cache();
+
+        let handles: Vec<_> = (0..8).map(|_| {
+            let users = users.clone();
+            thread::spawn(move || users.get_user_by_uid(0).map(|u| u.name))
+        }).collect();
+
+        let names: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(names[0].is_some());
+        assert!(names.iter().all(|n| *n == names[0]));
+    }
+
+    #[test]
+    fn user_by_name() {
+        let users = SharedUsers::empty_cache();
+        let root = users.get_user_by_uid(0).unwrap();
+        assert_eq!(users.get_user_by_name(&root.name).map(|u| u.uid), Some(0));
+        assert!(users.get_user_by_name("user\0").is_none());
+    }
+
+    #[test]
+    fn group_by_name() {
+        let users = SharedUsers::empty_cache();
+        let root = users.get_user_by_uid(0).unwrap();
+        let group = users.get_group_by_gid(root.primary_group).unwrap();
+        assert_eq!(users.get_group_by_name(&group.name).map(|g| g.gid), Some(group.gid));
+    }
+
+    #[test]
+    fn misses_not_cached_by_default() {
+        let users = SharedUsers::empty_cache();
+        assert!(users.get_user_by_name("no such user, surely").is_none());
+        assert!(users.get_group_by_name("no such group, surely").is_none());
+
+        let cache = users.cache.read().unwrap();
+        assert!(cache.users_back.is_empty());
+        assert!(cache.groups_back.is_empty());
+    }
+
+    #[test]
+    fn negative_caching() {
+        let users = SharedUsers::empty_cache().with_negative_caching(true);
+        assert!(users.get_user_by_name("no such user, surely").is_none());
+        assert!(users.get_group_by_name("no such group, surely").is_none());
+
+        let cache = users.cache.read().unwrap();
+        assert_eq!(cache.users_back.get("no such user, surely"), Some(&None));
+        assert_eq!(cache.groups_back.get("no such group, surely"), Some(&None));
+    }
+
+    #[test]
+    fn flush() {
+        let users = SharedUsers::empty_cache();
+        let other = users.clone();
+        let name = users.get_user_by_uid(0).unwrap().name;
+        assert!(users.cache.read().unwrap().users.contains_key(&0));
+
+        other.flush();
+        assert!(users.cache.read().unwrap().users.is_empty());
+        assert!(users.cache.read().unwrap().users_back.is_empty());
+
+        assert_eq!(users.get_user_by_name(&name).map(|u| u.uid), Some(0));
+        assert!(users.cache.read().unwrap().users.contains_key(&0));
+    }
+}
@@ -0,0 +1,587 @@
+//! Where `OSUsers` reads its users and groups from.
+//!
+//! Normally this is libc, by way of the reentrant `getpw*_r` and `getgr*_r`
+//! functions. Swapping in another source lets tests drive the caching and
+//! buffer-growing code without depending on which users the system has.
+//!
+//! Everything else an `OSUsers` knows comes from its source as well: the
+//! lists of every user and group, the groups each user belongs to, and the
+//! IDs of the running process.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use libc::{c_char, size_t, uid_t, gid_t};
+
+use super::{User, Group, c_passwd, c_group, passwd_result, group_result, passwd_to_user, struct_to_group};
+use super::{getpwuid_r, getpwnam_r, getgrgid_r, getgrnam_r, setpwent, getpwent, endpwent, setgrent, getgrent, endgrent};
+use super::{getuid, geteuid, getgid, getegid, group_list, group_count, process_groups, login_name};
+#[cfg(target_os = "linux")]
+use super::read_loginuid;
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+/// A database of users and groups that can be looked up one at a time, in
+/// the way the `get*_r` functions do.
+///
+/// Each method is given a buffer to hold the entry's strings. If the buffer
+/// is too small, the method should fail with `ERANGE`, and it'll be called
+/// again with a bigger one. Not finding the entry isn't an error.
+pub trait PasswdSource {
+
+    /// Look up the user with the given ID.
+    fn user_by_uid(&self, uid: uid_t, buffer: &mut [c_char]) -> io::Result<Option<User>>;
+
+    /// Look up the user with the given name.
+    fn user_by_name(&self, username: &CStr, buffer: &mut [c_char]) -> io::Result<Option<User>>;
+
+    /// Look up the group with the given ID.
+    fn group_by_gid(&self, gid: gid_t, buffer: &mut [c_char]) -> io::Result<Option<Group>>;
+
+    /// Look up the group with the given name.
+    fn group_by_name(&self, group_name: &CStr, buffer: &mut [c_char]) -> io::Result<Option<Group>>;
+
+    /// Return every user in the database, in the order it lists them.
+    fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_>;
+
+    /// Return every group in the database, with their member lists, in the
+    /// order it lists them.
+    fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_>;
+
+    /// Return the IDs of every group the given user belongs to, including
+    /// the given group ID, or None if the user can't be asked about.
+    ///
+    /// By default, this is the given group followed by every group that
+    /// lists the user as a member, in the order `all_groups` returns them.
+    fn group_list(&self, username: &str, gid: gid_t) -> Option<Vec<gid_t>> {
+        Some(member_group_list(self.all_groups(), username, gid))
+    }
+
+    /// Return how many groups the given user belongs to, including the
+    /// given group ID. By default, this is the length of `group_list`.
+    fn group_count(&self, username: &str, gid: gid_t) -> usize {
+        self.group_list(username, gid).map_or(0, |gids| gids.len())
+    }
+
+    /// Return the real user ID of the running process. By default, this
+    /// is the value of `getuid`, as it is for the rest of these.
+    fn current_uid(&self) -> uid_t {
+        unsafe { getuid() }
+    }
+
+    /// Return the real group ID of the running process.
+    fn current_gid(&self) -> gid_t {
+        unsafe { getgid() }
+    }
+
+    /// Return the effective user ID of the running process.
+    fn effective_uid(&self) -> uid_t {
+        unsafe { geteuid() }
+    }
+
+    /// Return the effective group ID of the running process.
+    fn effective_gid(&self) -> gid_t {
+        unsafe { getegid() }
+    }
+
+    /// Return the IDs of the running process's supplementary groups.
+    fn process_groups(&self) -> Vec<gid_t> {
+        process_groups()
+    }
+
+    /// Return the name of the user logged in on the process's controlling
+    /// terminal, if there is one.
+    fn login_name(&self) -> Option<String> {
+        login_name()
+    }
+
+    /// Return the audit login UID of the running process, if one was set.
+    /// This is only known on Linux; elsewhere, the login name is used
+    /// instead.
+    fn audit_login_uid(&self) -> Option<uid_t> {
+        #[cfg(target_os = "linux")]
+        {
+            read_loginuid(Path::new("/proc/self/loginuid"))
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+}
+
+/// Return the given group ID followed by the ID of every one of the given
+/// groups that lists the user as a member, without repeats.
+pub fn member_group_list<I>(groups: I, username: &str, gid: gid_t) -> Vec<gid_t>
+where I: Iterator<Item = Group> {
+    let mut gids = vec![ gid ];
+    for group in groups {
+        if group.members.iter().any(|m| m == username) && !gids.contains(&group.gid) {
+            gids.push(group.gid);
+        }
+    }
+    gids
+}
+
+/// The system's users and groups, as libc sees them. This is what an
+/// `OSUsers` object uses unless it's given something else.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LibcSource;
+
+impl PasswdSource for LibcSource {
+    fn user_by_uid(&self, uid: uid_t, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        let mut passwd: c_passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        unsafe {
+            let ret = getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len() as size_t, &mut result);
+            passwd_result(ret, result)
+        }
+    }
+
+    fn user_by_name(&self, username: &CStr, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        let mut passwd: c_passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        unsafe {
+            let ret = getpwnam_r(username.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len() as size_t, &mut result);
+            passwd_result(ret, result)
+        }
+    }
+
+    fn group_by_gid(&self, gid: gid_t, buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        let mut group: c_group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        unsafe {
+            let ret = getgrgid_r(gid, &mut group, buffer.as_mut_ptr(), buffer.len() as size_t, &mut result);
+            group_result(ret, result)
+        }
+    }
+
+    fn group_by_name(&self, group_name: &CStr, buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        let mut group: c_group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        unsafe {
+            let ret = getgrnam_r(group_name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len() as size_t, &mut result);
+            group_result(ret, result)
+        }
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+        Box::new(PasswdEntries::new())
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+        Box::new(GroupEntries::new())
+    }
+
+    fn group_list(&self, username: &str, gid: gid_t) -> Option<Vec<gid_t>> {
+        group_list(username, gid)
+    }
+
+    fn group_count(&self, username: &str, gid: gid_t) -> usize {
+        group_count(username, gid)
+    }
+}
+
+/// The entries in the users database, read one at a time with `getpwent`.
+///
+/// `getpwent` keeps its position in global state, so only one of these
+/// should be alive at once.
+struct PasswdEntries;
+
+impl PasswdEntries {
+    fn new() -> PasswdEntries {
+        unsafe { setpwent() };
+        PasswdEntries
+    }
+}
+
+impl Iterator for PasswdEntries {
+    type Item = User;
+
+    fn next(&mut self) -> Option<User> {
+        unsafe { passwd_to_user(getpwent()) }
+    }
+}
+
+impl Drop for PasswdEntries {
+    fn drop(&mut self) {
+        unsafe { endpwent() };
+    }
+}
+
+/// The entries in the groups database, read one at a time with `getgrent`,
+/// which has the same problem with global state as `getpwent`.
+struct GroupEntries;
+
+impl GroupEntries {
+    fn new() -> GroupEntries {
+        unsafe { setgrent() };
+        GroupEntries
+    }
+}
+
+impl Iterator for GroupEntries {
+    type Item = Group;
+
+    fn next(&mut self) -> Option<Group> {
+        unsafe { struct_to_group(getgrent()) }
+    }
+}
+
+impl Drop for GroupEntries {
+    fn drop(&mut self) {
+        unsafe { endgrent() };
+    }
+}
+
+/// A source that stops waiting for another source once a lookup has taken
+/// longer than a time limit, failing with an error of kind `TimedOut`
+/// instead. This is for systems whose users come from a network service
+/// such as LDAP, where a lookup can hang for as long as the server is
+/// unreachable.
+///
+/// Each lookup runs on a thread of its own, with a clone of the source.
+/// There's no way to cancel a libc call that's blocked, so a lookup that
+/// times out carries on in the background until it finishes by itself,
+/// and its result is thrown away.
+///
+/// Only lookups of a single user or group have a time limit. Listing every
+/// user or group, or the groups a user belongs to, goes straight to the
+/// wrapped source.
+#[derive(Clone, Debug)]
+pub struct TimeoutSource<S> {
+    source: S,
+    timeout: Duration,
+}
+
+impl<S> TimeoutSource<S> {
+    /// Wrap the given source so its lookups time out after the given time.
+    pub fn new(source: S, timeout: Duration) -> TimeoutSource<S> {
+        TimeoutSource { source, timeout }
+    }
+}
+
+impl<S: PasswdSource + Clone + Send + 'static> TimeoutSource<S> {
+    /// Run a lookup on a new thread with a buffer of the given size, and
+    /// wait for it for no longer than the time limit.
+    fn run<T, F>(&self, buffer_size: usize, lookup: F) -> io::Result<Option<T>>
+    where T: Send + 'static,
+          F: FnOnce(&S, &mut [c_char]) -> io::Result<Option<T>> + Send + 'static {
+        let (sender, receiver) = mpsc::channel();
+        let source = self.source.clone();
+        thread::spawn(move || {
+            let mut buffer = vec![0; buffer_size];
+            let _ = sender.send(lookup(&source, &mut buffer));
+        });
+
+        match receiver.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(io::ErrorKind::TimedOut, "lookup timed out")),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::other("lookup thread panicked")),
+        }
+    }
+}
+
+impl<S: PasswdSource + Clone + Send + 'static> PasswdSource for TimeoutSource<S> {
+    fn user_by_uid(&self, uid: uid_t, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        self.run(buffer.len(), move |source, buffer| source.user_by_uid(uid, buffer))
+    }
+
+    fn user_by_name(&self, username: &CStr, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+        let username = CString::from(username);
+        self.run(buffer.len(), move |source, buffer| source.user_by_name(&username, buffer))
+    }
+
+    fn group_by_gid(&self, gid: gid_t, buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        self.run(buffer.len(), move |source, buffer| source.group_by_gid(gid, buffer))
+    }
+
+    fn group_by_name(&self, group_name: &CStr, buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+        let group_name = CString::from(group_name);
+        self.run(buffer.len(), move |source, buffer| source.group_by_name(&group_name, buffer))
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+        self.source.all_users()
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+        self.source.all_groups()
+    }
+
+    fn group_list(&self, username: &str, gid: gid_t) -> Option<Vec<gid_t>> {
+        self.source.group_list(username, gid)
+    }
+
+    fn group_count(&self, username: &str, gid: gid_t) -> usize {
+        self.source.group_count(username, gid)
+    }
+
+    fn current_uid(&self) -> uid_t {
+        self.source.current_uid()
+    }
+
+    fn current_gid(&self) -> gid_t {
+        self.source.current_gid()
+    }
+
+    fn effective_uid(&self) -> uid_t {
+        self.source.effective_uid()
+    }
+
+    fn effective_gid(&self) -> gid_t {
+        self.source.effective_gid()
+    }
+
+    fn process_groups(&self) -> Vec<gid_t> {
+        self.source.process_groups()
+    }
+
+    fn login_name(&self) -> Option<String> {
+        self.source.login_name()
+    }
+
+    fn audit_login_uid(&self) -> Option<uid_t> {
+        self.source.audit_login_uid()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PasswdSource, TimeoutSource};
+    use super::super::{Users, OSUsers, User, Group, Error};
+    use libc::{c_char, uid_t, gid_t, EIO, ERANGE};
+    use std::cell::RefCell;
+    use std::ffi::CStr;
+    use std::io;
+    use std::iter;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// A source with one user, whose entry needs `needed` bytes of buffer,
+    /// that records the size of every buffer it's offered. Looking up any
+    /// group by ID fails.
+    struct FakePasswdSource {
+        needed: usize,
+        offered: RefCell<Vec<usize>>,
+    }
+
+    impl FakePasswdSource {
+        fn new(needed: usize) -> FakePasswdSource {
+            FakePasswdSource { needed, offered: RefCell::new(Vec::new()) }
+        }
+
+        fn fit<T>(&self, buffer: &[c_char], entry: Option<T>) -> io::Result<Option<T>> {
+            self.offered.borrow_mut().push(buffer.len());
+            if buffer.len() < self.needed { Err(io::Error::from_raw_os_error(ERANGE)) }
+            else { Ok(entry) }
+        }
+    }
+
+    impl PasswdSource for FakePasswdSource {
+        fn user_by_uid(&self, uid: uid_t, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            let user = if uid == 1000 { Some(User::builder(1000, "fred").build()) } else { None };
+            self.fit(buffer, user)
+        }
+
+        fn user_by_name(&self, username: &CStr, buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            let user = if username.to_bytes() == b"fred" { Some(User::builder(1000, "fred").build()) } else { None };
+            self.fit(buffer, user)
+        }
+
+        fn group_by_gid(&self, _gid: gid_t, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            Err(io::Error::from_raw_os_error(EIO))
+        }
+
+        fn group_by_name(&self, _group_name: &CStr, buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            self.fit(buffer, None)
+        }
+
+        fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+            Box::new(iter::once(User::builder(1000, "fred").build()))
+        }
+
+        fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+            Box::new(iter::empty())
+        }
+    }
+
+    /// A source with one group, whose members can be changed behind the
+    /// cache's back.
+    struct ChangingSource {
+        members: RefCell<Vec<String>>,
+    }
+
+    impl PasswdSource for ChangingSource {
+        fn user_by_uid(&self, _uid: uid_t, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            Ok(None)
+        }
+
+        fn user_by_name(&self, _username: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            Ok(None)
+        }
+
+        fn group_by_gid(&self, gid: gid_t, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            if gid != 100 { return Ok(None) }
+            let group = self.members.borrow().iter().fold(Group::builder(100, "staff"), |g, m| g.member(m));
+            Ok(Some(group.build()))
+        }
+
+        fn group_by_name(&self, _group_name: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            Ok(None)
+        }
+
+        fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+            Box::new(iter::empty())
+        }
+
+        fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+            let group = self.members.borrow().iter().fold(Group::builder(100, "staff"), |g, m| g.member(m));
+            Box::new(iter::once(group.build()))
+        }
+    }
+
+    /// A source with one user and no groups, which takes its time looking
+    /// either of them up.
+    #[derive(Clone)]
+    struct SlowSource {
+        delay: Duration,
+    }
+
+    impl PasswdSource for SlowSource {
+        fn user_by_uid(&self, uid: uid_t, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            thread::sleep(self.delay);
+            Ok(if uid == 1000 { Some(User::builder(1000, "fred").build()) } else { None })
+        }
+
+        fn user_by_name(&self, username: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            thread::sleep(self.delay);
+            Ok(if username.to_bytes() == b"fred" { Some(User::builder(1000, "fred").build()) } else { None })
+        }
+
+        fn group_by_gid(&self, _gid: gid_t, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            thread::sleep(self.delay);
+            Ok(None)
+        }
+
+        fn group_by_name(&self, _group_name: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            thread::sleep(self.delay);
+            Ok(None)
+        }
+
+        fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+            Box::new(iter::once(User::builder(1000, "fred").build()))
+        }
+
+        fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+            Box::new(iter::empty())
+        }
+    }
+
+    #[test]
+    fn slow_lookups_time_out() {
+        let slow = SlowSource { delay: Duration::from_secs(2) };
+        let mut users = OSUsers::with_source(TimeoutSource::new(slow, Duration::from_millis(50)));
+
+        let started = Instant::now();
+        assert!(matches!(users.try_get_user_by_uid(1000), Err(Error::TimedOut)));
+        assert!(matches!(users.try_get_user_by_name("fred"), Err(Error::TimedOut)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn quick_lookups_finish() {
+        let quick = SlowSource { delay: Duration::from_millis(0) };
+        let mut users = OSUsers::with_source(TimeoutSource::new(quick, Duration::from_secs(5)));
+        assert_eq!(users.get_user_by_name("fred").map(|u| u.uid), Some(1000));
+        assert!(users.try_get_group_by_gid(100).unwrap().is_none());
+    }
+
+    #[test]
+    fn fresh_lookup_bypasses_the_cache() {
+        let mut users = OSUsers::with_source(ChangingSource { members: RefCell::new(vec!["fred".to_string()]) });
+        assert_eq!(users.get_group_by_gid(100).unwrap().members, vec!["fred"]);
+
+        users.source.members.borrow_mut().push("bob".to_string());
+        assert_eq!(users.get_group_by_gid(100).unwrap().members, vec!["fred"]);
+        assert_eq!(users.get_group_by_gid_fresh(100).unwrap().members, vec!["fred", "bob"]);
+        assert_eq!(users.get_group_by_gid(100).unwrap().members, vec!["fred", "bob"]);
+    }
+
+    #[test]
+    fn retries_with_bigger_buffers() {
+        let mut users = OSUsers::with_source(FakePasswdSource::new(100_000));
+        assert_eq!(users.get_user_by_uid(1000).map(|u| u.name), Some("fred".to_string()));
+
+        let offered = users.source.offered.borrow().clone();
+        assert!(offered.len() > 1);
+        assert!(offered.windows(2).all(|w| w[1] == w[0] * 2));
+        assert!(*offered.last().unwrap() >= 100_000);
+    }
+
+    #[test]
+    fn cached_entries_skip_the_source() {
+        let mut users = OSUsers::with_source(FakePasswdSource::new(0)).with_negative_caching(true);
+        assert_eq!(users.get_user_by_uid(1000).map(|u| u.name), Some("fred".to_string()));
+        assert_eq!(users.get_user_by_name("fred").map(|u| u.uid), Some(1000));
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert_eq!(users.source.offered.borrow().len(), 2);
+    }
+
+    #[test]
+    fn negative_caching() {
+        let mut users = OSUsers::with_source(FakePasswdSource::new(0)).with_negative_caching(true);
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert!(users.get_user_by_name("bob").is_none());
+        assert!(users.get_user_by_name("bob").is_none());
+        assert_eq!(users.source.offered.borrow().len(), 2);
+
+        users.forget_user(1001);
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert_eq!(users.source.offered.borrow().len(), 3);
+    }
+
+    #[test]
+    fn misses_go_back_to_the_source_by_default() {
+        let mut users = OSUsers::with_source(FakePasswdSource::new(0));
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert!(users.get_user_by_uid(1001).is_none());
+        assert_eq!(users.source.offered.borrow().len(), 2);
+    }
+
+    #[test]
+    fn missing_entries_are_not_errors() {
+        let mut users = OSUsers::with_source(FakePasswdSource::new(0));
+        assert!(users.try_get_user_by_uid(1001).unwrap().is_none());
+        assert!(users.try_get_user_by_name("bob").unwrap().is_none());
+    }
+
+    #[test]
+    fn entries_too_big_for_any_buffer() {
+        let mut users = OSUsers::with_source(FakePasswdSource::new(usize::MAX));
+        assert!(matches!(users.try_get_user_by_uid(1000), Err(Error::BufferTooSmall)));
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let mut users = OSUsers::with_source(FakePasswdSource::new(0));
+        match users.try_get_group_by_gid(10) {
+            Err(Error::Io(e)) => assert_eq!(e.raw_os_error(), Some(EIO)),
+            _ => panic!("expected an I/O error"),
+        }
+        assert!(users.get_group_by_gid(10).is_none());
+        assert!(users.groups.borrow().is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+//! Subordinate user and group IDs, from `/etc/subuid` and `/etc/subgid`.
+//!
+//! These files give unprivileged users ranges of IDs that `newuidmap` and
+//! `newgidmap` let them map into a user namespace, as container tools do.
+//! Each line is `owner:start:count`, where the owner is either a username or
+//! a user ID, and a user can have any number of lines.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use libc::uid_t;
+
+/// The file that subordinate user ID ranges are normally kept in.
+pub const SUBUID_PATH: &str = "/etc/subuid";
+
+/// The file that subordinate group ID ranges are normally kept in.
+pub const SUBGID_PATH: &str = "/etc/subgid";
+
+/// A range of subordinate IDs: `count` IDs, starting at `start`. The same
+/// type is used for both user and group IDs.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct IdRange {
+
+    /// The first ID in the range
+    pub start: uid_t,
+
+    /// How many IDs are in the range
+    pub count: uid_t,
+}
+
+impl IdRange {
+    /// The ID just past the end of the range. A range that runs past the
+    /// largest ID, which a broken file can contain, ends at the largest ID
+    /// rather than wrapping around.
+    pub fn end(&self) -> uid_t {
+        self.start.saturating_add(self.count)
+    }
+
+    /// The last ID in the range, or None if the range is empty. This is
+    /// the largest ID for a range that runs past it.
+    pub fn last(&self) -> Option<uid_t> {
+        if self.count == 0 { None }
+        else { Some(self.start.saturating_add(self.count - 1)) }
+    }
+
+    /// Whether the given ID is in the range.
+    pub fn contains(&self, id: uid_t) -> bool {
+        id >= self.start && id - self.start < self.count
+    }
+}
+
+/// Return the ranges of subordinate IDs given to the user with the given
+/// name and ID in the `subuid`- or `subgid`-format file at the given path,
+/// in the order they appear, or the error if the file couldn't be read.
+/// Lines that can't be parsed are skipped.
+pub fn read_subid_ranges<P: AsRef<Path>>(path: P, username: &str, uid: uid_t) -> io::Result<Vec<IdRange>> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(parse_subid_ranges(&contents, username, uid))
+}
+
+fn parse_subid_ranges(contents: &str, username: &str, uid: uid_t) -> Vec<IdRange> {
+    contents.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.trim().split(':').collect();
+                if fields.len() != 3 {
+                    return None;
+                }
+
+                let owner = fields[0];
+                if owner != username && owner.parse() != Ok(uid) {
+                    return None;
+                }
+
+                Some(IdRange { start: fields[1].parse().ok()?, count: fields[2].parse().ok()? })
+            })
+            .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_subid_ranges, IdRange};
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/subuid");
+
+    #[test]
+    fn ranges_by_name_and_uid() {
+        let ranges = read_subid_ranges(FIXTURE, "fred", 1000).unwrap();
+        assert_eq!(ranges, vec![
+            IdRange { start: 100000, count: 65536 },
+            IdRange { start: 300000, count: 1000 },
+            IdRange { start: 400000, count: 65536 },
+        ]);
+    }
+
+    #[test]
+    fn no_allocation() {
+        assert!(read_subid_ranges(FIXTURE, "jim", 1003).unwrap().is_empty());
+        assert!(read_subid_ranges(FIXTURE, "sue", 1002).unwrap().is_empty());
+    }
+
+    #[test]
+    fn range_bounds() {
+        let range = IdRange { start: 100000, count: 65536 };
+        assert_eq!(range.end(), 165536);
+        assert_eq!(range.last(), Some(165535));
+        assert!(range.contains(100000));
+        assert!(range.contains(165535));
+        assert!(!range.contains(165536));
+        assert!(!range.contains(99999));
+
+        let empty = IdRange { start: 100000, count: 0 };
+        assert_eq!(empty.last(), None);
+        assert!(!empty.contains(100000));
+    }
+
+    #[test]
+    fn range_past_the_largest_id() {
+        let range = IdRange { start: u32::MAX - 10, count: 100 };
+        assert_eq!(range.end(), u32::MAX);
+        assert_eq!(range.last(), Some(u32::MAX));
+        assert!(range.contains(u32::MAX - 10));
+        assert!(range.contains(u32::MAX));
+        assert!(!range.contains(u32::MAX - 11));
+        assert!(!range.contains(5));
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(read_subid_ranges(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/nonexistent"), "fred", 1000).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! Noticing when the passwd and group files change, using inotify.
+//!
+//! Tools such as `usermod` and `vipw` don't edit `/etc/passwd` in place:
+//! they write a new copy and rename it over the old one. So rather than
+//! watching the files themselves, which would stop working after the first
+//! rename, this watches the directories they're in, and picks out the
+//! events that name them.
+
+use std::cell::Cell;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use libc::{c_char, c_int, c_short, c_ulong, c_void, size_t, ssize_t, uint32_t};
+
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: uint32_t) -> c_int;
+    fn poll(fds: *mut pollfd, nfds: c_ulong, timeout: c_int) -> c_int;
+    fn read(fd: c_int, buf: *mut c_void, count: size_t) -> ssize_t;
+    fn close(fd: c_int) -> c_int;
+}
+
+#[repr(C)]
+struct pollfd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+const IN_CLOEXEC: c_int = 0o2000000;
+const IN_MODIFY: uint32_t = 0x2;
+const IN_CLOSE_WRITE: uint32_t = 0x8;
+const IN_MOVED_TO: uint32_t = 0x80;
+const IN_CREATE: uint32_t = 0x100;
+const IN_DELETE: uint32_t = 0x200;
+const IN_Q_OVERFLOW: uint32_t = 0x4000;
+const POLLIN: c_short = 0x1;
+
+/// How long the watcher thread waits for an event before checking whether
+/// anyone is still listening to it.
+const POLL_TIMEOUT_MS: c_int = 500;
+
+/// The size of `struct inotify_event`, not counting the name after it.
+const EVENT_HEADER_SIZE: usize = 16;
+
+/// How many times each file has been seen to change, counted by the watcher
+/// thread.
+struct Changes {
+    passwd: AtomicUsize,
+    group: AtomicUsize,
+}
+
+/// A watch on a passwd file and a group file, which a cache can ask whether
+/// either of them has changed since it last asked.
+///
+/// The watching is done by a background thread, which stops shortly after
+/// the last clone of this is dropped.
+#[derive(Clone)]
+pub struct Watch {
+    changes: Arc<Changes>,
+    seen_passwd: Cell<usize>,
+    seen_group: Cell<usize>,
+}
+
+impl Watch {
+    /// Start watching the given files.
+    pub fn start(passwd: &Path, group: &Path) -> io::Result<Watch> {
+        let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let targets = match (add_watch(fd, passwd), add_watch(fd, group)) {
+            (Ok(p), Ok(g)) => [ p, g ],
+            (Err(e), _) | (_, Err(e)) => {
+                unsafe { close(fd) };
+                return Err(e);
+            },
+        };
+
+        let changes = Arc::new(Changes { passwd: AtomicUsize::new(0), group: AtomicUsize::new(0) });
+        let thread_changes = changes.clone();
+        thread::spawn(move || watch_loop(fd, &targets, &thread_changes));
+
+        Ok(Watch { changes, seen_passwd: Cell::new(0), seen_group: Cell::new(0) })
+    }
+
+    /// Whether the passwd file has changed since this was last called.
+    pub fn passwd_changed(&self) -> bool {
+        changed_since(&self.changes.passwd, &self.seen_passwd)
+    }
+
+    /// Whether the group file has changed since this was last called.
+    pub fn group_changed(&self) -> bool {
+        changed_since(&self.changes.group, &self.seen_group)
+    }
+}
+
+fn changed_since(count: &AtomicUsize, seen: &Cell<usize>) -> bool {
+    let now = count.load(Ordering::SeqCst);
+    seen.replace(now) != now
+}
+
+/// A watch descriptor for a file's directory, and the file's name in it.
+type Target = (c_int, Vec<u8>);
+
+fn add_watch(fd: c_int, path: &Path) -> io::Result<Target> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let name = path.file_name().map(OsStr::as_bytes)
+                   .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+
+    let dir_c = CString::new(dir.as_os_str().as_bytes())
+                       .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+
+    let mask = IN_MODIFY | IN_CLOSE_WRITE | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+    let wd = unsafe { inotify_add_watch(fd, dir_c.as_ptr(), mask) };
+    if wd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((wd, name.to_vec()))
+}
+
+fn watch_loop(fd: c_int, targets: &[Target; 2], changes: &Arc<Changes>) {
+    let mut buffer = vec![0_u8; 4096];
+
+    // The only other reference is the one held by the watches, so once
+    // they've all gone, there's nobody left to tell.
+    while Arc::strong_count(changes) > 1 {
+        let mut fds = pollfd { fd, events: POLLIN, revents: 0 };
+        let ready = unsafe { poll(&mut fds, 1, POLL_TIMEOUT_MS) };
+        if ready == -1 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            break;
+        }
+        else if ready <= 0 {
+            continue;
+        }
+
+        let len = unsafe { read(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len() as size_t) };
+        if len <= 0 {
+            break;
+        }
+
+        for (wd, mask, name) in events(&buffer[.. len as usize]) {
+            let overflowed = mask & IN_Q_OVERFLOW != 0;
+            if overflowed || targets[0] == (wd, name.to_vec()) {
+                changes.passwd.fetch_add(1, Ordering::SeqCst);
+            }
+            if overflowed || targets[1] == (wd, name.to_vec()) {
+                changes.group.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    unsafe { close(fd) };
+}
+
+/// Split a buffer read from an inotify descriptor into the watch
+/// descriptor, mask, and file name of each event in it.
+fn events(mut buffer: &[u8]) -> Vec<(c_int, uint32_t, &[u8])> {
+    let mut events = Vec::new();
+
+    while buffer.len() >= EVENT_HEADER_SIZE {
+        let wd   = c_int::from_ne_bytes([ buffer[0], buffer[1], buffer[2], buffer[3] ]);
+        let mask = uint32_t::from_ne_bytes([ buffer[4], buffer[5], buffer[6], buffer[7] ]);
+        let len  = uint32_t::from_ne_bytes([ buffer[12], buffer[13], buffer[14], buffer[15] ]) as usize;
+
+        let end = ::std::cmp::min(EVENT_HEADER_SIZE + len, buffer.len());
+        let name = &buffer[EVENT_HEADER_SIZE .. end];
+        let name = &name[.. name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        events.push((wd, mask, name));
+
+        buffer = &buffer[end ..];
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Users, OSUsers, User, Group, PasswdSource};
+    use libc::{c_char, uid_t, gid_t};
+    use std::env;
+    use std::ffi::CStr;
+    use std::fs;
+    use std::io;
+    use std::iter;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// A source whose only user, with ID 1000, is named by the contents of
+    /// a file.
+    struct FileNameSource {
+        path: PathBuf,
+    }
+
+    impl PasswdSource for FileNameSource {
+        fn user_by_uid(&self, uid: uid_t, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            if uid != 1000 { return Ok(None) }
+            let name = fs::read_to_string(&self.path)?;
+            Ok(Some(User::builder(1000, name.trim()).build()))
+        }
+
+        fn user_by_name(&self, _username: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<User>> {
+            Ok(None)
+        }
+
+        fn group_by_gid(&self, _gid: gid_t, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            Ok(None)
+        }
+
+        fn group_by_name(&self, _group_name: &CStr, _buffer: &mut [c_char]) -> io::Result<Option<Group>> {
+            Ok(None)
+        }
+
+        fn all_users(&self) -> Box<dyn Iterator<Item = User> + '_> {
+            Box::new(iter::empty())
+        }
+
+        fn all_groups(&self) -> Box<dyn Iterator<Item = Group> + '_> {
+            Box::new(iter::empty())
+        }
+    }
+
+    #[test]
+    fn changes_flush_the_cache() {
+        let dir = env::temp_dir().join(format!("users-watch-test-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let passwd = dir.join("passwd");
+        let group = dir.join("group");
+        fs::write(&passwd, "fred\n").unwrap();
+        fs::write(&group, "").unwrap();
+
+        let mut users = OSUsers::with_source(FileNameSource { path: passwd.clone() });
+        users.watch_files(&passwd, &group).unwrap();
+        assert_eq!(users.get_user_by_uid(1000).map(|u| u.name), Some("fred".to_string()));
+
+        // Replace the file the way usermod does, by renaming a new copy
+        // over it.
+        let new_copy = dir.join("passwd+");
+        fs::write(&new_copy, "bob\n").unwrap();
+        fs::rename(&new_copy, &passwd).unwrap();
+
+        let started = Instant::now();
+        let mut name = None;
+        while started.elapsed() < Duration::from_secs(5) {
+            name = users.get_user_by_uid(1000).map(|u| u.name);
+            if name.as_ref().map(|n| &n[..]) == Some("bob") { break }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(name, Some("bob".to_string()));
+    }
+
+    #[test]
+    fn events() {
+        let mut buffer = Vec::new();
+        for &(wd, mask, name) in &[ (1_i32, 0x80_u32, &b"passwd\0\0"[..]), (2, 0x2, &b""[..]) ] {
+            buffer.extend_from_slice(&wd.to_ne_bytes());
+            buffer.extend_from_slice(&mask.to_ne_bytes());
+            buffer.extend_from_slice(&0_u32.to_ne_bytes());
+            buffer.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+            buffer.extend_from_slice(name);
+        }
+
+        assert_eq!(super::events(&buffer), vec![ (1, 0x80, &b"passwd"[..]), (2, 0x2, &b""[..]) ]);
+    }
+}